@@ -0,0 +1,225 @@
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Stay,
+    /// `drops` lists onboard passengers to let out; `None` lets out everyone bound for this floor.
+    Open {
+        picks: Vec<usize>,
+        drops: Option<Vec<usize>>,
+    },
+}
+
+impl Action {
+    /// Plain `OPEN`, boarding `picks` and letting out everyone bound for this floor.
+    pub fn open(picks: &[usize]) -> Self {
+        Action::Open {
+            picks: picks.to_vec(),
+            drops: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Up => "UP",
+            Action::Down => "DOWN",
+            Action::Stay => "STAY",
+            Action::Open { .. } => "OPEN",
+        }
+    }
+
+    pub fn picks(&self) -> &[usize] {
+        match self {
+            Action::Open { picks, .. } => picks,
+            _ => &[],
+        }
+    }
+
+    pub fn drops(&self) -> Option<&[usize]> {
+        match self {
+            Action::Open { drops, .. } => drops.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Parses one output line. Strict mode accepts only the exact upper-case keywords;
+    /// lenient mode also accepts any casing and the abbreviations `U`, `D`, `S` and `O`.
+    pub fn parse_line(line: &str, lenient: bool) -> Result<Self> {
+        Self::parse_line_capped(line, lenient, None)
+    }
+
+    /// [`Action::parse_line`], but an `OPEN` listing more than `max_indices` boarding or
+    /// alighting indices is rejected as soon as the next index is reached, so an overlong
+    /// line is never read in full.
+    pub fn parse_line_capped(
+        line: &str,
+        lenient: bool,
+        max_indices: Option<usize>,
+    ) -> Result<Self> {
+        let mut parts = line.split_whitespace().peekable();
+        let Some(keyword) = parts.next() else {
+            bail!("Empty action line");
+        };
+        let keyword = if lenient {
+            match keyword.to_ascii_uppercase().as_str() {
+                "U" | "UP" => "UP",
+                "D" | "DOWN" => "DOWN",
+                "S" | "STAY" => "STAY",
+                "O" | "OPEN" => "OPEN",
+                _ => keyword,
+            }
+        } else {
+            keyword
+        };
+
+        match keyword {
+            "UP" => Ok(Action::Up),
+            "DOWN" => Ok(Action::Down),
+            "STAY" => Ok(Action::Stay),
+            "OPEN" => {
+                // Boarding indices, optionally followed by `/` and alighting indices
+                let picks = parse_indices(&mut parts, max_indices)?;
+                let drops = match parts.next() {
+                    Some(_) => Some(parse_indices(&mut parts, max_indices)?),
+                    None => None,
+                };
+                if let Some(x) = parts.next() {
+                    bail!("Invalid passenger index format: {}", x);
+                }
+                Ok(Action::Open { picks, drops })
+            }
+            _ => bail!("Unknown action: {}", keyword),
+        }
+    }
+}
+
+/// Reads indices up to the next `/`, which is left for the caller, or the end of the line.
+fn parse_indices<'a>(
+    parts: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    max_indices: Option<usize>,
+) -> Result<Vec<usize>> {
+    let mut indices = vec![];
+    while let Some(x) = parts.next_if(|&x| x != "/") {
+        if max_indices.is_some_and(|max| indices.len() >= max) {
+            bail!("OPEN lists more than {} passenger indices", indices.len());
+        }
+        indices.push(
+            x.parse::<usize>()
+                .with_context(|| format!("Invalid passenger index format: {}", x))?,
+        );
+    }
+    Ok(indices)
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse_line(s, false)
+    }
+}
+
+/// Writes the action as an output line, which [`Action::from_str`] reads back.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())?;
+        if let Action::Open { picks, drops } = self {
+            for pick in picks {
+                write!(f, " {}", pick)?;
+            }
+            if let Some(drops) = drops {
+                f.write_str(" /")?;
+                for drop in drops {
+                    write!(f, " {}", drop)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strict_parse() -> Result<()> {
+        assert_eq!("UP".parse::<Action>()?, Action::Up);
+        assert_eq!(
+            "OPEN 0 3".parse::<Action>()?,
+            Action::Open {
+                picks: vec![0, 3],
+                drops: None
+            }
+        );
+        assert_eq!(
+            "OPEN 0 / 1 2".parse::<Action>()?,
+            Action::Open {
+                picks: vec![0],
+                drops: Some(vec![1, 2])
+            }
+        );
+        assert_eq!(
+            "OPEN /".parse::<Action>()?,
+            Action::Open {
+                picks: vec![],
+                drops: Some(vec![])
+            }
+        );
+        assert!("up".parse::<Action>().is_err());
+        assert!("U".parse::<Action>().is_err());
+        assert!("OPEN x".parse::<Action>().is_err());
+        assert!("".parse::<Action>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_round_trip() -> Result<()> {
+        for line in ["UP", "STAY", "OPEN", "OPEN 0 3", "OPEN 0 / 1 2", "OPEN /"] {
+            assert_eq!(line.parse::<Action>()?.to_string(), line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lenient_parse() -> Result<()> {
+        assert_eq!(Action::parse_line("up", true)?, Action::Up);
+        assert_eq!(Action::parse_line("D", true)?, Action::Down);
+        assert_eq!(Action::parse_line("Stay", true)?, Action::Stay);
+        assert_eq!(
+            Action::parse_line("open 0 1", true)?,
+            Action::Open {
+                picks: vec![0, 1],
+                drops: None
+            }
+        );
+        assert!(Action::parse_line("jump", true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capped_parse() -> Result<()> {
+        assert_eq!(
+            Action::parse_line_capped("OPEN 0 1 / 2", false, Some(2))?,
+            Action::Open {
+                picks: vec![0, 1],
+                drops: Some(vec![2])
+            }
+        );
+        let e = Action::parse_line_capped("OPEN 0 1 2", false, Some(2)).unwrap_err();
+        assert_eq!(e.to_string(), "OPEN lists more than 2 passenger indices");
+        // The cap is hit before the bad token past it is ever parsed
+        assert!(
+            Action::parse_line_capped("OPEN 0 / 1 2 x", false, Some(2))
+                .unwrap_err()
+                .to_string()
+                .contains("more than 2")
+        );
+        assert!(Action::parse_line("OPEN 0 / 1 / 2", false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{Action, Observation, SimParams};
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+
+/// The first line the judge sends: `N M C T lambda`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolHeader {
+    pub n: usize,
+    pub m: usize,
+    pub c: usize,
+    pub t: usize,
+    pub lambda: f64,
+}
+
+impl FromStr for ProtocolHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let [n, m, c, t, lambda] = parts[..] else {
+            bail!("Header needs 5 values, got {}", parts.len());
+        };
+        Ok(Self {
+            n: n.parse().context("Invalid N")?,
+            m: m.parse().context("Invalid M")?,
+            c: c.parse().context("Invalid C")?,
+            t: t.parse().context("Invalid T")?,
+            lambda: lambda.parse().context("Invalid lambda")?,
+        })
+    }
+}
+
+/// One per-elevator capability line (protocol version 3):
+/// `capacity travel_time k f_1 ... f_k`, listing the floors the elevator serves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub capacity: usize,
+    pub travel_time: usize,
+    pub floors: Vec<usize>,
+}
+
+impl Capability {
+    pub fn serves(&self, floor: usize) -> bool {
+        self.floors.contains(&floor)
+    }
+}
+
+impl FromStr for Capability {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split_whitespace()
+            .map(|x| {
+                x.parse::<usize>()
+                    .with_context(|| format!("Invalid number: {}", x))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let [capacity, travel_time, k, ref floors @ ..] = values[..] else {
+            bail!("Capability line needs at least 3 values");
+        };
+        if floors.len() != k {
+            bail!("Expected {} floors, got {}", k, floors.len());
+        }
+        Ok(Self {
+            capacity,
+            travel_time,
+            floors: floors.to_vec(),
+        })
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.capacity,
+            self.travel_time,
+            self.floors.len()
+        )?;
+        for floor in &self.floors {
+            write!(f, " {}", floor)?;
+        }
+        Ok(())
+    }
+}
+
+/// An agent running in the judge's process, for agents written against the library
+/// instead of the stdin/stdout protocol. See [`crate::run_episode`].
+pub trait Agent {
+    /// Called before the first turn of each episode.
+    fn start(&mut self, _params: &SimParams) {}
+
+    /// One action per elevator for the observed turn.
+    fn act(&mut self, observation: &Observation) -> Vec<Action>;
+}
+
+/// A closure is an agent with nothing to set up, for quick experiments.
+impl<F: FnMut(&Observation) -> Vec<Action>> Agent for F {
+    fn act(&mut self, observation: &Observation) -> Vec<Action> {
+        self(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_protocol_lines() -> Result<()> {
+        let header: ProtocolHeader = "10 3 10 100 0.1".parse()?;
+        assert_eq!((header.n, header.m, header.c, header.t), (10, 3, 10, 100));
+
+        let capability: Capability = "6 2 3 0 4 8".parse()?;
+        assert_eq!(capability.floors, vec![0, 4, 8]);
+        assert!(capability.serves(4) && !capability.serves(5));
+        assert_eq!(capability.to_string(), "6 2 3 0 4 8");
+
+        assert!("6 2 3 0 4".parse::<Capability>().is_err());
+        assert!("10 3 10".parse::<ProtocolHeader>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use elevator_sim::{
+    Action, Capability, ElevatorSpec, Observation, ProtocolHeader, SimParams, SimulationState,
+};
+use std::io::{self, BufRead};
+
+/// Beam width and look-ahead in turns, unless BEAM_WIDTH and BEAM_DEPTH say otherwise
+const WIDTH: usize = 8;
+const DEPTH: usize = 5;
+/// Most joint actions tried from one state
+const MAX_JOINT: usize = 256;
+
+/// One path of the beam: the state it leads to and the actions of its first turn.
+struct Node {
+    state: SimulationState,
+    first: Vec<Action>,
+    cost: u64,
+}
+
+/// The score so far plus, for every passenger still travelling, the penalty of the
+/// turns they would need at best: the ride for those aboard, and the nearest car's trip
+/// to them and the ride for those waiting.
+fn cost(state: &SimulationState) -> u64 {
+    let snapshot = state.create_snapshot();
+    let params = state.params();
+    let end = state.turn;
+    let mut cost = state.calculate_final_score();
+    for e in &snapshot.elevators {
+        for p in &e.passengers {
+            let turns = e.floor.abs_diff(p.target_floor) + 1;
+            cost += p.marginal_penalty(end, turns, params.warmup, &params.scoring);
+        }
+    }
+    for (floor, f) in snapshot.floors.iter().enumerate() {
+        let fetch = snapshot
+            .elevators
+            .iter()
+            .map(|e| e.floor.abs_diff(floor))
+            .min()
+            .unwrap_or(0);
+        for p in &f.waiting {
+            let turns = fetch + floor.abs_diff(p.target_floor) + 2;
+            cost += p.marginal_penalty(end, turns, params.warmup, &params.scoring);
+        }
+    }
+    cost
+}
+
+/// Actions worth trying for each elevator, the most promising first: an `OPEN` that
+/// lets someone out or boards as many as fit, a move toward the next destination or
+/// the nearest waiting passenger, the other move, and `STAY`.
+fn candidates(state: &SimulationState, capabilities: &[Capability]) -> Vec<Vec<Action>> {
+    let snapshot = state.create_snapshot();
+    let n = snapshot.floors.len();
+    snapshot
+        .elevators
+        .iter()
+        .zip(capabilities)
+        .map(|(e, capability)| {
+            let mut actions = vec![];
+            let waiting = state.detected_count(e.floor);
+            let space = capability.capacity.saturating_sub(e.passengers.len());
+            let dropping = e.passengers.iter().any(|p| p.target_floor == e.floor);
+            if capability.serves(e.floor) && (dropping || space.min(waiting) > 0) {
+                let picks: Vec<usize> = (0..space.min(waiting)).collect();
+                actions.push(Action::open(&picks));
+            }
+            let goal = e.passengers.first().map(|p| p.target_floor).or_else(|| {
+                (0..n)
+                    .filter(|&f| !snapshot.floors[f].waiting.is_empty())
+                    .min_by_key(|&f| f.abs_diff(e.floor))
+            });
+            let (toward, away) = match goal {
+                Some(goal) if goal < e.floor => (Action::Down, Action::Up),
+                _ => (Action::Up, Action::Down),
+            };
+            for action in [toward, away] {
+                let valid = match action {
+                    Action::Up => e.floor + 1 < n,
+                    _ => e.floor > 0,
+                };
+                if valid {
+                    actions.push(action);
+                }
+            }
+            actions.push(Action::Stay);
+            actions
+        })
+        .collect()
+}
+
+/// Up to [`MAX_JOINT`] combinations of one candidate per elevator. With many
+/// elevators only the two most promising candidates of each are combined.
+fn joint_actions(mut options: Vec<Vec<Action>>) -> Vec<Vec<Action>> {
+    let combinations = options
+        .iter()
+        .try_fold(1usize, |product, o| product.checked_mul(o.len()));
+    if combinations.is_none_or(|c| c > MAX_JOINT) {
+        for o in &mut options {
+            o.truncate(2);
+        }
+    }
+    let mut joint = vec![vec![]];
+    for o in &options {
+        joint = joint
+            .iter()
+            .flat_map(|prefix: &Vec<Action>| {
+                o.iter().map(move |action| {
+                    let mut actions = prefix.clone();
+                    actions.push(action.clone());
+                    actions
+                })
+            })
+            .take(MAX_JOINT)
+            .collect();
+    }
+    joint
+}
+
+/// The first turn's actions of the best path found from `root`.
+fn search(
+    root: &SimulationState,
+    capabilities: &[Capability],
+    width: usize,
+    depth: usize,
+) -> Vec<Action> {
+    let mut beam = vec![Node {
+        state: root.fork(),
+        first: vec![],
+        cost: 0,
+    }];
+    for level in 0..depth {
+        let mut next = vec![];
+        for node in &beam {
+            if node.state.turn >= node.state.t {
+                continue;
+            }
+            for actions in joint_actions(candidates(&node.state, capabilities)) {
+                let mut state = node.state.fork();
+                // Invalid combinations, like two cars boarding the same line, are dropped
+                if state.step(&actions).is_err() {
+                    continue;
+                }
+                let cost = cost(&state);
+                let first = if level == 0 {
+                    actions
+                } else {
+                    node.first.clone()
+                };
+                next.push(Node { state, first, cost });
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        next.sort_by_key(|node| node.cost);
+        next.truncate(width);
+        beam = next;
+    }
+    beam.into_iter()
+        .next()
+        .map(|node| node.first)
+        .filter(|first| !first.is_empty())
+        .unwrap_or_else(|| vec![Action::Stay; root.m])
+}
+
+fn env_or(key: &str, default: usize) -> usize {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    let width = env_or("BEAM_WIDTH", WIDTH).max(1);
+    let depth = env_or("BEAM_DEPTH", DEPTH).max(1);
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut next_line = || {
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        line
+    };
+
+    let mut header_line = next_line();
+    if header_line.is_empty() {
+        return;
+    }
+    // The same protocol versions as greedy_agent
+    let mut protocol = 1;
+    if let Some(offered) = header_line.strip_prefix("PROTOCOL ") {
+        protocol = offered.trim().parse::<u32>().unwrap().min(11);
+        println!("PROTOCOL {}", protocol);
+        header_line = next_line();
+    }
+    let Ok(header) = header_line.parse::<ProtocolHeader>() else {
+        return;
+    };
+    let ProtocolHeader { n, m, c, t, lambda } = header;
+
+    let capabilities: Vec<Capability> = if protocol >= 3 {
+        (0..m).map(|_| next_line().parse().unwrap()).collect()
+    } else {
+        (0..m)
+            .map(|_| Capability {
+                capacity: c,
+                travel_time: 1,
+                floors: (0..n).collect(),
+            })
+            .collect()
+    };
+    let label_lines = match protocol {
+        ..4 => 0,
+        4 => m,
+        _ => m + 1,
+    };
+    for _ in 0..label_lines {
+        next_line();
+    }
+    // The agent cannot know the judge's other options, so it plans under the defaults
+    let params = SimParams {
+        n,
+        m,
+        c,
+        t,
+        lambda,
+        fleet: capabilities
+            .iter()
+            .map(|capability| ElevatorSpec {
+                capacity: capability.capacity,
+                travel_time: capability.travel_time,
+                floors: capability.floors.clone(),
+                max_boardings: None,
+            })
+            .collect(),
+        ..SimParams::default()
+    };
+
+    for turn in 0..t {
+        let (observation, _) = Observation::read(&mut input, &header, protocol, turn).unwrap();
+        let actions = match SimulationState::from_snapshot(&params, &observation.state) {
+            Ok(root) => search(&root, &capabilities, width, depth),
+            Err(_) => vec![Action::Stay; m],
+        };
+        let lines: Vec<String> = actions.iter().map(Action::to_string).collect();
+        if protocol >= 9 {
+            println!("{}", lines.join(";"));
+        } else {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+    }
+}
@@ -1,65 +1,83 @@
+use elevator_sim::{Capability, Observation, ProtocolHeader};
 use std::io::{self, BufRead};
 
 #[allow(clippy::needless_range_loop)]
 fn main() {
     let stdin = io::stdin();
-    let mut lines = stdin.lock().lines();
+    let mut input = stdin.lock();
+    let mut next_line = || {
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        line
+    };
 
     // Read initial header: N M C T lambda
-    let header_line = match lines.next() {
-        Some(Ok(l)) => l,
-        _ => return,
-    };
-    let header: Vec<&str> = header_line.split_whitespace().collect();
-    if header.len() < 4 {
+    let mut header_line = next_line();
+    if header_line.is_empty() {
         return;
     }
-    let n: usize = header[0].parse().unwrap();
-    let m: usize = header[1].parse().unwrap();
-    let c: usize = header[2].parse().unwrap();
-    let t: usize = header[3].parse().unwrap();
-
-    for _ in 0..t {
-        // Read current floors of M elevators
-        let h_line = lines.next().unwrap().unwrap();
-        let h: Vec<usize> = h_line
-            .split_whitespace()
-            .map(|x| x.parse().unwrap())
-            .collect();
+    // Accept the judge's protocol handshake; version 2 adds a remaining-time line per turn,
+    // version 3 per-elevator capability lines after the header, versions 4 and 5 labels
+    // versions 6 and 7 a noise report and the action budget per turn, version 8 lists
+    // only the floors where someone waits, version 9 takes all actions on one line and
+    // versions 10 and 11 report destination changes and how each OPEN went
+    let mut protocol = 1;
+    if let Some(offered) = header_line.strip_prefix("PROTOCOL ") {
+        protocol = offered.trim().parse::<u32>().unwrap().min(11);
+        println!("PROTOCOL {}", protocol);
+        header_line = next_line();
+    }
+    let Ok(header) = header_line.parse::<ProtocolHeader>() else {
+        return;
+    };
+    let ProtocolHeader { n, m, c, t, .. } = header;
 
-        // Read elevator states
-        let mut elevator_passengers = vec![];
-        for _ in 0..m {
-            let line = lines.next().unwrap().unwrap();
-            let parts: Vec<usize> = line
-                .split_whitespace()
-                .map(|x| x.parse().unwrap())
-                .collect();
-            let count = parts[0];
-            let mut ps = vec![];
-            for j in 0..count {
-                ps.push(parts[2 * j + 1]); // target_floor
-            }
-            elevator_passengers.push(ps);
-        }
+    let capabilities: Vec<Capability> = if protocol >= 3 {
+        (0..m).map(|_| next_line().parse().unwrap()).collect()
+    } else {
+        (0..m)
+            .map(|_| Capability {
+                capacity: c,
+                travel_time: 1,
+                floors: (0..n).collect(),
+            })
+            .collect()
+    };
+    // Elevator and floor labels are only for display
+    let label_lines = match protocol {
+        ..4 => 0,
+        4 => m,
+        _ => m + 1,
+    };
+    for _ in 0..label_lines {
+        next_line();
+    }
 
-        // Read floor waiting states
-        let mut floor_waiting = vec![];
-        for _ in 0..n {
-            let line = lines.next().unwrap().unwrap();
-            let parts: Vec<usize> = line
-                .split_whitespace()
-                .map(|x| x.parse().unwrap())
-                .collect();
-            let count = parts[0];
-            let mut ps = vec![];
-            for j in 0..count {
-                ps.push(parts[2 * j + 1]); // target_floor
-            }
-            floor_waiting.push(ps);
-        }
+    for turn in 0..t {
+        // This agent is fast enough to ignore the time left, and the state already
+        // reflects the noise, destination changes and pickups of the previous turn
+        let (observation, _) = Observation::read(&mut input, &header, protocol, turn).unwrap();
+        let h: Vec<usize> = observation
+            .state
+            .elevators
+            .iter()
+            .map(|e| e.floor)
+            .collect();
+        let elevator_passengers: Vec<Vec<usize>> = observation
+            .state
+            .elevators
+            .iter()
+            .map(|e| e.passengers.iter().map(|p| p.target_floor).collect())
+            .collect();
+        let floor_waiting: Vec<Vec<usize>> = observation
+            .state
+            .floors
+            .iter()
+            .map(|f| f.waiting.iter().map(|p| p.target_floor).collect())
+            .collect();
 
         let mut picked_on_floor = vec![0; n];
+        let mut actions = Vec::with_capacity(m);
 
         // Greedy Decision
         for i in 0..m {
@@ -68,6 +86,8 @@ fn main() {
             let waiting_here = &floor_waiting[current_floor];
 
             // 1. Should we OPEN to drop off?
+            let capability = &capabilities[i];
+            let c = capability.capacity;
             let has_delivery = my_passengers.contains(&current_floor);
 
             // 2. Should we OPEN to pick up?
@@ -78,7 +98,7 @@ fn main() {
             };
             let can_pickup = my_passengers.len() < c && available_to_pick > 0;
 
-            if has_delivery || can_pickup {
+            if capability.serves(current_floor) && (has_delivery || can_pickup) {
                 let mut output = String::from("OPEN");
                 if can_pickup {
                     let space = c - my_passengers.len();
@@ -92,7 +112,7 @@ fn main() {
                     }
                     picked_on_floor[current_floor] += num_to_pick;
                 }
-                println!("{}", output);
+                actions.push(output);
                 continue;
             }
 
@@ -100,9 +120,9 @@ fn main() {
             if !my_passengers.is_empty() {
                 let target = my_passengers[0];
                 if target > current_floor {
-                    println!("UP");
+                    actions.push("UP".to_string());
                 } else {
-                    println!("DOWN");
+                    actions.push("DOWN".to_string());
                 }
             } else {
                 // 4. Move towards nearest waiting passenger
@@ -120,14 +140,21 @@ fn main() {
 
                 if let Some(f) = best_floor {
                     if f > current_floor {
-                        println!("UP");
+                        actions.push("UP".to_string());
                     } else {
-                        println!("DOWN");
+                        actions.push("DOWN".to_string());
                     }
                 } else {
-                    println!("STAY");
+                    actions.push("STAY".to_string());
                 }
             }
         }
+        if protocol >= 9 {
+            println!("{}", actions.join(";"));
+        } else {
+            for action in actions {
+                println!("{}", action);
+            }
+        }
     }
 }
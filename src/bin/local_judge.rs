@@ -1,156 +1,1490 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use elevator_sim::{Passenger, SimulationState};
-use proconio::input;
-use proconio::source::once::OnceSource;
-use std::io::{BufRead, BufReader, Write};
-use std::process::{Command, Stdio};
-
-#[derive(Parser)]
+#[cfg(feature = "batch")]
+use clap::Subcommand;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser};
+#[cfg(feature = "batch")]
+use elevator_sim::ScoreSummary;
+use elevator_sim::{
+    ActionOrder, AgentCommand, HistoryBuffer, Instance, JudgeOptions, Latency, Lesson,
+    ProblemVariant, Profile, ReferenceTable, ReplayFile, ReplayReport, RunSummary, ScoreComponents,
+    ScoreRequest, ScoreWeights, Scoring, SimParams, Tier, Verdict, annotation, run_agent,
+    write_protocol,
+};
+#[cfg(feature = "batch")]
+use rayon::prelude::*;
+#[cfg(feature = "batch")]
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// When the judge started, for --global-deadline
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+#[derive(Parser, Clone)]
+#[clap(
+    group = ArgGroup::new("agent_command").args(["command", "agent"]),
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    input_file: String,
-    command: String,
+    #[clap(required_unless_present_any = ["request", "demo", "interactive", "tutorial"])]
+    input_file: Option<String>,
+    #[clap(required_unless_present_any = ["output", "request", "daemon", "agent", "demo", "interactive", "tutorial"])]
+    command: Option<String>,
+    /// Run a built-in example instance instead of an input file: one-passenger,
+    /// capacity-edge or burst
+    #[clap(long, conflicts_with_all = ["request", "watch", "daemon"])]
+    demo: Option<String>,
+    /// Play a tutorial lesson and check its goals: first-ride, go-fetch, capacity,
+    /// two-elevators or let-out
+    #[clap(long, conflicts_with_all = ["demo", "interactive", "request", "watch", "daemon"])]
+    tutorial: Option<String>,
+    /// Draw arrivals turn by turn from --seed instead of reading an input file, and
+    /// send the agent each turn's new arrivals in place of the waiting lines
+    #[clap(
+        long,
+        requires = "seed",
+        conflicts_with_all = ["demo", "output", "request", "watch", "daemon", "observation_delay", "detection_delay"]
+    )]
+    interactive: bool,
+    /// The agent's whole command line as one string, e.g. "python3 my_agent.py --depth 3",
+    /// split with the platform's shell quoting rules
+    #[clap(long, conflicts_with = "args")]
+    agent: Option<String>,
+    /// Score a pre-computed output file instead of running an agent
+    #[clap(long, conflicts_with = "agent_command")]
+    output: Option<String>,
+    /// Score a JSON request carrying a seed or an embedded input together with an output
+    #[clap(long, conflicts_with_all = ["agent_command", "output", "input_file"])]
+    request: Option<String>,
     #[clap(short, long)]
     save_log: Option<String>,
+    /// Save the run as a replay file with its input, for the `replay` binary
+    #[clap(long, requires = "agent_command", conflicts_with_all = ["interactive", "repeats", "watch", "daemon"])]
+    save_replay: Option<String>,
+    /// Keep every turn's snapshot in the --save-replay file, so that a replay that
+    /// diverges shows the first turn where it does
+    #[clap(long, requires = "save_replay")]
+    replay_snapshots: bool,
+    /// Save the per-turn state as a JSON array of snapshots
+    #[clap(long)]
+    save_history: Option<String>,
+    /// Keep only the last K saved turns, bounding memory on long runs
+    #[clap(long, requires = "save_history")]
+    history_last: Option<usize>,
+    /// Save only every k-th turn
+    #[clap(long, default_value_t = 1, requires = "save_history")]
+    history_every: usize,
+    /// Save only these turns, e.g. 10,50,99
+    #[clap(long, value_delimiter = ',', requires = "save_history")]
+    snapshot_at: Option<Vec<usize>>,
+    /// Save what the agent was sent each turn as a JSON array (with --output or --request)
+    #[clap(long, conflicts_with = "agent_command")]
+    save_observations: Option<String>,
+    /// Write the exact bytes an agent would have been sent under --protocol while
+    /// playing the --output or --request actions, for testing a parser offline
+    #[clap(long, conflicts_with = "agent_command")]
+    echo_protocol: Option<String>,
+    /// Run every agent process in a fresh temporary directory, removed afterwards
+    #[clap(long)]
+    workdir_per_case: bool,
+    /// Extra environment variable for the agent, as KEY=VALUE (repeatable)
+    #[clap(long = "env", value_parser = parse_env)]
+    envs: Vec<(String, String)>,
+    /// Run the agent this many times on each input, passing AGENT_SEED=S..S+K-1
+    #[clap(long, default_value_t = 1)]
+    repeats: usize,
+    /// Seed S passed to the agent as AGENT_SEED (the first one under --repeats)
+    #[clap(long)]
+    agent_seed: Option<u64>,
+    /// Run the agent K times and check that every run writes the same actions
+    #[clap(
+        long,
+        requires = "agent_command",
+        conflicts_with_all = ["repeats", "watch"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..)
+    )]
+    check_determinism: Option<usize>,
+    /// Pace turns on the wall clock for live demos: x1 plays one turn per second, x10 ten
+    #[clap(long, value_parser = parse_speed)]
+    realtime: Option<f64>,
+    /// Write the state after every turn as one JSON line to this file or pipe
+    #[clap(long)]
+    stream: Option<String>,
+    /// Draw the state after every turn on stderr
+    #[clap(long)]
+    verbose: bool,
+    /// Print where the judge's time went: input generation, protocol I/O, waiting for
+    /// the agent, applying actions and recording snapshots
+    #[clap(long)]
+    profile: bool,
+    /// Report the agent's answer time per turn (min/mean/max/p99) and its CPU time,
+    /// also appended to --save-log as `#` lines
+    #[clap(long)]
+    latency: bool,
+    /// Accept lower-case and abbreviated actions (U/D/S/O)
+    #[clap(long)]
+    lenient: bool,
+    /// Turns an elevator needs to travel one floor
+    #[clap(long)]
+    travel_time: Option<usize>,
+    /// Comma-separated home floor per elevator; enables the parking policy
+    #[clap(long, value_delimiter = ',')]
+    home_floors: Option<Vec<usize>>,
+    /// Idle turns before an empty elevator drifts toward its home floor
+    #[clap(long)]
+    park_after: Option<usize>,
+    /// Order in which elevator actions are applied each turn: index, reverse or random
+    #[clap(long)]
+    action_order: Option<ActionOrder>,
+    /// Seed for --action-order random
+    #[clap(long)]
+    order_seed: Option<u64>,
+    /// Offer this protocol version to the agent (2 sends the remaining time every turn)
+    #[clap(long, default_value_t = 1)]
+    protocol: u32,
+    /// Total time in milliseconds the agent may spend answering
+    #[clap(long)]
+    time_limit: Option<u64>,
+    /// Time in milliseconds the agent may take to answer one turn; an agent that takes
+    /// longer is killed with TLE
+    #[clap(long, alias = "time-limit-ms")]
+    turn_time_limit: Option<u64>,
+    /// Most bytes the agent may write per turn
+    #[clap(long)]
+    max_turn_bytes: Option<usize>,
+    /// Most bytes the agent may write over the whole run
+    #[clap(long)]
+    max_total_bytes: Option<usize>,
+    /// Wall-clock budget in milliseconds for the whole invocation; episodes still
+    /// running when it is spent stop early with a partial score
+    #[clap(long)]
+    global_deadline: Option<u64>,
+    /// Reference score table to compare results against
+    #[clap(long)]
+    reference: Option<String>,
+    /// Seed of the input, for --reference; defaults to the input file's name (e.g. 0003.txt)
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Probability that a picked passenger fails to board (noise mode)
+    #[clap(long)]
+    no_show: Option<f64>,
+    /// Probability that an onboard passenger gets off at the wrong floor on OPEN
+    #[clap(long)]
+    early_exit: Option<f64>,
+    /// Seed for the noise mode
+    #[clap(long)]
+    noise_seed: Option<u64>,
+    /// Most passengers that can board an elevator in one OPEN
+    #[clap(long)]
+    max_boardings: Option<usize>,
+    /// Picks an OPEN may list beyond the elevator's capacity before it is invalid
+    #[clap(long)]
+    pick_slack: Option<usize>,
+    /// Problem variant: classic, destination-dispatch or energy
+    #[clap(long)]
+    variant: Option<ProblemVariant>,
+    /// Send agents the state as of this many turns ago
+    #[clap(long)]
+    observation_delay: Option<usize>,
+    /// Hide newly arrived passengers from agents for this many turns
+    #[clap(long)]
+    detection_delay: Option<usize>,
+    /// Count no penalty for the first K turns, scoring only the steady state
+    #[clap(long)]
+    warmup: Option<usize>,
+    /// Load factor above which an elevator counts as crowded, e.g. 0.8
+    #[clap(long)]
+    comfort_threshold: Option<f64>,
+    /// Score added for every turn an elevator ends crowded
+    #[clap(long)]
+    comfort_penalty: Option<u64>,
+    /// Turns a passenger may ride before the ride penalty applies
+    #[clap(long)]
+    max_ride: Option<usize>,
+    /// Score added for every ride longer than --max-ride
+    #[clap(long)]
+    ride_penalty: Option<u64>,
+    /// Keep doors open after OPEN until a STAY closes them, and reject UP/DOWN meanwhile
+    #[clap(long)]
+    door_physics: bool,
+    /// Scoring policy: quadratic, linear, capped:CAP or energy-weighted:WEIGHT
+    #[clap(long)]
+    scoring: Option<Scoring>,
+    /// Weights of the score components, e.g. wait=1,movement=3 (the others stay 1)
+    #[clap(long)]
+    weights: Option<ScoreWeights>,
+    /// Shell command to run before judging, e.g. "cargo build --release"
+    #[clap(long)]
+    build: Option<String>,
+    /// Record every agent run in this SQLite database
+    #[cfg(feature = "results-db")]
+    #[clap(long)]
+    results_db: Option<String>,
+    /// Judge every new agent binary or output directory that appears in this
+    /// submissions directory, recording the runs in --results-db
+    #[clap(long, conflicts_with_all = ["agent_command", "output", "watch", "seed"])]
+    daemon: Option<String>,
+    /// Re-judge whenever the agent binary changes, printing score deltas
+    #[clap(long, requires = "agent_command", conflicts_with_all = ["save_log", "save_history"])]
+    watch: bool,
+    /// Parallel agent runs for `batch`; defaults to one per CPU
+    #[cfg(feature = "batch")]
+    #[clap(long)]
+    jobs: Option<usize>,
+    /// Write the `batch` results to this file, as JSON if it ends in .json and CSV otherwise
+    #[cfg(feature = "batch")]
+    #[clap(long)]
+    report: Option<String>,
     #[clap(trailing_var_arg = true)]
     args: Vec<String>,
+    #[cfg(feature = "batch")]
+    #[command(subcommand)]
+    mode: Option<Mode>,
 }
 
-#[allow(clippy::needless_range_loop)]
-fn main() -> Result<()> {
-    let args = Args::parse();
+#[cfg(feature = "batch")]
+#[derive(Subcommand, Clone)]
+enum Mode {
+    /// Judge a directory of inputs in parallel and summarize the scores, e.g.
+    /// `local_judge batch in ./agent --report scores.csv`
+    // Named like the top-level arguments, which the options refer to
+    #[clap(group = ArgGroup::new("agent_command").args(["command", "agent"]))]
+    Batch {
+        input_file: String,
+        #[clap(required_unless_present = "agent")]
+        command: Option<String>,
+        #[clap(trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+}
 
-    let mut log_writer = if let Some(ref path) = args.save_log {
-        Some(std::io::BufWriter::new(std::fs::File::create(path)?))
+fn parse_env(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => anyhow::bail!("Expected KEY=VALUE, got {:?}", s),
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64> {
+    match s.strip_prefix('x').map(str::parse::<f64>) {
+        Some(Ok(speed)) if speed > 0.0 => Ok(speed),
+        _ => anyhow::bail!("Expected a speed such as x1 or x10, got {:?}", s),
+    }
+}
+
+/// Seed of an input named after it, e.g. 0003.txt.
+fn seed_of(path: &str) -> Option<u64> {
+    Path::new(path).file_stem()?.to_str()?.parse().ok()
+}
+
+fn read_input(path: &str) -> Result<Instance> {
+    let input_content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read input file: {}", path))?;
+    Instance::parse(&input_content).with_context(|| format!("Failed to parse input file: {}", path))
+}
+
+/// Looks up the reference score for this input, if a table was given. The seed comes
+/// from --seed, then `known_seed`, then the input file's name.
+fn reference_score(args: &Args, known_seed: Option<u64>) -> Result<Option<u64>> {
+    let Some(ref path) = args.reference else {
+        return Ok(None);
+    };
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read reference table: {}", path))?;
+    let table = ReferenceTable::parse(&text)
+        .with_context(|| format!("Failed to load reference table: {}", path))?;
+    let seed = match args.seed.or(known_seed) {
+        Some(seed) => seed,
+        None => args
+            .input_file
+            .as_deref()
+            .and_then(seed_of)
+            .context("Cannot infer the seed from the input file name; pass --seed")?,
+    };
+    table
+        .get(seed)
+        .map(Some)
+        .with_context(|| format!("Seed {} is not in the reference table", seed))
+}
+
+fn print_reference(reference: Option<u64>, score: f64) {
+    if let Some(reference) = reference {
+        println!(
+            "Reference: {} (relative {:.3})",
+            reference,
+            score / reference.max(1) as f64
+        );
+    }
+}
+
+/// A fresh temporary directory, removed on drop.
+struct CaseDir(PathBuf);
+
+impl CaseDir {
+    fn new() -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "elevator-sim-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create working directory: {}", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for CaseDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs the agent once on `input`, read from `case`. Besides --env, the agent gets
+/// `CASE_PATH`, `SEED` when the case's seed is known, `TIME_LIMIT_MS` under --time-limit,
+/// `TURN_TIME_LIMIT_MS` under --turn-time-limit and `AGENT_SEED` when `agent_seed` is given.
+fn run_episode(
+    args: &Args,
+    input: &Instance,
+    case: &str,
+    agent_seed: Option<u64>,
+    log_writer: Option<&mut dyn Write>,
+    mut history: Option<&mut HistoryBuffer>,
+    mut summary: Option<&mut RunSummary>,
+) -> Result<Verdict> {
+    let mut agent = AgentCommand::new(
+        args.command.as_deref().context("No agent command given")?,
+        &args.args,
+    );
+    let workdir = args.workdir_per_case.then(CaseDir::new).transpose()?;
+    if let Some(ref workdir) = workdir {
+        agent = agent.dir(&workdir.0)?;
+    }
+    // The agent no longer runs where relative case paths point from
+    let case = if workdir.is_some() {
+        std::path::absolute(case)?.to_string_lossy().into_owned()
     } else {
-        None
+        case.to_string()
     };
+    let seed = args.seed.or_else(|| seed_of(&case));
+    // An interactive agent gets neither, as either would give away the arrivals
+    if args.interactive {
+        agent = agent.env("INTERACTIVE", "1");
+    } else {
+        agent = agent.env("CASE_PATH", &case);
+        if let Some(seed) = seed {
+            agent = agent.env("SEED", &seed.to_string());
+        }
+    }
+    if let Some(limit) = args.time_limit {
+        agent = agent.env("TIME_LIMIT_MS", &limit.to_string());
+    }
+    if let Some(limit) = args.turn_time_limit {
+        agent = agent.env("TURN_TIME_LIMIT_MS", &limit.to_string());
+    }
+    if let Some(seed) = agent_seed {
+        agent = agent.env("AGENT_SEED", &seed.to_string());
+    }
+    for (key, value) in &args.envs {
+        agent = agent.env(key, value);
+    }
+    let options = JudgeOptions {
+        lenient: args.lenient,
+        protocol: args.protocol,
+        time_limit: args.time_limit.map(Duration::from_millis),
+        turn_time_limit: args.turn_time_limit.map(Duration::from_millis),
+        max_turn_bytes: args.max_turn_bytes,
+        max_total_bytes: args.max_total_bytes,
+        deadline: args
+            .global_deadline
+            .map(|ms| *STARTED + Duration::from_millis(ms)),
+        online_seed: args.seed.filter(|_| args.interactive),
+    };
+    let mut stream = match args.stream {
+        Some(ref path) => Some(
+            std::fs::File::create(path)
+                .with_context(|| format!("Failed to open stream: {}", path))?,
+        ),
+        None => None,
+    };
+    let pace = args
+        .realtime
+        .map(|speed| Duration::from_secs_f64(1.0 / speed));
+    let mut next_turn = Instant::now();
+    #[cfg(feature = "results-db")]
+    let started = Instant::now();
+    let verdict = run_agent(input, &agent, &options, log_writer, &mut |snapshot| {
+        if let Some(pace) = pace {
+            next_turn += pace;
+            std::thread::sleep(next_turn.saturating_duration_since(Instant::now()));
+        }
+        // A viewer that went away should not stop the run
+        if let Some(ref mut stream) = stream
+            && let Ok(json) = serde_json::to_string(&snapshot)
+        {
+            let _ = writeln!(stream, "{}", json).and_then(|_| stream.flush());
+        }
+        if args.verbose {
+            eprintln!("{}", snapshot.render_ascii());
+        }
+        if let Some(ref mut summary) = summary {
+            summary.push(&snapshot);
+        }
+        if let Some(ref mut history) = history {
+            history.push(snapshot);
+        }
+    });
+    #[cfg(feature = "results-db")]
+    record_run(args, input, seed, &verdict, started.elapsed())?;
+    verdict
+}
 
-    let input_content = std::fs::read_to_string(&args.input_file)
-        .with_context(|| format!("Failed to read input file: {}", args.input_file))?;
-    let mut source = OnceSource::from(input_content.as_str());
+/// Adds one run to --results-db. Failed runs are kept too, with the error as verdict;
+/// runs on an input without a seed are left out.
+#[cfg(feature = "results-db")]
+fn record_run(
+    args: &Args,
+    input: &Instance,
+    seed: Option<u64>,
+    verdict: &Result<Verdict>,
+    elapsed: Duration,
+) -> Result<()> {
+    let Some(ref path) = args.results_db else {
+        return Ok(());
+    };
+    let Some(seed) = seed else {
+        eprintln!("Not recorded in --results-db: the input has no seed (see --seed)");
+        return Ok(());
+    };
+    let command = args.command.as_deref().unwrap_or_default();
+    let agent = std::iter::once(command)
+        .chain(args.args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let agent_hash = agent_path(command)
+        .and_then(|p| std::fs::read(p).ok())
+        .map(|bytes| elevator_sim::fnv1a_hex(&bytes));
+    let (score, verdict) = match verdict {
+        Ok(v) if v.stopped_at.is_some() => (Some(v.score), "TLE-partial".to_string()),
+        Ok(v) => (Some(v.score), "OK".to_string()),
+        Err(e) => (None, format!("{:#}", e)),
+    };
+    let db = elevator_sim::ResultsDb::open(path)
+        .with_context(|| format!("Failed to open results database: {}", path))?;
+    db.record(&elevator_sim::RunRecord {
+        agent,
+        agent_hash,
+        seed,
+        params: serde_json::to_string(&input.params)?,
+        score,
+        duration_ms: elapsed.as_millis() as u64,
+        verdict,
+        recorded_at: elevator_sim::RunRecord::now(),
+    })
+}
 
-    input! {
-        from &mut source,
-        n: usize, m: usize, c: usize, t: usize, lambda: f64,
+/// A note for scores cut short by --global-deadline.
+fn partial_note(verdict: &Verdict) -> String {
+    match verdict.stopped_at {
+        Some(turn) => format!(" (TLE-partial: stopped at turn {})", turn),
+        None => String::new(),
     }
+}
 
-    let mut passenger_source: Vec<Vec<Vec<Passenger>>> = vec![vec![vec![]; t]; n];
-    let mut next_passenger_id = 0;
+/// Runs the agent `runs` times and compares each action stream with the first one.
+fn check_determinism(args: &Args, input: &Instance, case: &str, runs: usize) -> Result<()> {
+    let m = input.params.m;
+    let mut first: Vec<u8> = vec![];
+    let seed = args.agent_seed;
+    let verdict = run_episode(args, input, case, seed, Some(&mut first), None, None)?;
+    // `#` lines are the agent's commentary: they may vary, and they are not actions
+    let action_lines = |log: &[u8]| -> Vec<String> {
+        String::from_utf8_lossy(log)
+            .lines()
+            .filter(|line| annotation(line).is_none())
+            .map(String::from)
+            .collect()
+    };
+    let a = action_lines(&first);
+    for r in 1..runs {
+        let mut actions: Vec<u8> = vec![];
+        run_episode(args, input, case, seed, Some(&mut actions), None, None)?;
+        let b = action_lines(&actions);
+        // A run that stops early diverges where its lines run out
+        if let Some(line) = (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i)) {
+            let show =
+                |x: Option<&String>| x.map_or("end of output".to_string(), |x| format!("{:?}", x));
+            anyhow::bail!(
+                "Run {} diverged from run 0 at turn {}, elevator {}: {} vs {}",
+                r,
+                line / m,
+                line % m,
+                show(a.get(line)),
+                show(b.get(line))
+            );
+        }
+    }
+    println!("Score: {}", verdict.score);
+    println!("Deterministic: {} runs wrote identical actions", runs);
+    Ok(())
+}
 
-    for i in 0..n {
-        for turn in 0..t {
-            input! {
-                from &mut source,
-                count: usize,
-                targets: [usize; count],
+fn history_buffer(args: &Args) -> Option<HistoryBuffer> {
+    args.save_history.as_ref().map(|_| {
+        let buffer = HistoryBuffer::new(args.history_last, args.history_every);
+        match args.snapshot_at {
+            Some(ref turns) => buffer.at(turns.iter().copied()),
+            None => buffer,
+        }
+    })
+}
+
+fn save_history(args: &Args, history: Option<HistoryBuffer>) -> Result<()> {
+    let (Some(path), Some(history)) = (&args.save_history, history) else {
+        return Ok(());
+    };
+    let json = serde_json::to_string(&history.into_vec())?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write history file: {}", path))
+}
+
+/// Replays `output_text`, saving its history if requested, and prints the report.
+fn score_replay(
+    args: &Args,
+    input: &Instance,
+    output_text: &str,
+    lenient: bool,
+    reference: Option<u64>,
+) -> Result<()> {
+    let mut report = input.replay(output_text, lenient);
+    let summary = RunSummary::from_history(&report.history, report.score, &input.params);
+    let mut history = history_buffer(args);
+    if let Some(ref mut history) = history {
+        for snapshot in std::mem::take(&mut report.history) {
+            history.push(snapshot);
+        }
+    }
+    save_history(args, history)?;
+    if let Some(ref path) = args.echo_protocol {
+        let mut out = vec![];
+        write_protocol(&mut out, input, &report.observations, args.protocol)?;
+        std::fs::write(path, out)
+            .with_context(|| format!("Failed to write protocol file: {}", path))?;
+    }
+    if let Some(ref path) = args.save_observations {
+        let json = serde_json::to_string(&std::mem::take(&mut report.observations))?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write observations file: {}", path))?;
+    }
+    print_report(&report, reference, Some(&summary))
+}
+
+fn score_output(args: &Args, input: &Instance, path: &str) -> Result<()> {
+    let output_text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read output file: {}", path))?;
+    score_replay(
+        args,
+        input,
+        &output_text,
+        args.lenient,
+        reference_score(args, None)?,
+    )
+}
+
+fn print_report(
+    report: &ReplayReport,
+    reference: Option<u64>,
+    summary: Option<&RunSummary>,
+) -> Result<()> {
+    for d in &report.diagnostics {
+        let elevator = match d.label {
+            Some(ref label) => format!("{} \"{}\"", d.elevator, label),
+            None => d.elevator.to_string(),
+        };
+        eprintln!(
+            "Line {} (turn {}, elevator {}): {}",
+            d.line, d.turn, elevator, d.message
+        );
+    }
+    println!("Score: {}", report.score);
+    print_components(&report.components);
+    print_reference(reference, report.score as f64);
+    if let Some(summary) = summary {
+        println!("{}", summary);
+    }
+    if !report.diagnostics.is_empty() {
+        anyhow::bail!(
+            "{} invalid line(s); the score above is partial",
+            report.diagnostics.len()
+        );
+    }
+    Ok(())
+}
+
+/// The --latency report, one line each.
+fn latency_report(verdict: &Verdict) -> Vec<String> {
+    let mut lines = vec![match Latency::from_turns(&verdict.turn_times) {
+        Some(latency) => format!("Latency: {}", latency),
+        None => "Latency: no turns".to_string(),
+    }];
+    if let Some(cpu) = verdict.agent_cpu {
+        lines.push(format!(
+            "Agent CPU time: {:.2} s ({:.2} s spent answering)",
+            cpu.as_secs_f64(),
+            verdict.time_used.as_secs_f64()
+        ));
+    }
+    lines
+}
+
+fn print_profile(generation: Duration, profile: &Profile) {
+    let phases = [
+        ("generation", generation),
+        ("protocol I/O", profile.protocol),
+        ("agent", profile.agent),
+        ("apply_action", profile.apply),
+        ("snapshots", profile.snapshot),
+    ];
+    let total: Duration = phases.iter().map(|(_, d)| *d).sum();
+    println!("Profile:");
+    for (name, duration) in phases {
+        println!(
+            "  {:<13}{:>10.3} ms {:>5.1}%",
+            name,
+            duration.as_secs_f64() * 1e3,
+            100.0 * duration.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+}
+
+/// The score by component, when there is more to it than waiting.
+fn print_components(components: &ScoreComponents) {
+    if components.movement + components.crowding + components.long_ride > 0 {
+        println!("Components: {}", components);
+    }
+}
+
+/// Overrides the instance's parameters with the options given on the command line.
+fn apply_options(args: &Args, params: &mut SimParams) -> Result<()> {
+    if let Some(variant) = args.variant {
+        *params = variant.params(params);
+    }
+    if let Some(travel_time) = args.travel_time {
+        params.travel_time = travel_time;
+    }
+    if let Some(ref home_floors) = args.home_floors {
+        params.home_floors = home_floors.clone();
+    }
+    if let Some(park_after) = args.park_after {
+        params.park_after = park_after;
+    }
+    if let Some(action_order) = args.action_order {
+        params.action_order = action_order;
+    }
+    if let Some(order_seed) = args.order_seed {
+        params.order_seed = order_seed;
+    }
+
+    if let Some(no_show) = args.no_show {
+        params.no_show_prob = no_show;
+    }
+    if let Some(early_exit) = args.early_exit {
+        params.early_exit_prob = early_exit;
+    }
+    if let Some(noise_seed) = args.noise_seed {
+        params.noise_seed = noise_seed;
+    }
+    if let Some(limit) = args.max_boardings {
+        params.max_boardings = Some(limit);
+        for spec in &mut params.fleet {
+            spec.max_boardings = Some(limit);
+        }
+    }
+    if let Some(slack) = args.pick_slack {
+        params.pick_slack = Some(slack);
+    }
+    if let Some(delay) = args.observation_delay {
+        params.observation_delay = delay;
+    }
+    if let Some(delay) = args.detection_delay {
+        params.detection_delay = delay;
+    }
+    if let Some(warmup) = args.warmup {
+        params.warmup = warmup;
+    }
+    if let Some(threshold) = args.comfort_threshold {
+        params.comfort_threshold = Some(threshold);
+    }
+    if let Some(penalty) = args.comfort_penalty {
+        params.comfort_penalty = penalty;
+    }
+    if let Some(max_ride) = args.max_ride {
+        params.max_ride = Some(max_ride);
+    }
+    if let Some(penalty) = args.ride_penalty {
+        params.ride_penalty = penalty;
+    }
+    if args.door_physics {
+        params.door_physics = true;
+    }
+    if let Some(scoring) = args.scoring {
+        params.scoring = scoring;
+    }
+    if let Some(weights) = args.weights {
+        params.weights = weights;
+    }
+
+    params.validate()
+}
+
+/// Runs the --build command through the shell. Its output goes to stderr so that
+/// stdout only carries scores.
+fn build(command: &str) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut c = std::process::Command::new("cmd");
+        c.arg("/C");
+        c
+    } else {
+        let mut c = std::process::Command::new("sh");
+        c.arg("-c");
+        c
+    };
+    let status = shell
+        .arg(command)
+        .stdout(std::io::stderr())
+        .status()
+        .with_context(|| format!("Failed to run build command: {}", command))?;
+    if !status.success() {
+        anyhow::bail!("Build command failed ({}): {}", status, command);
+    }
+    Ok(())
+}
+
+/// Reads `path`, or every file in it when it is a directory, as named inputs.
+fn read_inputs(args: &Args, path: &str) -> Result<Vec<(String, Instance)>> {
+    let paths = if std::path::Path::new(path).is_dir() {
+        let mut paths = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read input directory: {}", path))?
+            .map(|e| Ok(e?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        paths
+    } else {
+        vec![path.into()]
+    };
+    paths
+        .iter()
+        .map(|p| {
+            let name = p.display().to_string();
+            let mut input = read_input(&name)?;
+            apply_options(args, &mut input.params)?;
+            Ok((name, input))
+        })
+        .collect()
+}
+
+/// Score of one input in a set: the rounded mean over --repeats agent seeds, with the
+/// spread across seeds (or the partial-score note of a single run).
+fn judge_case(args: &Args, input: &Instance, case: &str) -> Result<(u64, String)> {
+    if args.repeats == 1 {
+        let verdict = run_episode(args, input, case, args.agent_seed, None, None, None)?;
+        return Ok((verdict.score, partial_note(&verdict)));
+    }
+    let base = args.agent_seed.unwrap_or(0);
+    let scores = (0..args.repeats as u64)
+        .map(|r| Ok(run_episode(args, input, case, Some(base + r), None, None, None)?.score))
+        .collect::<Result<Vec<_>>>()?;
+    let mean = scores.iter().sum::<u64>() as f64 / scores.len() as f64;
+    let note = format!(
+        " (min {} max {} over {} seeds)",
+        scores.iter().min().unwrap(),
+        scores.iter().max().unwrap(),
+        scores.len()
+    );
+    Ok((mean.round() as u64, note))
+}
+
+/// Score of one input from the saved output of the same name in `dir`, with a note on
+/// invalid lines and, under --reference, the score relative to the table.
+fn score_saved(
+    args: &Args,
+    dir: &str,
+    reference: Option<&ReferenceTable>,
+    input: &Instance,
+    case: &str,
+) -> Result<(u64, String)> {
+    let name = Path::new(case)
+        .file_name()
+        .context("Input without a file name")?;
+    let path = Path::new(dir).join(name);
+    let output_text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Missing output: {}", path.display()))?;
+    let report = input.replay(&output_text, args.lenient);
+    let mut note = String::new();
+    if !report.diagnostics.is_empty() {
+        note += &format!(" ({} invalid lines)", report.diagnostics.len());
+    }
+    if let Some(best) = reference.and_then(|table| table.get(seed_of(case)?)) {
+        note += &format!(
+            " (relative {:.3})",
+            report.score as f64 / best.max(1) as f64
+        );
+    }
+    Ok((report.score, note))
+}
+
+/// One input's line in a `batch` report.
+#[cfg(feature = "batch")]
+#[derive(Serialize)]
+struct BatchCase {
+    case: String,
+    seed: Option<u64>,
+    score: Option<u64>,
+    error: Option<String>,
+}
+
+#[cfg(feature = "batch")]
+#[derive(Serialize)]
+struct BatchReport {
+    summary: ScoreSummary,
+    failures: usize,
+    cases: Vec<BatchCase>,
+}
+
+/// Judges every input on a pool of --jobs threads, then prints each score and the
+/// summary, and writes the --report file.
+#[cfg(feature = "batch")]
+fn batch(args: &Args, inputs: &[(String, Instance)]) -> Result<()> {
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = args.jobs {
+        pool = pool.num_threads(jobs);
+    }
+    let results: Vec<Result<(u64, String)>> = pool.build()?.install(|| {
+        inputs
+            .par_iter()
+            .map(|(name, input)| judge_case(args, input, name))
+            .collect()
+    });
+
+    let mut cases = Vec::with_capacity(inputs.len());
+    let mut scores = vec![];
+    for ((name, _), result) in inputs.iter().zip(results) {
+        let (score, error) = match result {
+            Ok((score, note)) => {
+                println!("{}: {}{}", name, score, note);
+                scores.push((name.clone(), score));
+                (Some(score), None)
             }
-            for target_floor in targets {
-                passenger_source[i][turn].push(Passenger {
-                    id: next_passenger_id,
-                    arrival_turn: turn,
-                    target_floor,
-                });
-                next_passenger_id += 1;
+            Err(e) => {
+                println!("{}: error: {:#}", name, e);
+                (None, Some(format!("{:#}", e)))
+            }
+        };
+        cases.push(BatchCase {
+            case: name.clone(),
+            seed: seed_of(name),
+            score,
+            error,
+        });
+    }
+    let report = BatchReport {
+        summary: ScoreSummary::from_scores(&scores),
+        failures: cases.len() - scores.len(),
+        cases,
+    };
+    println!("{}", report.summary);
+    if report.failures > 0 {
+        println!("Failures: {}", report.failures);
+    }
+
+    if let Some(ref path) = args.report {
+        let text = if path.ends_with(".json") {
+            serde_json::to_string_pretty(&report)?
+        } else {
+            let mut csv = String::from("case,seed,score,error\n");
+            for case in &report.cases {
+                csv += &format!(
+                    "{},{},{},{}\n",
+                    csv_field(&case.case),
+                    case.seed.map_or(String::new(), |s| s.to_string()),
+                    case.score.map_or(String::new(), |s| s.to_string()),
+                    csv_field(case.error.as_deref().unwrap_or("")),
+                );
             }
+            csv
+        };
+        std::fs::write(path, text).with_context(|| format!("Failed to write report: {}", path))?;
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field when it holds a separator, quote or line break.
+#[cfg(feature = "batch")]
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Scores one input of a set, returning the score and a note to print after it.
+type CaseScorer<'a> = dyn Fn(&Instance, &str) -> Result<(u64, String)> + 'a;
+
+/// Scores every input with `score_case`, printing each score next to its change from
+/// `previous`.
+fn judge_set(
+    inputs: &[(String, Instance)],
+    previous: &HashMap<String, u64>,
+    score_case: &CaseScorer<'_>,
+) -> HashMap<String, u64> {
+    let mut scores = HashMap::new();
+    for (name, input) in inputs {
+        match score_case(input, name) {
+            Ok((score, note)) => {
+                match previous.get(name) {
+                    Some(&prev) => println!(
+                        "{}: {} ({:+}){}",
+                        name,
+                        score,
+                        score as i64 - prev as i64,
+                        note
+                    ),
+                    None => println!("{}: {}{}", name, score, note),
+                }
+                scores.insert(name.clone(), score);
+            }
+            Err(e) => println!("{}: error: {:#}", name, e),
+        }
+    }
+    let total: u64 = scores.values().sum();
+    let prev_total: u64 = previous.values().sum();
+    if previous.is_empty() {
+        println!("Score: total {}", total);
+    } else {
+        println!(
+            "Score: total {} ({:+})",
+            total,
+            total as i64 - prev_total as i64
+        );
+    }
+    let mut tiers: BTreeMap<Tier, (usize, u64)> = BTreeMap::new();
+    for (name, input) in inputs {
+        if let Some(&score) = scores.get(name) {
+            let entry = tiers.entry(input.difficulty().tier).or_default();
+            entry.0 += 1;
+            entry.1 += score;
+        }
+    }
+    if tiers.len() > 1 {
+        for (tier, (count, total)) in tiers {
+            println!("  {}: {} inputs, total {}", tier, count, total);
         }
     }
+    scores
+}
 
-    let mut state = SimulationState::new(n, m, c, t);
+/// Path of the agent executable, looked up in PATH for bare command names.
+fn agent_path(command: &str) -> Option<PathBuf> {
+    if command.contains(std::path::MAIN_SEPARATOR) {
+        return Some(command.into());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|p| p.is_file())
+}
 
-    let mut child = Command::new(&args.command)
-        .args(&args.args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn agent process")?;
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn watch(args: &Args, inputs: &[(String, Instance)]) -> Result<()> {
+    let command = args.command.as_deref().context("No agent command given")?;
+    let path = agent_path(command).context("Cannot find the agent executable to watch")?;
+    let mut previous = HashMap::new();
+    loop {
+        let stamp = modified(&path);
+        previous = judge_set(inputs, &previous, &|input, case| {
+            judge_case(args, input, case)
+        });
+        println!("Watching {} for changes...", path.display());
+        // Wait for a new, stable timestamp so that a binary still being written is not run
+        while modified(&path) == stamp || modified(&path).is_none() {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
 
-    let mut stdin = child.stdin.take().context("Failed to open stdin")?;
-    let mut stdout = BufReader::new(child.stdout.take().context("Failed to open stdout")?);
+/// FNV-1a of a submission: an executable's bytes, or the names and contents of the
+/// files in an output directory.
+#[cfg(feature = "results-db")]
+fn submission_hash(path: &Path) -> Result<String> {
+    if !path.is_dir() {
+        return Ok(elevator_sim::fnv1a_hex(&std::fs::read(path)?));
+    }
+    let mut files = std::fs::read_dir(path)?
+        .map(|e| Ok(e?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+    let mut bytes = vec![];
+    for file in files {
+        bytes.extend(file.file_name().unwrap_or_default().as_encoded_bytes());
+        bytes.extend(std::fs::read(&file)?);
+    }
+    Ok(elevator_sim::fnv1a_hex(&bytes))
+}
 
-    writeln!(stdin, "{} {} {} {} {}", n, m, c, t, lambda)?;
-    stdin.flush()?;
+/// When a submission last changed. Rewriting a file leaves its directory's time alone,
+/// so an output directory changed when its newest file did.
+#[cfg(feature = "results-db")]
+fn last_change(path: &Path) -> Option<SystemTime> {
+    let own = modified(path);
+    if !path.is_dir() {
+        return own;
+    }
+    std::fs::read_dir(path)
+        .ok()?
+        .filter_map(|e| modified(&e.ok()?.path()))
+        .chain(own)
+        .max()
+}
 
-    for turn in 0..t {
-        state.turn = turn;
-        for i in 0..n {
-            for p in passenger_source[i][turn].drain(..) {
-                state.add_passenger(i, p.target_floor, p.arrival_turn, p.id);
+/// Judges every new submission in `dir` against `inputs` and records each case in
+/// --results-db: an executable is run as the agent, and a directory is scored as saved
+/// outputs named like the inputs. Each case of a submission stays judged as long as the
+/// database holds a run of it under the current hash, so restarting the daemon judges
+/// nothing twice and finishes the submissions it was stopped in the middle of.
+#[cfg(feature = "results-db")]
+fn daemon(args: &Args, dir: &str, inputs: &[(String, Instance)]) -> Result<()> {
+    let path = args
+        .results_db
+        .as_deref()
+        .context("--daemon needs --results-db")?;
+    // Runs are told apart by seed, which comes from the input's name
+    if let Some((name, _)) = inputs.iter().find(|(name, _)| seed_of(name).is_none()) {
+        anyhow::bail!(
+            "--daemon needs inputs named after their seed, like 0000.txt: {}",
+            name
+        );
+    }
+    let db = elevator_sim::ResultsDb::open(path)
+        .with_context(|| format!("Failed to open results database: {}", path))?;
+    println!("Watching {} for submissions...", dir);
+    // Hashes of the submissions seen so far, with the time each last changed
+    let mut hashes: HashMap<PathBuf, (SystemTime, String)> = HashMap::new();
+    loop {
+        let mut submissions = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read submissions directory: {}", dir))?
+            .map(|e| Ok(e?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        submissions.sort();
+        for submission in submissions {
+            // Leave submissions alone while they are still being copied in
+            let Some(changed) = last_change(&submission) else {
+                continue;
+            };
+            if !changed
+                .elapsed()
+                .is_ok_and(|age| age >= Duration::from_secs(1))
+            {
+                continue;
+            }
+            let hash = match hashes.get(&submission) {
+                Some((stamp, hash)) if *stamp == changed => hash.clone(),
+                _ => {
+                    let Ok(hash) = submission_hash(&submission) else {
+                        continue;
+                    };
+                    hashes.insert(submission.clone(), (changed, hash.clone()));
+                    hash
+                }
+            };
+            let agent = submission.display().to_string();
+            let mut pending = vec![];
+            for (case, input) in inputs {
+                let seed = seed_of(case).expect("inputs have seeds");
+                let params = serde_json::to_string(&input.params)?;
+                if !db.has_run(&agent, &hash, seed, &params)? {
+                    pending.push((case.clone(), input.clone()));
+                }
+            }
+            if pending.is_empty() {
+                continue;
+            }
+            if pending.len() == inputs.len() {
+                println!("Judging {}", agent);
+            } else {
+                println!(
+                    "Resuming {} ({} of {} cases left)",
+                    agent,
+                    pending.len(),
+                    inputs.len()
+                );
+            }
+            if submission.is_dir() {
+                judge_set(&pending, &HashMap::new(), &|input, case| {
+                    let started = Instant::now();
+                    let result = score_saved(args, &agent, None, input, case);
+                    let (score, verdict) = match result {
+                        Ok((score, ref note)) if note.is_empty() => (Some(score), "OK".into()),
+                        Ok((score, ref note)) => (Some(score), note.trim().to_string()),
+                        Err(ref e) => (None, format!("{:#}", e)),
+                    };
+                    db.record(&elevator_sim::RunRecord {
+                        agent: agent.clone(),
+                        agent_hash: Some(hash.clone()),
+                        seed: seed_of(case).expect("inputs have seeds"),
+                        params: serde_json::to_string(&input.params)?,
+                        score,
+                        duration_ms: started.elapsed().as_millis() as u64,
+                        verdict,
+                        recorded_at: elevator_sim::RunRecord::now(),
+                    })?;
+                    result
+                });
+            } else {
+                // Runs record themselves through --results-db
+                let args = Args {
+                    command: Some(agent.clone()),
+                    args: vec![],
+                    ..args.clone()
+                };
+                judge_set(&pending, &HashMap::new(), &|input, case| {
+                    judge_case(&args, input, case)
+                });
             }
         }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
 
-        // Send state to agent
-        let mut h_floors = vec![];
-        for i in 0..m {
-            h_floors.push(state.get_elevator_floor(i).to_string());
+fn main() -> Result<()> {
+    LazyLock::force(&STARTED);
+    // Options apply before and after a subcommand alike
+    let command = Args::command().mut_args(|arg| {
+        if arg.is_positional() {
+            arg
+        } else {
+            arg.global(true)
+        }
+    });
+    let mut args = Args::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
+    #[cfg(feature = "batch")]
+    let batch = match args.mode.take() {
+        Some(Mode::Batch {
+            input_file,
+            command,
+            args: agent_args,
+        }) => {
+            args.input_file = Some(input_file);
+            args.command = command;
+            args.args = agent_args;
+            true
         }
-        writeln!(stdin, "{}", h_floors.join(" "))?;
+        None => false,
+    };
+    if (args.demo.is_some() || args.tutorial.is_some() || args.interactive)
+        && let Some(program) = args.input_file.take()
+    {
+        // There is no input file to take the first place, so it holds the agent
+        if let Some(command) = args.command.replace(program) {
+            args.args.insert(0, command);
+        }
+    }
+    if let Some(line) = args.agent.take() {
+        let agent = AgentCommand::parse(&line)?;
+        args.command = Some(agent.program);
+        args.args = agent.args;
+    }
+    if args.repeats == 0 {
+        anyhow::bail!("--repeats must be at least 1");
+    }
+    if args.history_every == 0 {
+        anyhow::bail!("--history-every must be at least 1");
+    }
+
+    if let Some(ref command) = args.build {
+        build(command)?;
+    }
 
-        for i in 0..m {
-            let p_count = state.get_elevator_passenger_count(i);
-            write!(stdin, "{}", p_count)?;
-            for p_idx in 0..p_count {
-                let target = state.get_elevator_passenger_target(i, p_idx);
-                // Note: local_judge originally sent (turn - p.arrival_turn),
-                // but SimulationState doesn't expose arrival_turn for elevator passengers yet.
-                // Let's add it or just send 0 for now if the agent doesn't strictly need it.
-                // For a proper greedy agent, target floor is most important.
-                write!(stdin, " {} {}", target, 0)?;
+    let mut log_writer = if let Some(ref path) = args.save_log {
+        Some(std::io::BufWriter::new(std::fs::File::create(path)?))
+    } else {
+        None
+    };
+
+    if let Some(ref path) = args.request {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read request file: {}", path))?;
+        let request: ScoreRequest = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse request file: {}", path))?;
+        let mut input = request.instance()?;
+        apply_options(&args, &mut input.params)?;
+        return score_replay(
+            &args,
+            &input,
+            &request.output,
+            request.lenient || args.lenient,
+            reference_score(&args, request.seed)?,
+        );
+    }
+
+    let lesson = args.tutorial.as_deref().map(Lesson::get).transpose()?;
+    let input_file = match args.demo.as_ref().or(args.tutorial.as_ref()) {
+        Some(name) => name.as_str(),
+        None if args.interactive => "interactive",
+        None => args.input_file.as_deref().context("No input file given")?,
+    };
+    #[cfg(feature = "batch")]
+    if batch {
+        if !Path::new(input_file).is_dir() {
+            anyhow::bail!("batch needs a directory of inputs, got {}", input_file);
+        }
+        return self::batch(&args, &read_inputs(&args, input_file)?);
+    }
+    if args.watch {
+        return watch(&args, &read_inputs(&args, input_file)?);
+    }
+    if let Some(ref dir) = args.daemon {
+        #[cfg(feature = "results-db")]
+        return daemon(&args, dir, &read_inputs(&args, input_file)?);
+        #[cfg(not(feature = "results-db"))]
+        anyhow::bail!("--daemon {} needs a build with --features results-db", dir);
+    }
+    if args.input_file.is_some() && std::path::Path::new(input_file).is_dir() {
+        let inputs = read_inputs(&args, input_file)?;
+        if let Some(ref dir) = args.output {
+            if !Path::new(dir).is_dir() {
+                anyhow::bail!("With an input directory, --output must be a directory of outputs");
             }
-            writeln!(stdin)?;
+            let reference = match args.reference {
+                Some(ref path) => Some(
+                    std::fs::read_to_string(path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|text| ReferenceTable::parse(&text))
+                        .with_context(|| format!("Failed to load reference table: {}", path))?,
+                ),
+                None => None,
+            };
+            judge_set(&inputs, &HashMap::new(), &|input, case| {
+                score_saved(&args, dir, reference.as_ref(), input, case)
+            });
+            return Ok(());
         }
+        judge_set(&inputs, &HashMap::new(), &|input, case| {
+            judge_case(&args, input, case)
+        });
+        return Ok(());
+    }
+    let generating = Instant::now();
+    let mut input = match args.demo {
+        Some(ref name) => Instance::demo(name)?,
+        None if let Some(ref lesson) = lesson => lesson.instance()?,
+        // Nobody is scheduled: the judge draws the arrivals as the run goes
+        None if args.interactive => Instance {
+            params: SimParams::default(),
+            passengers: vec![],
+        },
+        None => read_input(input_file)?,
+    };
+    apply_options(&args, &mut input.params)?;
+    let generation = generating.elapsed();
 
-        for i in 0..n {
-            let p_count = state.get_waiting_passenger_count(i);
-            write!(stdin, "{}", p_count)?;
-            for p_idx in 0..p_count {
-                let target = state.get_waiting_passenger_target(i, p_idx);
-                write!(stdin, " {} {}", target, 0)?;
-            }
-            writeln!(stdin)?;
-        }
-        stdin.flush()?;
-
-        // Process agent actions
-        for i in 0..m {
-            let mut action_line = String::new();
-            if stdout.read_line(&mut action_line)? == 0 {
-                anyhow::bail!(
-                    "Agent process terminated unexpectedly at turn {} for elevator {}",
-                    turn,
-                    i
-                );
-            }
-            if let Some(ref mut writer) = log_writer {
-                write!(writer, "{}", action_line)?;
+    if let Some(ref path) = args.output {
+        if let Some(ref lesson) = lesson {
+            let output_text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read output file: {}", path))?;
+            let report = lesson.check(&output_text)?;
+            println!("Score: {}\n{}", report.score, report);
+            return Ok(());
+        }
+        return score_output(&args, &input, path);
+    }
+    if let Some(runs) = args.check_determinism {
+        return check_determinism(&args, &input, input_file, runs);
+    }
+    let reference = reference_score(&args, None)?;
+
+    let mut history = history_buffer(&args);
+    if args.repeats == 1 {
+        let mut summary = RunSummary::for_params(&input.params);
+        // A replay or a lesson's goals need the actions after the run, so they are kept
+        // and copied to --save-log afterwards
+        let mut actions = (args.save_replay.is_some() || lesson.is_some()).then(Vec::new);
+        let verdict = run_episode(
+            &args,
+            &input,
+            input_file,
+            args.agent_seed,
+            match actions.as_mut() {
+                Some(actions) => Some(actions as &mut dyn Write),
+                None => log_writer.as_mut().map(|w| w as &mut dyn Write),
+            },
+            history.as_mut(),
+            Some(&mut summary),
+        )?;
+        save_history(&args, history)?;
+        let mut lesson_report = None;
+        if let Some(actions) = actions {
+            if let Some(writer) = log_writer.as_mut() {
+                writer.write_all(&actions)?;
             }
-            let parts: Vec<&str> = action_line.split_whitespace().collect();
-            if parts.is_empty() {
-                anyhow::bail!("Empty action line at turn {} for elevator {}", turn, i);
+            let output_text = String::from_utf8(actions)?;
+            if let Some(ref path) = args.save_replay {
+                let replay =
+                    ReplayFile::record(&input, &output_text, args.lenient, args.replay_snapshots)?;
+                std::fs::write(path, replay.to_json()?)
+                    .with_context(|| format!("Failed to write replay: {}", path))?;
             }
-            let action = parts[0];
-            let mut picks = vec![];
-            if action == "OPEN" {
-                for &p_idx_str in &parts[1..] {
-                    picks.push(
-                        p_idx_str
-                            .parse::<usize>()
-                            .context("Invalid passenger index format")?,
-                    );
+            lesson_report = lesson
+                .map(|lesson| lesson.check(&output_text))
+                .transpose()?;
+        }
+        println!("Score: {}{}", verdict.score, partial_note(&verdict));
+        print_components(&verdict.components);
+        print_reference(reference, verdict.score as f64);
+        if args.max_turn_bytes.is_some() || args.max_total_bytes.is_some() {
+            println!(
+                "Output: {} bytes (at most {} in one turn)",
+                verdict.output_bytes, verdict.peak_turn_bytes
+            );
+        }
+        println!("{}", summary);
+        if let Some(report) = lesson_report {
+            println!("{}", report);
+        }
+        if args.profile {
+            print_profile(generation, &verdict.profile);
+        }
+        if args.latency {
+            for line in latency_report(&verdict) {
+                println!("{}", line);
+                if let Some(writer) = log_writer.as_mut() {
+                    writeln!(writer, "# {}", line)?;
                 }
             }
-            state
-                .apply_action_wasm(i, action, &picks)
-                .map_err(|e| anyhow::anyhow!(e))
-                .with_context(|| format!("Turn {}: Invalid action by elevator {}", turn, i))?;
         }
+        return Ok(());
     }
 
-    if let Some(ref mut writer) = log_writer {
-        writer.flush()?;
+    // Only the first run is logged so that --save-log stays a single replayable output.
+    let mut scores = Vec::with_capacity(args.repeats);
+    let base = args.agent_seed.unwrap_or(0);
+    for r in 0..args.repeats {
+        let (writer, history) = if r == 0 {
+            (
+                log_writer.as_mut().map(|w| w as &mut dyn Write),
+                history.as_mut(),
+            )
+        } else {
+            (None, None)
+        };
+        let verdict = run_episode(
+            &args,
+            &input,
+            input_file,
+            Some(base + r as u64),
+            writer,
+            history,
+            None,
+        )?;
+        println!("Run {}: {}{}", r, verdict.score, partial_note(&verdict));
+        scores.push(verdict.score);
     }
+    let mean = scores.iter().sum::<u64>() as f64 / scores.len() as f64;
+    println!(
+        "Score: mean {:.1} min {} max {}",
+        mean,
+        scores.iter().min().unwrap(),
+        scores.iter().max().unwrap()
+    );
+    print_reference(reference, mean);
+    save_history(&args, history)
+}
 
-    println!("Score: {}", state.calculate_final_score());
-    let _ = child.kill();
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_determinism_needs_two_runs() {
+        let parse = |runs: &str| {
+            Args::try_parse_from([
+                "local_judge",
+                "--check-determinism",
+                runs,
+                "in.txt",
+                "agent",
+            ])
+        };
+        assert!(parse("0").is_err());
+        assert!(parse("1").is_err());
+        assert!(parse("2").is_ok());
+    }
+
+    #[cfg(feature = "results-db")]
+    #[test]
+    fn test_last_change_of_output_directory() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("submission-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let file = dir.join("0000.txt");
+        std::fs::write(&file, "STAY\n")?;
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)?
+            .set_modified(hour_ago)?;
+        let dir_time = modified(&dir);
+        // Rewriting the file leaves the directory's own time alone
+        std::fs::write(&file, "UP\n")?;
+        assert_eq!(modified(&dir), dir_time);
+        assert_eq!(last_change(&dir), modified(&file));
+        assert!(last_change(&dir) > Some(hour_ago));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_determinism_ignores_annotations() -> Result<()> {
+        let input = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let check = |script: &str| {
+            let args = Args::try_parse_from([
+                "local_judge",
+                "--check-determinism",
+                "2",
+                "--",
+                "case.txt",
+                "sh",
+                "-c",
+                script,
+            ])?;
+            check_determinism(&args, &input, "case.txt", 2)
+        };
+        // Each run comments with its own process ID
+        check("while :; do echo \"# pid $$\"; echo STAY; done")?;
+
+        // The second run moves on turn 1, after a different number of comments
+        let marker = std::env::temp_dir().join(format!("determinism-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!(
+            "if [ -e {0} ]; then echo '# again'; echo '# twice'; A=UP; else A=STAY; fi; \
+             touch {0}; echo STAY; echo $A; yes STAY",
+            marker.display()
+        );
+        let error = check(&script).unwrap_err();
+        std::fs::remove_file(&marker)?;
+        assert!(
+            error
+                .to_string()
+                .contains("at turn 1, elevator 0: \"STAY\" vs \"UP\""),
+            "{}",
+            error
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use elevator_sim::ReplayFile;
+
+/// Re-simulates a replay saved with `local_judge --save-replay` and checks it against
+/// the saved run.
+#[derive(Parser)]
+struct Args {
+    replay_file: String,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let text = std::fs::read_to_string(&args.replay_file)
+        .with_context(|| format!("Failed to read replay: {}", args.replay_file))?;
+    let replay = ReplayFile::parse(&text)
+        .with_context(|| format!("Failed to parse replay: {}", args.replay_file))?;
+
+    let (report, divergence) = replay.rerun()?;
+    for d in &report.diagnostics {
+        eprintln!("Line {} (turn {}): {}", d.line, d.turn, d.message);
+    }
+    println!("Score: {}", report.score);
+    match divergence {
+        Some(divergence) => anyhow::bail!("{}", divergence),
+        None => println!(
+            "OK: {} turns match the saved run{}",
+            report.history.len(),
+            if replay.snapshots.is_empty() {
+                " (score only, no snapshots saved)"
+            } else {
+                ""
+            }
+        ),
+    }
+    Ok(())
+}
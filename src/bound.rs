@@ -0,0 +1,82 @@
+use crate::{Passenger, SimulationState};
+
+/// Lowest penalty `passenger` can still end up with, from the distances between the
+/// elevators and the passenger alone: the nearest elevator comes straight to them and
+/// then the fastest one carries them straight to their destination.
+///
+/// Returns `None` when the passenger is neither waiting nor riding in `state`.
+pub fn min_possible_penalty(passenger: &Passenger, state: &SimulationState) -> Option<u64> {
+    let fastest = state.specs.iter().map(|s| s.travel_time).min().unwrap_or(1);
+    let destination = passenger.destination();
+    // Sub-steps from elevator `i` to `floor`
+    let steps = |i: usize, floor: usize| {
+        let travel_time = state.specs[i].travel_time;
+        let e = &state.elevators[i];
+        (e.floor * travel_time + e.offset).abs_diff(floor * travel_time)
+    };
+
+    let delivered = if let Some(i) = state
+        .elevators
+        .iter()
+        .position(|e| e.passengers.iter().any(|p| p.id == passenger.id))
+    {
+        // OPEN on arrival at the current leg's floor, then any further legs
+        let leg = passenger.target_floor;
+        state.turn + steps(i, leg) + leg.abs_diff(destination) * fastest
+    } else {
+        let floor = state
+            .waiting_passengers
+            .iter()
+            .position(|ps| ps.iter().any(|p| p.id == passenger.id))?;
+        let pickup = (0..state.m).map(|i| steps(i, floor)).min()?;
+        // OPEN to board, the ride, then OPEN to get off
+        state.turn + pickup + floor.abs_diff(destination) * fastest + 1
+    };
+    Some(passenger.penalty(delivered + 1, state.params.warmup, &state.params.scoring))
+}
+
+/// Lower bound on the final score from the passengers present so far: the score already
+/// taken plus [`min_possible_penalty`] of every waiting and riding passenger.
+pub fn lower_bound_score(state: &SimulationState) -> u64 {
+    let present = state
+        .waiting_passengers
+        .iter()
+        .flatten()
+        .chain(state.elevators.iter().flat_map(|e| &e.passengers));
+    state.score
+        + present
+            .filter_map(|p| min_possible_penalty(p, state))
+            .sum::<u64>()
+            * state.params.weights.wait
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+    use anyhow::Result;
+
+    #[test]
+    fn test_min_possible_penalty() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 10, 10)?;
+        sim.add_passenger(5, 6, 0, 0)?;
+        sim.add_passenger(2, 4, 0, 1)?;
+        let p = sim.waiting_passengers[5][0].clone();
+        // Board at turn 0, UP at turn 1, OPEN at turn 2: (2 - 0 + 1)^2
+        assert_eq!(min_possible_penalty(&p, &sim), Some(9));
+
+        sim.apply_action(0, &Action::open(&[0]))?;
+        sim.turn = 1;
+        assert_eq!(min_possible_penalty(&p, &sim), Some(9));
+        // Three floors to reach floor 2, OPEN, two floors, OPEN: delivered at turn 7
+        let q = sim.waiting_passengers[2][0].clone();
+        assert_eq!(min_possible_penalty(&q, &sim), Some(64));
+        assert_eq!(lower_bound_score(&sim), 73);
+
+        sim.apply_action(0, &Action::Up)?;
+        sim.turn = 2;
+        sim.apply_action(0, &Action::open(&[]))?;
+        assert_eq!(min_possible_penalty(&p, &sim), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{Instance, SimParams};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Keyed by the parameters' JSON, since `SimParams` holds floats and is not `Hash`
+type Key = (u64, String);
+
+/// Generated instances memoized by seed and parameters. Clones share the same cache,
+/// so one can be handed to every worker thread of a batch tool.
+#[derive(Clone, Default)]
+pub struct InstanceCache {
+    instances: Arc<Mutex<HashMap<Key, Arc<Instance>>>>,
+}
+
+impl InstanceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The instance for `seed` and `params`, generated on first use. Generation runs
+    /// outside the lock; if two threads race, both get the instance stored first.
+    pub fn get(&self, seed: u64, params: &SimParams) -> Result<Arc<Instance>> {
+        let key = (seed, serde_json::to_string(params)?);
+        if let Some(instance) = self.instances.lock().unwrap().get(&key) {
+            return Ok(instance.clone());
+        }
+        let instance = Arc::new(Instance::generate(seed, params)?);
+        Ok(self
+            .instances
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(instance)
+            .clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_cache_shares_across_threads() -> Result<()> {
+        let cache = InstanceCache::new();
+        let params = SimParams::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (cache, params) = (cache.clone(), params.clone());
+                std::thread::spawn(move || cache.get(1, &params))
+            })
+            .collect();
+        let instances = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Result<Vec<_>>>()?;
+        assert!(instances.iter().all(|i| Arc::ptr_eq(i, &instances[0])));
+        assert_eq!(*instances[0], Instance::generate(1, &params)?);
+
+        let busier = SimParams {
+            lambda: 0.2,
+            ..params
+        };
+        assert!(!Arc::ptr_eq(&cache.get(1, &busier)?, &instances[0]));
+        assert_eq!(cache.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use crate::events::Event;
+use crate::judge::write_header;
+use crate::{Instance, Observation, PROTOCOL_VERSION, SimParams, SimulationState};
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which hidden cases a contest plays: the provisional ones during the contest, the
+/// system-test ones for the final standings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Split {
+    #[default]
+    Provisional,
+    System,
+}
+
+impl FromStr for Split {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "provisional" => Ok(Self::Provisional),
+            "system" => Ok(Self::System),
+            _ => bail!("Unknown split {:?} (expected provisional or system)", s),
+        }
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Provisional => write!(f, "provisional"),
+            Self::System => write!(f, "system"),
+        }
+    }
+}
+
+/// A contest over hidden seeds. Seeds are an HMAC of the split and case keyed with
+/// `secret`, so clients can only play the cases through [`Episode`]s and never see a
+/// passenger schedule or work one out.
+#[derive(Debug, Clone)]
+pub struct Contest {
+    secret: String,
+    pub provisional: usize,
+    pub system: usize,
+    pub split: Split,
+}
+
+impl Contest {
+    pub fn new(secret: &str, provisional: usize, system: usize) -> Self {
+        Self {
+            secret: secret.to_string(),
+            provisional,
+            system,
+            split: Split::Provisional,
+        }
+    }
+
+    pub fn split(mut self, split: Split) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// Number of cases in the split being played.
+    pub fn cases(&self) -> usize {
+        match self.split {
+            Split::Provisional => self.provisional,
+            Split::System => self.system,
+        }
+    }
+
+    fn seed(&self, case: usize) -> Result<u64> {
+        if case >= self.cases() {
+            bail!(
+                "Case {} is out of range: the {} split has {} cases",
+                case,
+                self.split,
+                self.cases()
+            );
+        }
+        let mac = hmac_sha256::HMAC::mac(format!("{}:{}", self.split, case), &self.secret);
+        Ok(u64::from_le_bytes(mac[..8].try_into()?))
+    }
+
+    /// Starts case `case` of the current split, returning the episode and the protocol
+    /// header followed by the first observation.
+    pub fn start(&self, case: usize) -> Result<(Episode, String)> {
+        let instance = Instance::generate(self.seed(case)?, &SimParams::default())?;
+        let mut episode = Episode {
+            case,
+            state: SimulationState::from_instance(&instance)?,
+            previous: vec![],
+        };
+        let mut out = vec![];
+        write_header(&mut out, &episode.state, PROTOCOL_VERSION)?;
+        let mut text = String::from_utf8(out)?;
+        text += &episode.observe()?;
+        Ok((episode, text))
+    }
+}
+
+/// One case being played through the server, turn by turn, under the latest protocol.
+pub struct Episode {
+    pub case: usize,
+    state: SimulationState,
+    previous: Vec<Event>,
+}
+
+impl Episode {
+    /// The current turn's observation.
+    fn observe(&mut self) -> Result<String> {
+        let mut out = vec![];
+        Observation::new(&mut self.state, &self.previous).write(
+            &mut out,
+            PROTOCOL_VERSION,
+            None,
+        )?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Plays one turn from a line of actions separated by `;`. Returns the next
+    /// observation, or `None` once the last turn is played. An invalid line leaves the
+    /// episode as it was.
+    pub fn play(&mut self, line: &str) -> Result<Option<String>> {
+        let actions: Vec<&str> = line.trim_end().split(';').collect();
+        if actions.len() != self.state.m {
+            bail!("Expected {} actions, got {}", self.state.m, actions.len());
+        }
+        let actions = actions
+            .into_iter()
+            .enumerate()
+            .map(|(i, action)| {
+                self.state
+                    .parse_action(i, action, false)
+                    .with_context(|| format!("Invalid action by elevator {}", i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.previous = self.state.step(&actions)?.snapshot.events;
+        if self.state.turn >= self.state.t {
+            return Ok(None);
+        }
+        Ok(Some(self.observe()?))
+    }
+
+    pub fn score(&self) -> u64 {
+        self.state.calculate_final_score()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hidden_seeds() -> Result<()> {
+        let contest = Contest::new("secret", 2, 3);
+        assert_ne!(contest.seed(0)?, Contest::new("other", 2, 3).seed(0)?);
+        assert!(contest.seed(2).is_err());
+        let system = contest.clone().split(Split::System);
+        assert_ne!(contest.seed(0)?, system.seed(0)?);
+        assert!(system.seed(2).is_ok());
+
+        let (mut episode, text) = contest.start(0)?;
+        let params = SimParams::default();
+        let header = format!(
+            "{} {} {} {} {}",
+            params.n, params.m, params.c, params.t, params.lambda
+        );
+        assert_eq!(text.lines().next(), Some(header.as_str()));
+        let stay = vec!["STAY"; params.m].join(";");
+        assert!(episode.play("JUMP").is_err());
+        assert!(episode.play(&vec!["STAY"; params.m + 1].join(";")).is_err());
+        for turn in 1..params.t {
+            let observation = episode.play(&stay)?.context("ended early")?;
+            assert!(!observation.is_empty(), "turn {}", turn);
+        }
+        assert_eq!(episode.play(&stay)?, None);
+        assert!(episode.score() > 0);
+        Ok(())
+    }
+}
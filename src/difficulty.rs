@@ -0,0 +1,168 @@
+use crate::Instance;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Tier {
+    pub const ALL: [Tier; 3] = [Tier::Easy, Tier::Medium, Tier::Hard];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tier::Easy => "easy",
+            Tier::Medium => "medium",
+            Tier::Hard => "hard",
+        }
+    }
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Tier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL.into_iter().find(|t| t.name() == s) {
+            Some(tier) => Ok(tier),
+            None => bail!("Unknown difficulty tier: {}", s),
+        }
+    }
+}
+
+/// Rough difficulty of an instance, from its traffic alone (no agent is run).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Difficulty {
+    /// Passenger-floors of demand over the passenger-floors the fleet can move in `T` turns.
+    pub density: f64,
+    /// Variance-to-mean ratio of arrivals per turn (1 for Poisson traffic).
+    pub burstiness: f64,
+    /// Normalized entropy of the origin-destination pairs, from 0 (one pair) to 1 (uniform).
+    pub spread: f64,
+    pub score: f64,
+    pub tier: Tier,
+}
+
+impl Difficulty {
+    pub fn of(instance: &Instance) -> Self {
+        let p = &instance.params;
+        let mut per_turn = vec![0usize; p.t];
+        let mut pairs = vec![0usize; p.n * p.n];
+        let mut demand = 0;
+        for (floor, turns) in instance.passengers.iter().enumerate() {
+            for (turn, passengers) in turns.iter().enumerate() {
+                per_turn[turn] += passengers.len();
+                for passenger in passengers {
+                    let target = passenger.destination();
+                    demand += floor.abs_diff(target);
+                    pairs[floor * p.n + target] += 1;
+                }
+            }
+        }
+
+        let throughput: f64 = (0..p.m)
+            .map(|i| {
+                let spec = p.elevator_spec(i);
+                spec.capacity as f64 / spec.travel_time.max(1) as f64
+            })
+            .sum::<f64>()
+            * p.t as f64;
+        let density = if throughput > 0.0 {
+            demand as f64 / throughput
+        } else {
+            0.0
+        };
+
+        let total: usize = per_turn.iter().sum();
+        let mean = total as f64 / p.t.max(1) as f64;
+        let variance = per_turn
+            .iter()
+            .map(|&x| (x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / p.t.max(1) as f64;
+        let burstiness = if mean > 0.0 { variance / mean } else { 0.0 };
+
+        let possible = (p.n * p.n.saturating_sub(1)) as f64;
+        let entropy: f64 = pairs
+            .iter()
+            .filter(|&&x| x > 0)
+            .map(|&x| {
+                let q = x as f64 / total as f64;
+                -q * q.ln()
+            })
+            .sum();
+        let spread = if possible > 1.0 {
+            entropy / possible.ln()
+        } else {
+            0.0
+        };
+
+        // Bursts and scattered trips both make the same demand harder to batch.
+        let score = density * burstiness.max(1.0) * (0.5 + spread / 2.0);
+        let tier = if score < 0.15 {
+            Tier::Easy
+        } else if score < 0.4 {
+            Tier::Medium
+        } else {
+            Tier::Hard
+        };
+        Self {
+            density,
+            burstiness,
+            spread,
+            score,
+            tier,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (score {:.3}, density {:.3}, burstiness {:.3}, spread {:.3})",
+            self.tier, self.score, self.density, self.burstiness, self.spread
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimParams;
+
+    #[test]
+    fn test_difficulty_grows_with_traffic() -> Result<()> {
+        let quiet = Instance::generate(
+            0,
+            &SimParams {
+                lambda: 0.01,
+                ..SimParams::default()
+            },
+        )?;
+        let busy = Instance::generate(
+            0,
+            &SimParams {
+                lambda: 0.5,
+                ..SimParams::default()
+            },
+        )?;
+        let (quiet, busy) = (Difficulty::of(&quiet), Difficulty::of(&busy));
+        assert!(quiet.density < busy.density);
+        assert_eq!(quiet.tier, Tier::Easy);
+        assert_eq!(busy.tier, Tier::Hard);
+        assert!(busy.spread > 0.9);
+        Ok(())
+    }
+}
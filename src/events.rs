@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Event {
+    Boarded {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+    },
+    Delivered {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+        penalty: u64,
+    },
+    /// A passenger on a multi-leg journey got off at a transfer floor and is waiting
+    /// there for the next leg.
+    Transferred {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+    },
+    /// A picked passenger was left waiting because the elevator was full.
+    Skipped {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+    },
+    /// A picked passenger did not board (noise mode) and is still waiting.
+    NoShow {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+    },
+    /// A passenger got off before their destination (noise mode) and is waiting there.
+    ExitedEarly {
+        passenger_id: usize,
+        elevator: usize,
+        floor: usize,
+    },
+    /// A passenger arrived at their destination floor and was delivered at once
+    /// (`SameFloorPolicy::Deliver`).
+    DeliveredOnArrival {
+        passenger_id: usize,
+        floor: usize,
+        penalty: u64,
+    },
+    /// A waiting passenger changed their destination (`SimParams::destination_changes`).
+    TargetChanged {
+        passenger_id: usize,
+        floor: usize,
+        target: usize,
+    },
+    /// An elevator ended its action carrying more than `SimParams::comfort_threshold`
+    /// of its capacity.
+    Crowded {
+        elevator: usize,
+        load: usize,
+        penalty: u64,
+    },
+    /// A passenger was still on board `SimParams::max_ride` turns after boarding.
+    RideTooLong {
+        passenger_id: usize,
+        elevator: usize,
+        penalty: u64,
+    },
+    /// An idle elevator moved toward its home floor under the parking policy.
+    Parked {
+        elevator: usize,
+        from: usize,
+        to: usize,
+    },
+    /// The score went up by `amount`. Logged after the event that caused it, and on the
+    /// last turn for each passenger left unfinished.
+    ScoreDelta {
+        /// The passenger it is charged to, unless an elevator caused it
+        passenger_id: Option<usize>,
+        amount: u64,
+        reason: ScoreReason,
+    },
+}
+
+/// Why the score changed, in a [`Event::ScoreDelta`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreReason {
+    /// A delivered passenger's wait
+    Delivery,
+    /// The wait of a passenger still waiting or riding when the run ended
+    Unfinished,
+    /// `SimParams::move_penalty` for a moving elevator
+    Movement,
+    /// `SimParams::comfort_penalty`
+    Crowding,
+    /// `SimParams::ride_penalty`
+    LongRide,
+}
@@ -0,0 +1,431 @@
+use crate::spec::INPUT_VERSION;
+use crate::{
+    ArrivalProcess, Difficulty, Passenger, ReplayReport, SameFloorPolicy, Scenario, SimParams,
+    SimulationState, generate_passengers, replay_output,
+};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputVersion {
+    /// `N M C T lambda` followed by per-floor arrival counts and targets.
+    V1,
+    /// JSON document carrying the full `SimParams` and a flat arrival list.
+    V2,
+}
+
+impl InputVersion {
+    pub fn detect(text: &str) -> Self {
+        if text.trim_start().starts_with('{') {
+            InputVersion::V2
+        } else {
+            InputVersion::V1
+        }
+    }
+}
+
+/// A problem instance: parameters plus the passenger table `[floor][turn]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub params: SimParams,
+    pub passengers: Vec<Vec<Vec<Passenger>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InputV2 {
+    version: u32,
+    params: SimParams,
+    arrivals: Vec<ArrivalV2>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArrivalV2 {
+    /// Zero or negative for passengers already waiting at turn 0, that many turns ago.
+    turn: i64,
+    floor: FloorRef,
+    target: FloorRef,
+}
+
+/// A floor in a v2 input, by index or by one of `params.floor_labels`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum FloorRef {
+    Index(usize),
+    Label(String),
+}
+
+impl FloorRef {
+    fn new(params: &SimParams, floor: usize) -> Self {
+        if params.floor_labels.is_empty() {
+            FloorRef::Index(floor)
+        } else {
+            FloorRef::Label(params.floor_label(floor))
+        }
+    }
+
+    fn resolve(&self, params: &SimParams) -> Result<usize> {
+        match self {
+            FloorRef::Index(floor) => Ok(*floor),
+            FloorRef::Label(label) => params
+                .floor_index(label)
+                .with_context(|| format!("Unknown floor label: {}", label)),
+        }
+    }
+}
+
+impl Instance {
+    pub fn generate(seed: u64, params: &SimParams) -> Result<Self> {
+        params.validate()?;
+        Ok(Self {
+            params: params.clone(),
+            passengers: generate_passengers(seed, params)?,
+        })
+    }
+
+    /// One of the built-in example instances, [`Scenario::DEMOS`].
+    pub fn demo(name: &str) -> Result<Self> {
+        Scenario::demo(name)?.instance()
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        Difficulty::of(self)
+    }
+
+    pub fn replay(&self, output_text: &str, lenient: bool) -> ReplayReport {
+        let sim = SimulationState::from_params(&self.params);
+        replay_output(sim, &self.passengers, output_text, lenient)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_capped(text, None)
+    }
+
+    /// Parses an instance, refusing one with more than `max_cells` floor-turns before
+    /// allocating its passenger table.
+    pub fn parse_capped(text: &str, max_cells: Option<usize>) -> Result<Self> {
+        let instance = match InputVersion::detect(text) {
+            InputVersion::V1 => Self::parse_v1(text, max_cells)?,
+            InputVersion::V2 => Self::parse_v2(text, max_cells)?,
+        };
+        instance.params.validate()?;
+        Ok(instance)
+    }
+
+    fn parse_v1(text: &str, max_cells: Option<usize>) -> Result<Self> {
+        let mut tokens = text.split_whitespace();
+        let mut next = |what: &str| -> Result<&str> {
+            tokens
+                .next()
+                .with_context(|| format!("Unexpected end of input while reading {}", what))
+        };
+        let n: usize = next("N")?.parse().context("Invalid N")?;
+        let m: usize = next("M")?.parse().context("Invalid M")?;
+        let c: usize = next("C")?.parse().context("Invalid C")?;
+        let t: usize = next("T")?.parse().context("Invalid T")?;
+        let lambda: f64 = next("lambda")?.parse().context("Invalid lambda")?;
+        check_cells(n, t, max_cells)?;
+
+        let mut targets = vec![vec![vec![]; t]; n];
+        for (floor, floor_targets) in targets.iter_mut().enumerate() {
+            for (turn, turn_targets) in floor_targets.iter_mut().enumerate() {
+                let count: usize = next("count")?
+                    .parse()
+                    .with_context(|| format!("Invalid count at floor {} turn {}", floor, turn))?;
+                for _ in 0..count {
+                    turn_targets.push(next("target")?.parse::<usize>().with_context(|| {
+                        format!("Invalid target at floor {} turn {}", floor, turn)
+                    })?);
+                }
+            }
+        }
+
+        let params = SimParams {
+            n,
+            m,
+            c,
+            t,
+            lambda,
+            ..SimParams::default()
+        };
+        Self::from_targets(params, targets)
+    }
+
+    fn parse_v2(text: &str, max_cells: Option<usize>) -> Result<Self> {
+        let input: InputV2 = serde_json::from_str(text).context("Invalid v2 input")?;
+        if input.version != INPUT_VERSION {
+            bail!("Unsupported input version: {}", input.version);
+        }
+        let (n, t) = (input.params.n, input.params.t);
+        check_cells(n, t, max_cells)?;
+        let mut targets = vec![vec![vec![]; t]; n];
+        if !input.params.floor_labels.is_empty() && input.params.floor_labels.len() != n {
+            bail!(
+                "Floor labels need one name per floor ({} given, {} floors)",
+                input.params.floor_labels.len(),
+                n
+            );
+        }
+        // (floor, index at turn 0, turns waited before turn 0)
+        let mut initial = vec![];
+        for a in input.arrivals {
+            let floor = a.floor.resolve(&input.params)?;
+            let target = a.target.resolve(&input.params)?;
+            if floor >= n || target >= n || a.turn >= t as i64 {
+                bail!("Arrival at floor {} turn {} is out of range", floor, a.turn);
+            }
+            let turn = a.turn.max(0) as usize;
+            if a.turn < 0 {
+                initial.push((
+                    floor,
+                    targets[floor][0].len(),
+                    a.turn.unsigned_abs() as usize,
+                ));
+            }
+            targets[floor][turn].push(target);
+        }
+        let mut instance = Self::from_targets(input.params, targets)?;
+        for (floor, idx, waited) in initial {
+            instance.passengers[floor][0][idx].waited_before = waited;
+        }
+        Ok(instance)
+    }
+
+    /// Builds an instance from an arrival process, asking it floor by floor. Fails if the
+    /// process sends a passenger off the building or, unless `params.same_floor` allows
+    /// it, to their own floor.
+    pub fn from_process(params: &SimParams, process: &mut dyn ArrivalProcess) -> Result<Self> {
+        let targets = (0..params.n)
+            .map(|floor| {
+                (0..params.t)
+                    .map(|turn| process.sample(floor, turn))
+                    .collect()
+            })
+            .collect();
+        Self::from_targets(params.clone(), targets)
+    }
+
+    /// Builds the passenger table from targets, assigning IDs as the generator does.
+    /// Fails on targets the parsers would reject.
+    pub fn from_targets(params: SimParams, targets: Vec<Vec<Vec<usize>>>) -> Result<Self> {
+        check_targets(&params, &targets)?;
+        let mut next_passenger_id = 0;
+        let passengers = targets
+            .into_iter()
+            .enumerate()
+            .map(|(floor, floor_targets)| {
+                floor_targets
+                    .into_iter()
+                    .enumerate()
+                    .map(|(turn, turn_targets)| {
+                        turn_targets
+                            .into_iter()
+                            .map(|target| {
+                                next_passenger_id += 1;
+                                let (target_floor, legs) = params.route(floor, target);
+                                Passenger {
+                                    id: next_passenger_id - 1,
+                                    arrival_turn: turn,
+                                    target_floor,
+                                    legs,
+                                    waited_before: 0,
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Self { params, passengers })
+    }
+
+    pub fn to_v1(&self) -> Result<String> {
+        let p = &self.params;
+        let base = SimParams {
+            n: p.n,
+            m: p.m,
+            c: p.c,
+            t: p.t,
+            lambda: p.lambda,
+            ..SimParams::default()
+        };
+        let waiting_before = self
+            .passengers
+            .iter()
+            .flatten()
+            .flatten()
+            .any(|p| p.waited_before > 0);
+        if *p != base || waiting_before {
+            bail!("The instance uses options that the v1 input format cannot represent");
+        }
+
+        let mut text = format!("{} {} {} {} {}\n", p.n, p.m, p.c, p.t, p.lambda);
+        for floor in &self.passengers {
+            let line: Vec<String> = floor
+                .iter()
+                .map(|ps| {
+                    std::iter::once(ps.len().to_string())
+                        .chain(ps.iter().map(|p| p.destination().to_string()))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect();
+            text.push_str(&line.join(" "));
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    pub fn to_v2(&self) -> Result<String> {
+        let arrivals = self
+            .passengers
+            .iter()
+            .enumerate()
+            .flat_map(|(floor, turns)| {
+                turns.iter().flatten().map(move |p| ArrivalV2 {
+                    turn: p.arrival_turn as i64 - p.waited_before as i64,
+                    floor: FloorRef::new(&self.params, floor),
+                    target: FloorRef::new(&self.params, p.destination()),
+                })
+            })
+            .collect();
+        let input = InputV2 {
+            version: INPUT_VERSION,
+            params: self.params.clone(),
+            arrivals,
+        };
+        Ok(serde_json::to_string_pretty(&input)? + "\n")
+    }
+}
+
+pub fn convert_input(text: &str, to: InputVersion) -> Result<String> {
+    let instance = Instance::parse(text)?;
+    match to {
+        InputVersion::V1 => instance.to_v1(),
+        InputVersion::V2 => instance.to_v2(),
+    }
+}
+
+fn check_cells(n: usize, t: usize, max_cells: Option<usize>) -> Result<()> {
+    if let Some(max) = max_cells
+        && n.checked_mul(t).is_none_or(|cells| cells > max)
+    {
+        bail!(
+            "N x T is too large (N={}, T={}, at most {} allowed)",
+            n,
+            t,
+            max
+        );
+    }
+    Ok(())
+}
+
+/// Rejects targets off the building and, unless `params.same_floor` delivers them,
+/// passengers whose target is the floor they arrive at.
+fn check_targets(params: &SimParams, targets: &[Vec<Vec<usize>>]) -> Result<()> {
+    for (floor, turns) in targets.iter().enumerate() {
+        for (turn, ts) in turns.iter().enumerate() {
+            for &target in ts {
+                if target >= params.n {
+                    bail!(
+                        "Target {} at floor {} turn {} is out of range",
+                        target,
+                        floor,
+                        turn
+                    );
+                }
+                if target == floor && params.same_floor == SameFloorPolicy::Reject {
+                    bail!(
+                        "Passenger at floor {} turn {} targets their own floor",
+                        floor,
+                        turn
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let params = SimParams::default();
+        let instance = Instance {
+            passengers: generate_passengers(3, &params)?,
+            params,
+        };
+        let v1 = instance.to_v1()?;
+        assert_eq!(Instance::parse(&v1)?, instance);
+        let v2 = convert_input(&v1, InputVersion::V2)?;
+        assert_eq!(Instance::parse(&v2)?, instance);
+        assert_eq!(convert_input(&v2, InputVersion::V1)?, v1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_v1_rejects_extended_params() {
+        let instance = Instance {
+            params: SimParams {
+                travel_time: 2,
+                ..SimParams::default()
+            },
+            passengers: vec![vec![vec![]; 100]; 10],
+        };
+        assert!(instance.to_v1().is_err());
+        assert!(instance.to_v2().is_ok());
+    }
+
+    #[test]
+    fn test_floor_labels() -> Result<()> {
+        let text = r#"{
+            "version": 2,
+            "params": {"n": 3, "m": 1, "t": 2, "floor_labels": ["B1", "L", "2"]},
+            "arrivals": [{"turn": 0, "floor": "B1", "target": "2"}, {"turn": 1, "floor": 1, "target": 0}]
+        }"#;
+        let instance = Instance::parse(text)?;
+        assert_eq!(instance.passengers[0][0][0].target_floor, 2);
+        assert_eq!(instance.passengers[1][1][0].target_floor, 0);
+        assert!(instance.to_v2()?.contains(r#""floor": "L""#));
+        assert_eq!(Instance::parse(&instance.to_v2()?)?, instance);
+        assert!(Instance::parse(&text.replace(r#""2"}"#, r#""3"}"#)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_floor_targets() -> Result<()> {
+        assert!(Instance::parse("2 1 5 1 0.1\n1 0\n0\n").is_err());
+        assert!(Instance::parse("2 1 5 1 0.1\n1 2\n0\n").is_err());
+        assert!(Instance::parse("2 1 5 1 0.1\n1 1\n0\n").is_ok());
+        assert!(Instance::parse_capped("2 1 5 1 0.1\n1 1\n0\n", Some(2)).is_ok());
+        let huge = "2 1 1 1000000000000000000 0.1";
+        assert!(Instance::parse_capped(huge, Some(1 << 20)).is_err());
+
+        let text = r#"{
+            "version": 2,
+            "params": {"n": 2, "m": 1, "t": 1, "same_floor": "deliver"},
+            "arrivals": [{"turn": 0, "floor": 1, "target": 1}]
+        }"#;
+        assert_eq!(Instance::parse(text)?.replay("STAY\n", false).score, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_initial_waiting() -> Result<()> {
+        let text = r#"{
+            "version": 2,
+            "params": {"n": 3, "m": 1, "t": 4},
+            "arrivals": [{"turn": 1, "floor": 0, "target": 2}, {"turn": -5, "floor": 0, "target": 1}]
+        }"#;
+        let instance = Instance::parse(text)?;
+        let p = &instance.passengers[0][0][0];
+        assert_eq!((p.arrival_turn, p.waited_before, p.waited(2)), (0, 5, 7));
+        assert_eq!(Instance::parse(&instance.to_v2()?)?, instance);
+        assert!(instance.to_v1().is_err());
+        // Never picked up: (4 + 5)^2 and (4 - 1)^2
+        assert_eq!(instance.replay("", false).score, 81 + 9);
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{Instance, Passenger, SimParams};
+use anyhow::Result;
+use rand::distr::{Distribution, Uniform};
+use rand::{RngExt, SeedableRng};
+use rand_distr::Poisson;
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+
+/// A source of passenger arrivals, pluggable into [`generate_with`] and
+/// [`Instance::from_process`] in place of the default [`PoissonUniform`] traffic.
+pub trait ArrivalProcess {
+    /// Targets of the passengers arriving at `floor` on `turn`. Each floor is asked
+    /// about its turns in increasing order.
+    fn sample(&mut self, floor: usize, turn: usize) -> Vec<usize>;
+}
+
+/// The default traffic: Poisson arrivals with rate `lambda` on every floor, each
+/// heading to a uniformly random other floor.
+///
+/// Every floor draws from its own RNG streams derived from the seed, one for arrival
+/// counts and one for targets, so floors can be generated independently and the
+/// arrival counts of a floor do not depend on how many floors there are.
+pub struct PoissonUniform {
+    arrivals: Poisson<f64>,
+    targets: Uniform<usize>,
+    // (counts, targets) streams per floor
+    streams: Vec<(Pcg64, Pcg64)>,
+}
+
+impl PoissonUniform {
+    pub fn new(seed: u64, params: &SimParams) -> Result<Self> {
+        let state: u128 = Pcg64::seed_from_u64(seed).random();
+        Ok(Self {
+            arrivals: Poisson::new(params.lambda)?,
+            targets: Uniform::new(0, params.n)?,
+            streams: (0..params.n as u128)
+                .map(|floor| {
+                    (
+                        Pcg64::new(state, 2 * floor),
+                        Pcg64::new(state, 2 * floor + 1),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+impl ArrivalProcess for PoissonUniform {
+    fn sample(&mut self, floor: usize, _turn: usize) -> Vec<usize> {
+        if self.streams.len() < 2 {
+            return vec![]; // One floor: nowhere to go
+        }
+        let (counts, targets) = &mut self.streams[floor];
+        let count = self.arrivals.sample(counts) as u32;
+        (0..count)
+            .map(|_| {
+                loop {
+                    let target = self.targets.sample(targets);
+                    if target != floor {
+                        break target;
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Generates the passenger table `[floor][turn]` for a seed with [`PoissonUniform`].
+///
+/// Passenger IDs are assigned sequentially in floor-major order, then by turn, then by
+/// arrival order within the turn; see [`PassengerIds`] for computing them from counts.
+pub fn generate_passengers(seed: u64, params: &SimParams) -> Result<Vec<Vec<Vec<Passenger>>>> {
+    generate_with(&mut PoissonUniform::new(seed, params)?, params)
+}
+
+/// Generates the passenger table `[floor][turn]` from any arrival process.
+pub fn generate_with(
+    process: &mut dyn ArrivalProcess,
+    params: &SimParams,
+) -> Result<Vec<Vec<Vec<Passenger>>>> {
+    Ok(Instance::from_process(params, process)?.passengers)
+}
+
+/// Draws arrivals one turn at a time, for runs whose future must stay unknown. Each
+/// floor sees the same traffic as in the seed's generated table, but IDs go by order
+/// of arrival (turn, then floor), since floor-major IDs depend on arrivals to come.
+pub struct OnlineArrivals {
+    process: PoissonUniform,
+    params: SimParams,
+    turn: usize,
+    next_id: usize,
+}
+
+impl OnlineArrivals {
+    pub fn new(seed: u64, params: &SimParams) -> Result<Self> {
+        Ok(Self {
+            process: PoissonUniform::new(seed, params)?,
+            params: params.clone(),
+            turn: 0,
+            next_id: 0,
+        })
+    }
+
+    /// The next turn's arrivals as `(floor, passenger)`; none past turn `T`.
+    pub fn next_turn(&mut self) -> Vec<(usize, Passenger)> {
+        let turn = self.turn;
+        self.turn += 1;
+        if turn >= self.params.t {
+            return vec![];
+        }
+        let mut arrivals = vec![];
+        for floor in 0..self.params.n {
+            for target in self.process.sample(floor, turn) {
+                let (target_floor, legs) = self.params.route(floor, target);
+                let passenger = Passenger {
+                    id: self.next_id,
+                    arrival_turn: turn,
+                    target_floor,
+                    legs,
+                    waited_before: 0,
+                };
+                self.next_id += 1;
+                arrivals.push((floor, passenger));
+            }
+        }
+        arrivals
+    }
+}
+
+/// One passenger arrival, as listed by [`spawn_schedule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spawn {
+    pub turn: usize,
+    pub floor: usize,
+    /// Final destination (past any transfer floor).
+    pub target: usize,
+    pub id: usize,
+}
+
+/// The arrivals of a seed as a flat list ordered by turn, then floor, then ID.
+pub fn spawn_schedule(seed: u64, params: &SimParams) -> Result<Vec<Spawn>> {
+    let mut schedule: Vec<Spawn> = generate_passengers(seed, params)?
+        .iter()
+        .enumerate()
+        .flat_map(|(floor, turns)| {
+            turns.iter().flatten().map(move |p| Spawn {
+                turn: p.arrival_turn,
+                floor,
+                target: p.destination(),
+                id: p.id,
+            })
+        })
+        .collect();
+    schedule.sort_by_key(|s| (s.turn, s.floor, s.id));
+    Ok(schedule)
+}
+
+/// The mapping between `(floor, turn, k)` (the k-th passenger arriving at `floor` on
+/// `turn`) and passenger IDs. It only depends on the arrival counts, which are part of
+/// every input file, so solvers can compute IDs without replaying the generator.
+pub struct PassengerIds {
+    // First ID of each (floor, turn), flattened floor-major
+    offsets: Vec<usize>,
+    t: usize,
+    total: usize,
+}
+
+impl PassengerIds {
+    /// `counts[floor][turn]` is the number of arrivals at that floor and turn.
+    pub fn from_counts(counts: &[Vec<usize>]) -> Self {
+        let t = counts.first().map_or(0, |c| c.len());
+        let mut offsets = Vec::with_capacity(counts.len() * t);
+        let mut total = 0;
+        for floor_counts in counts {
+            for &count in floor_counts {
+                offsets.push(total);
+                total += count;
+            }
+        }
+        Self { offsets, t, total }
+    }
+
+    pub fn from_source(passenger_source: &[Vec<Vec<Passenger>>]) -> Self {
+        let counts: Vec<Vec<usize>> = passenger_source
+            .iter()
+            .map(|f| f.iter().map(|ps| ps.len()).collect())
+            .collect();
+        Self::from_counts(&counts)
+    }
+
+    pub fn id(&self, floor: usize, turn: usize, k: usize) -> Option<usize> {
+        if turn >= self.t {
+            return None;
+        }
+        let idx = floor * self.t + turn;
+        let start = *self.offsets.get(idx)?;
+        let end = self.offsets.get(idx + 1).copied().unwrap_or(self.total);
+        (start + k < end).then_some(start + k)
+    }
+
+    /// Inverse of [`PassengerIds::id`]: returns `(floor, turn, k)`.
+    pub fn origin(&self, id: usize) -> Option<(usize, usize, usize)> {
+        if id >= self.total {
+            return None;
+        }
+        // Last (floor, turn) whose first ID is <= id; empty slots share offsets with the next one
+        let idx = self.offsets.partition_point(|&o| o <= id) - 1;
+        Some((idx / self.t, idx % self.t, id - self.offsets[idx]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passenger_ids_match_generator() -> Result<()> {
+        let params = SimParams::default();
+        let source = generate_passengers(42, &params)?;
+        let ids = PassengerIds::from_source(&source);
+        for (floor, turns) in source.iter().enumerate() {
+            for (turn, ps) in turns.iter().enumerate() {
+                for (k, p) in ps.iter().enumerate() {
+                    assert_eq!(ids.id(floor, turn, k), Some(p.id));
+                    assert_eq!(ids.origin(p.id), Some((floor, turn, k)));
+                }
+                assert_eq!(ids.id(floor, turn, ps.len()), None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Everyone on floor 0 heads to the top every `every` turns.
+    struct Pulses {
+        every: usize,
+        top: usize,
+    }
+
+    impl ArrivalProcess for Pulses {
+        fn sample(&mut self, floor: usize, turn: usize) -> Vec<usize> {
+            if floor == 0 && turn.is_multiple_of(self.every) {
+                vec![self.top; 3]
+            } else {
+                vec![]
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_arrival_process() -> Result<()> {
+        let params = SimParams::default();
+        let mut pulses = Pulses { every: 10, top: 9 };
+        let source = generate_with(&mut pulses, &params)?;
+        assert_eq!(source[0][20].len(), 3);
+        assert!(source[0][21].is_empty() && source[5][20].is_empty());
+        assert_eq!(source[0][20][0].id, 6);
+        assert_eq!(source[0][20][0].target_floor, 9);
+
+        // Targets the input parsers reject are rejected here too
+        for top in [0, params.n] {
+            let mut pulses = Pulses { every: 10, top };
+            assert!(generate_with(&mut pulses, &params).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_adding_floors_keeps_arrival_counts() -> Result<()> {
+        let counts = |n: usize| -> Result<Vec<Vec<usize>>> {
+            let params = SimParams {
+                n,
+                ..SimParams::default()
+            };
+            Ok(generate_passengers(5, &params)?
+                .iter()
+                .map(|f| f.iter().map(Vec::len).collect())
+                .collect())
+        };
+        let (fewer, more) = (counts(10)?, counts(14)?);
+        assert_eq!(fewer[..], more[..10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_online_arrivals_match_generator() -> Result<()> {
+        let params = SimParams::default();
+        let source = generate_passengers(7, &params)?;
+        let mut online = OnlineArrivals::new(7, &params)?;
+        let mut ids = vec![];
+        let mut drawn = vec![vec![vec![]; params.t]; params.n];
+        for turn in 0..params.t {
+            for (floor, p) in online.next_turn() {
+                assert_eq!(p.arrival_turn, turn);
+                drawn[floor][p.arrival_turn].push(p.target_floor);
+                ids.push(p.id);
+            }
+        }
+        let expected: Vec<Vec<Vec<usize>>> = source
+            .iter()
+            .map(|floor| {
+                floor
+                    .iter()
+                    .map(|group| group.iter().map(|p| p.target_floor).collect())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(drawn, expected);
+        assert!(ids.iter().enumerate().all(|(i, &id)| i == id));
+        assert!(online.next_turn().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_schedule_matches_generator() -> Result<()> {
+        let params = SimParams::default();
+        let source = generate_passengers(7, &params)?;
+        let schedule = spawn_schedule(7, &params)?;
+        assert_eq!(schedule.len(), source.iter().flatten().flatten().count());
+        assert!(schedule.is_sorted_by_key(|s| (s.turn, s.floor, s.id)));
+        for s in &schedule {
+            let p = source[s.floor][s.turn]
+                .iter()
+                .find(|p| p.id == s.id)
+                .unwrap();
+            assert_eq!(p.target_floor, s.target);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+use crate::verify::hash_chain;
+use crate::{RunStats, Snapshot, fnv1a_hex};
+use anyhow::{Result, anyhow, bail};
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::{CompressionLevel, compress_to_vec};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::Read;
+use wasm_bindgen::prelude::*;
+
+/// Per-turn snapshots of a finished run together with its statistics.
+#[wasm_bindgen]
+pub struct History {
+    snapshots: Vec<Snapshot>,
+    stats: RunStats,
+}
+
+impl History {
+    pub fn new(snapshots: Vec<Snapshot>, capacity: usize) -> Self {
+        let stats = RunStats::from_history(&snapshots, capacity);
+        Self { snapshots, stats }
+    }
+
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    /// Packs the history for storage: every snapshot is stored as a JSON merge patch
+    /// (RFC 7386) against the previous one, along with the [`hash_chain`] of the
+    /// snapshots and a checksum tying the statistics to it, and the result is
+    /// zstd-compressed.
+    ///
+    /// The hashes are unkeyed, so they catch corruption, truncation and generator
+    /// drift, not deliberate edits: whoever edits a packed history can recompute them.
+    /// They are an integrity check, not a signature.
+    pub fn compress(&self) -> Result<Vec<u8>> {
+        let mut previous = Value::Null;
+        let mut patches = Vec::with_capacity(self.snapshots.len());
+        for snapshot in &self.snapshots {
+            let current = serde_json::to_value(snapshot)?;
+            patches.push(merge_patch(&previous, &current));
+            previous = current;
+        }
+        let chain = hash_chain(&self.snapshots);
+        let packed = serde_json::to_vec(&Packed {
+            checksum: checksum(&chain, &self.stats)?,
+            stats: self.stats.clone(),
+            patches,
+            chain,
+        })?;
+        Ok(compress_to_vec(&packed[..], CompressionLevel::Fastest))
+    }
+
+    /// Inverse of [`History::compress`]. Fails if the snapshots or statistics do not
+    /// match the stored hashes, so a corrupted replay is not shown as if it were real.
+    pub fn decompress(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = StreamingDecoder::new(bytes).map_err(|e| anyhow!("{:?}", e))?;
+        let mut json = vec![];
+        decoder.read_to_end(&mut json)?;
+        let packed: Packed = serde_json::from_slice(&json)?;
+        if packed.chain.is_empty() {
+            bail!("Replay has no hash chain");
+        }
+        let mut current = Value::Null;
+        let mut snapshots = Vec::with_capacity(packed.patches.len());
+        for patch in &packed.patches {
+            apply_patch(&mut current, patch);
+            snapshots.push(serde_json::from_value(current.clone())?);
+        }
+        let chain = hash_chain(&snapshots);
+        if chain.len() != packed.chain.len() {
+            bail!(
+                "Replay has {} turns but its hash chain has {}",
+                chain.len(),
+                packed.chain.len()
+            );
+        }
+        if let Some(turn) = (0..chain.len()).find(|&k| chain[k] != packed.chain[k]) {
+            bail!("Replay does not match its hash chain from turn {}", turn);
+        }
+        if checksum(&chain, &packed.stats)? != packed.checksum {
+            bail!("Replay statistics do not match their hash");
+        }
+        Ok(Self {
+            snapshots,
+            stats: packed.stats,
+        })
+    }
+
+    /// Size of the snapshots as plain JSON, to compare with the compressed size.
+    pub fn json_size(&self) -> Result<usize> {
+        Ok(serde_json::to_vec(&self.snapshots)?.len())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Packed {
+    stats: RunStats,
+    patches: Vec<Value>,
+    chain: Vec<String>,
+    /// Hash of the chain's last entry with the statistics
+    #[serde(alias = "seal")]
+    checksum: String,
+}
+
+fn checksum(chain: &[String], stats: &RunStats) -> Result<String> {
+    let mut data = chain.last().cloned().unwrap_or_default().into_bytes();
+    data.extend(serde_json::to_vec(stats)?);
+    Ok(fnv1a_hex(&data))
+}
+
+/// The merge patch turning `from` into `to`. Snapshots contain no nulls, so a null in
+/// a patch always means a removed key.
+fn merge_patch(from: &Value, to: &Value) -> Value {
+    match (from, to) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut patch = Map::new();
+            for (key, value) in b {
+                match a.get(key) {
+                    Some(old) if old == value => {}
+                    Some(old) => {
+                        patch.insert(key.clone(), merge_patch(old, value));
+                    }
+                    None => {
+                        patch.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in a.keys().filter(|k| !b.contains_key(*k)) {
+                patch.insert(key.clone(), Value::Null);
+            }
+            Value::Object(patch)
+        }
+        _ => to.clone(),
+    }
+}
+
+fn apply_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            apply_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Collects the snapshots of a run with bounded memory: only every `every`-th turn is
+/// kept and, with `last`, only the most recent `last` of those.
+#[derive(Default)]
+pub struct HistoryBuffer {
+    last: Option<usize>,
+    every: usize,
+    at: Option<BTreeSet<usize>>,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl HistoryBuffer {
+    pub fn new(last: Option<usize>, every: usize) -> Self {
+        Self {
+            last,
+            every: every.max(1),
+            at: None,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// Keeps only the snapshots of these turns (on top of the other limits).
+    pub fn at(mut self, turns: impl IntoIterator<Item = usize>) -> Self {
+        self.at = Some(turns.into_iter().collect());
+        self
+    }
+
+    /// Whether the snapshot of `turn` would be kept, so callers can skip building the
+    /// others.
+    pub fn wants(&self, turn: usize) -> bool {
+        turn.is_multiple_of(self.every)
+            && self.last != Some(0)
+            && self.at.as_ref().is_none_or(|at| at.contains(&turn))
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if !self.wants(snapshot.turn) {
+            return;
+        }
+        if self.last == Some(self.snapshots.len()) {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn into_vec(self) -> Vec<Snapshot> {
+        self.snapshots.into()
+    }
+}
+
+#[wasm_bindgen]
+impl History {
+    #[wasm_bindgen(js_name = snapshots)]
+    pub fn snapshots_js(&self) -> Result<JsValue, String> {
+        serde_wasm_bindgen::to_value(&self.snapshots).map_err(|e| e.to_string())
+    }
+
+    /// The history packed for browser storage; see [`History::compress`].
+    #[wasm_bindgen(js_name = compress)]
+    pub fn compress_js(&self) -> Result<Vec<u8>, String> {
+        self.compress().map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen(js_name = decompress)]
+    pub fn decompress_js(bytes: &[u8]) -> Result<History, String> {
+        Self::decompress(bytes).map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen(getter, js_name = json_size)]
+    pub fn json_size_js(&self) -> Result<usize, String> {
+        self.json_size().map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn turns(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn mean_wait(&self) -> f64 {
+        self.stats.mean_wait
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn p95_wait(&self) -> usize {
+        self.stats.p95_wait
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_wait(&self) -> usize {
+        self.stats.max_wait
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn delivered(&self) -> usize {
+        self.stats.delivered
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn utilization(&self) -> Vec<f64> {
+        self.stats.utilization.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn idle_turns(&self) -> Vec<usize> {
+        self.stats.idle_turns.clone()
+    }
+
+    #[wasm_bindgen]
+    pub fn heatmap(&self) -> Result<JsValue, String> {
+        serde_wasm_bindgen::to_value(&self.stats.heatmap).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimulationState;
+
+    #[test]
+    fn test_history_buffer_keeps_sampled_tail() -> Result<()> {
+        let mut sim = SimulationState::new(4, 1, 10, 20)?;
+        let mut buffer = HistoryBuffer::new(Some(3), 2);
+        for turn in 0..20 {
+            sim.turn = turn;
+            buffer.push(sim.create_snapshot());
+        }
+        let turns: Vec<usize> = buffer.into_vec().iter().map(|s| s.turn).collect();
+        assert_eq!(turns, vec![14, 16, 18]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_buffer_at_turns() -> Result<()> {
+        let mut sim = SimulationState::new(4, 1, 10, 20)?;
+        let mut buffer = HistoryBuffer::new(None, 1).at([10, 5, 19, 30]);
+        assert!(buffer.wants(5) && !buffer.wants(6));
+        for turn in 0..20 {
+            sim.turn = turn;
+            buffer.push(sim.create_snapshot());
+        }
+        let turns: Vec<usize> = buffer.into_vec().iter().map(|s| s.turn).collect();
+        assert_eq!(turns, vec![5, 10, 19]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_round_trip() -> Result<()> {
+        let instance = crate::Instance::generate(3, &crate::SimParams::default())?;
+        let report = instance.replay(&"UP\nSTAY\nOPEN\n".repeat(100), false);
+        let history = History::new(report.history, 10);
+        let bytes = history.compress()?;
+        assert!(bytes.len() * 5 < history.json_size()?);
+
+        let restored = History::decompress(&bytes)?;
+        assert_eq!(restored.stats(), history.stats());
+        assert_eq!(
+            serde_json::to_string(restored.snapshots())?,
+            serde_json::to_string(history.snapshots())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_replay() -> Result<()> {
+        let instance = crate::Instance::generate(3, &crate::SimParams::default())?;
+        let report = instance.replay(&"OPEN\nUP\n".repeat(50), false);
+        let history = History::new(report.history, 10);
+        let mut json = vec![];
+        StreamingDecoder::new(&history.compress()?[..])
+            .map_err(|e| anyhow!("{:?}", e))?
+            .read_to_end(&mut json)?;
+        let packed: Packed = serde_json::from_slice(&json)?;
+        let repack = |packed: &Packed| -> Result<Vec<u8>> {
+            let json = serde_json::to_vec(packed)?;
+            Ok(compress_to_vec(&json[..], CompressionLevel::Fastest))
+        };
+
+        let mut corrupted = serde_json::from_slice::<Packed>(&json)?;
+        corrupted.patches[7]["score"] = 123456.into();
+        let err = History::decompress(&repack(&corrupted)?).err().unwrap();
+        assert!(err.to_string().contains("from turn 7"), "{}", err);
+
+        corrupted.chain.clear();
+        assert!(History::decompress(&repack(&corrupted)?).is_err());
+        let mut missing = serde_json::from_slice::<Value>(&json)?;
+        missing.as_object_mut().unwrap().remove("chain");
+        let bytes = compress_to_vec(
+            &serde_json::to_vec(&missing)?[..],
+            CompressionLevel::Fastest,
+        );
+        assert!(History::decompress(&bytes).is_err());
+
+        let mut stats = serde_json::from_slice::<Packed>(&json)?;
+        stats.stats.delivered += 1;
+        let err = History::decompress(&repack(&stats)?).err().unwrap();
+        assert!(err.to_string().contains("statistics"), "{}", err);
+        assert!(History::decompress(&repack(&packed)?).is_ok());
+        Ok(())
+    }
+}
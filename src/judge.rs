@@ -0,0 +1,1379 @@
+use crate::spec::PROTOCOL_VERSION;
+use crate::{
+    Action, Agent, Capability, Direction, DoorState, ElevatorSnapshot, Event, FloorSnapshot,
+    Instance, OnlineArrivals, Passenger, Pickup, ProtocolHeader, ScoreComponents, SimParams,
+    SimulationState, Snapshot, annotation,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+/// How to launch an agent process.
+#[derive(Debug, Clone, Default)]
+pub struct AgentCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// Working directory of the agent; the judge's own when `None`
+    pub dir: Option<PathBuf>,
+}
+
+impl AgentCommand {
+    pub fn new(program: &str, args: &[String]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.to_vec(),
+            env: vec![],
+            dir: None,
+        }
+    }
+
+    /// Parses a whole command line, e.g. `python3 my_agent.py --depth 3`, with the
+    /// quoting rules of the platform's shell; see [`split_command_line`].
+    pub fn parse(line: &str) -> Result<Self> {
+        let words = split_command_line(line, cfg!(windows))?;
+        let Some((program, args)) = words.split_first() else {
+            bail!("Empty agent command");
+        };
+        Ok(Self::new(program, args))
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Runs the agent in `dir`. A relative program path still refers to the judge's
+    /// working directory.
+    pub fn dir(mut self, dir: &Path) -> Result<Self> {
+        if self.program.contains(std::path::MAIN_SEPARATOR) {
+            self.program = std::path::absolute(&self.program)?
+                .to_string_lossy()
+                .into_owned();
+        }
+        self.dir = Some(dir.to_path_buf());
+        Ok(self)
+    }
+}
+
+/// Splits a command line into words. By default it follows POSIX shells: single quotes
+/// keep everything literally, double quotes keep all but `\"`, `\\`, `\$` and
+/// `` \` ``, and a backslash outside quotes escapes any character. With `windows`,
+/// only double quotes group and backslashes are literal unless they precede a quote,
+/// as for `CommandLineToArgvW`.
+pub fn split_command_line(line: &str, windows: bool) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, '\\') if windows => {
+                let mut backslashes = 1;
+                while chars.next_if_eq(&'\\').is_some() {
+                    backslashes += 1;
+                }
+                let word = word.get_or_insert_default();
+                if chars.peek() == Some(&'"') {
+                    word.extend(std::iter::repeat_n('\\', backslashes / 2));
+                    if backslashes % 2 == 1 {
+                        word.push('"');
+                        chars.next();
+                    }
+                } else {
+                    word.extend(std::iter::repeat_n('\\', backslashes));
+                }
+            }
+            (None, '\'') if !windows => {
+                quote = Some('\'');
+                word.get_or_insert_default();
+            }
+            (None, '"') => {
+                quote = Some('"');
+                word.get_or_insert_default();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (None, '\\') => match chars.next() {
+                Some(next) => word.get_or_insert_default().push(next),
+                None => bail!("Trailing backslash in {:?}", line),
+            },
+            (Some('"'), '\\') if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                word.get_or_insert_default().extend(chars.next());
+            }
+            (_, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        bail!("Unterminated {} quote in {:?}", q, line);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[derive(Debug, Clone)]
+pub struct JudgeOptions {
+    /// Accept lower-case and abbreviated actions
+    pub lenient: bool,
+    /// Protocol version offered to the agent. Above 1, the judge first sends
+    /// `PROTOCOL <version>` and the agent answers `PROTOCOL <v>` with the version it
+    /// speaks, at most the offered one.
+    pub protocol: u32,
+    /// Total time the agent may spend answering, summed over all turns
+    pub time_limit: Option<Duration>,
+    /// Time the agent may take to answer a single turn, from the observation being
+    /// sent. An agent still answering when it runs out is killed.
+    pub turn_time_limit: Option<Duration>,
+    /// Most bytes the agent may write in one turn
+    pub max_turn_bytes: Option<usize>,
+    /// Most bytes the agent may write over the whole episode
+    pub max_total_bytes: Option<usize>,
+    /// Wall-clock instant after which the episode stops at the next turn, with every
+    /// passenger not yet delivered scored as unserved
+    pub deadline: Option<Instant>,
+    /// Interactive mode: arrivals are drawn turn by turn from this seed, ignoring the
+    /// instance's passengers, and each turn sends the new arrivals in place of the
+    /// waiting lines; see [`Observation::write_arrivals`]. Not available with an
+    /// observation or detection delay.
+    pub online_seed: Option<u64>,
+}
+
+/// Outcome of one agent episode.
+#[derive(Debug, Clone, Default)]
+pub struct Verdict {
+    pub score: u64,
+    /// `score` by component, before `SimParams::weights`
+    pub components: ScoreComponents,
+    /// Bytes the agent wrote after the handshake, and the most in a single turn
+    pub output_bytes: usize,
+    pub peak_turn_bytes: usize,
+    pub time_used: Duration,
+    /// Turn at which the deadline stopped the episode, making the score partial
+    pub stopped_at: Option<usize>,
+    pub profile: Profile,
+    /// How long the agent took to answer each turn, from the observation being sent
+    pub turn_times: Vec<Duration>,
+    /// CPU time of the agent process and the children it waited for, where the OS
+    /// reports it (Linux)
+    pub agent_cpu: Option<Duration>,
+}
+
+/// Where the judge's side of an episode spent its wall-clock time, in coarse phases.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Profile {
+    /// Writing observations and parsing the agent's actions
+    pub protocol: Duration,
+    /// Waiting for the agent's answers
+    pub agent: Duration,
+    /// Applying each turn's actions
+    pub apply: Duration,
+    /// Taking each turn's snapshot and handing it to `on_turn`, which records or
+    /// streams it
+    pub snapshot: Duration,
+}
+
+impl Default for JudgeOptions {
+    fn default() -> Self {
+        Self {
+            lenient: false,
+            protocol: 1,
+            time_limit: None,
+            turn_time_limit: None,
+            max_turn_bytes: None,
+            max_total_bytes: None,
+            deadline: None,
+            online_seed: None,
+        }
+    }
+}
+
+/// Summary of an agent's per-turn answer times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub turns: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    /// The 99th percentile, by nearest rank
+    pub p99: Duration,
+}
+
+impl Latency {
+    /// `None` for an episode without a turn.
+    pub fn from_turns(turn_times: &[Duration]) -> Option<Self> {
+        let mut sorted = turn_times.to_vec();
+        sorted.sort_unstable();
+        let turns = sorted.len();
+        let total: Duration = sorted.iter().sum();
+        Some(Self {
+            turns,
+            min: *sorted.first()?,
+            mean: total / turns as u32,
+            max: sorted[turns - 1],
+            p99: sorted[(turns * 99).div_ceil(100) - 1],
+        })
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        write!(
+            f,
+            "min {:.3} ms, mean {:.3} ms, max {:.3} ms, p99 {:.3} ms over {} turns",
+            ms(self.min),
+            ms(self.mean),
+            ms(self.max),
+            ms(self.p99),
+            self.turns
+        )
+    }
+}
+
+/// User and system CPU time of process `pid` and its waited-for children, from
+/// `/proc/<pid>/stat`.
+fn cpu_time(pid: u32) -> Option<Duration> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may hold spaces, so fields are counted after its `)`; utime,
+    // stime, cutime and cstime are fields 14 to 17, in ticks of 1/100 s
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks = fields
+        .get(11..15)?
+        .iter()
+        .map(|field| field.parse::<u64>().ok())
+        .sum::<Option<u64>>()?;
+    Some(Duration::from_millis(ticks * 10))
+}
+
+/// Everything the agent is sent at the start of a turn.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Observation {
+    pub turn: usize,
+    /// (no-shows, early exits) per elevator in the previous turn
+    pub noise: Vec<(usize, usize)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_left: Option<u64>,
+    /// (floor, index in that floor's waiting list, new target) of this turn's
+    /// destination changes that the agent can see
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<(usize, usize, usize)>,
+    /// How each elevator's `OPEN` in the previous turn went
+    pub pickups: Vec<Pickup>,
+    /// The state as the agent sees it, which lags behind under an observation delay
+    pub state: Snapshot,
+}
+
+impl Observation {
+    /// Takes this turn's observation; `previous` holds the events of the previous turn.
+    pub fn new(state: &mut SimulationState, previous: &[Event]) -> Self {
+        let mut noise = vec![(0, 0); state.m];
+        for event in previous {
+            match *event {
+                Event::NoShow { elevator, .. } => noise[elevator].0 += 1,
+                Event::ExitedEarly { elevator, .. } => noise[elevator].1 += 1,
+                _ => {}
+            }
+        }
+        let observed = state.observe();
+        let changes = state
+            .current_events()
+            .iter()
+            .filter_map(|event| match *event {
+                Event::TargetChanged {
+                    passenger_id,
+                    floor,
+                    target,
+                } => {
+                    let waiting = &observed.floors[floor].waiting;
+                    let idx = waiting.iter().position(|p| p.id == passenger_id)?;
+                    Some((floor, idx, target))
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            turn: state.turn,
+            noise,
+            budget_left: state.budget_left(),
+            changes,
+            pickups: state.previous_pickups(),
+            state: observed,
+        }
+    }
+
+    /// Reads one turn's lines as protocol `protocol` sends them (see
+    /// [`Observation::write`]), returning the observation and the time left. The text
+    /// has no passenger IDs, labels, elevator directions or score, so those are left
+    /// empty; `turn` turns the waited turns back into arrival turns.
+    pub fn read(
+        input: &mut dyn BufRead,
+        header: &ProtocolHeader,
+        protocol: u32,
+        turn: usize,
+    ) -> Result<(Self, Option<Duration>)> {
+        let (n, m) = (header.n, header.m);
+        let mut next = || -> Result<Vec<i64>> {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                bail!("Observation for turn {} ends early", turn);
+            }
+            line.split_whitespace()
+                .map(|x| x.parse().with_context(|| format!("Invalid number: {}", x)))
+                .collect()
+        };
+        let optional = |values: Vec<i64>| -> Result<Option<u64>> {
+            match values[..] {
+                [v] => Ok(u64::try_from(v).ok()),
+                _ => bail!("Expected one number, got {}", values.len()),
+            }
+        };
+
+        let time_left = match protocol {
+            2.. => optional(next()?)?.map(Duration::from_millis),
+            _ => None,
+        };
+        let mut noise = vec![(0, 0); m];
+        if protocol >= 6 {
+            let counts = unsigned(&next()?)?;
+            if counts.len() != 2 * m {
+                bail!("Expected {} noise counts, got {}", 2 * m, counts.len());
+            }
+            for (i, pair) in counts.chunks(2).enumerate() {
+                noise[i] = (pair[0], pair[1]);
+            }
+        }
+        let budget_left = match protocol {
+            7.. => optional(next()?)?,
+            _ => None,
+        };
+        let mut changes = vec![];
+        if protocol >= 10 {
+            let values = unsigned(&next()?)?;
+            let (count, rest) = values.split_first().context("Missing change count")?;
+            if rest.len() != 3 * count {
+                bail!("Expected {} destination changes", count);
+            }
+            changes = rest.chunks(3).map(|c| (c[0], c[1], c[2])).collect();
+        }
+        let mut pickups = vec![];
+        if protocol >= 11 {
+            for _ in 0..m {
+                let values = unsigned(&next()?)?;
+                let (boarded, rest) = counted(&values)?;
+                let (skipped, rest) = counted(rest)?;
+                if !rest.is_empty() {
+                    bail!("Unexpected values after a pickup line");
+                }
+                pickups.push(Pickup {
+                    turn: turn.saturating_sub(1),
+                    boarded: boarded.to_vec(),
+                    skipped: skipped.to_vec(),
+                });
+            }
+        }
+
+        let floors = unsigned(&next()?)?;
+        if floors.len() != m {
+            bail!("Expected {} elevator floors, got {}", m, floors.len());
+        }
+        let mut elevators = Vec::with_capacity(m);
+        for floor in floors {
+            let passengers = read_group(&unsigned(&next()?)?, turn)?;
+            elevators.push(ElevatorSnapshot {
+                label: None,
+                floor,
+                position: floor as f64,
+                direction: Direction::Idle,
+                door: DoorState::Closed,
+                passenger_count: passengers.len(),
+                passengers,
+            });
+        }
+        let mut waiting = vec![vec![]; n];
+        if protocol >= 8 {
+            let lines = optional(next()?)?.context("Invalid floor count")?;
+            for _ in 0..lines {
+                let values = unsigned(&next()?)?;
+                let (&floor, rest) = values.split_first().context("Missing floor")?;
+                *waiting.get_mut(floor).context("Floor out of range")? = read_group(rest, turn)?;
+            }
+        } else {
+            for floor in &mut waiting {
+                *floor = read_group(&unsigned(&next()?)?, turn)?;
+            }
+        }
+        let floors = waiting
+            .into_iter()
+            .enumerate()
+            .map(|(floor, waiting)| {
+                let up_calls = waiting.iter().filter(|p| p.target_floor > floor).count();
+                FloorSnapshot {
+                    label: None,
+                    waiting_count: waiting.len(),
+                    up_calls,
+                    down_calls: waiting.len() - up_calls,
+                    waiting,
+                }
+            })
+            .collect();
+
+        let state = Snapshot {
+            turn,
+            score: 0,
+            elevators,
+            floors,
+            arrivals: vec![],
+            events: vec![],
+            budget_left,
+            statistics: None,
+            annotations: vec![],
+        };
+        let observation = Self {
+            turn,
+            noise,
+            budget_left,
+            changes,
+            pickups,
+            state,
+        };
+        Ok((observation, time_left))
+    }
+
+    /// [`Observation::read`] from a string holding one turn's lines.
+    pub fn parse(
+        text: &str,
+        header: &ProtocolHeader,
+        protocol: u32,
+        turn: usize,
+    ) -> Result<(Self, Option<Duration>)> {
+        Self::read(&mut text.as_bytes(), header, protocol, turn)
+    }
+
+    /// Writes the turn's lines as protocol `protocol` sends them. Without `time_left`
+    /// the time line reads `-1`.
+    pub fn write(
+        &self,
+        out: &mut dyn Write,
+        protocol: u32,
+        time_left: Option<Duration>,
+    ) -> Result<()> {
+        self.write_turn_info(out, protocol, time_left)?;
+        write_observation(out, &self.state, protocol >= 8)
+    }
+
+    /// Like [`Observation::write`], but with this turn's new arrivals, `(floor,
+    /// passenger)`, in place of the waiting lines: one line with their count, then the
+    /// floor and target of each.
+    pub fn write_arrivals(
+        &self,
+        out: &mut dyn Write,
+        protocol: u32,
+        time_left: Option<Duration>,
+        arrivals: &[(usize, Passenger)],
+    ) -> Result<()> {
+        self.write_turn_info(out, protocol, time_left)?;
+        write_elevators(out, &self.state)?;
+        write!(out, "{}", arrivals.len())?;
+        for (floor, p) in arrivals {
+            write!(out, " {} {}", floor, p.target_floor)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// The lines before the state: time left, noise, budget, destination changes and
+    /// pickups, as far as `protocol` has them.
+    fn write_turn_info(
+        &self,
+        out: &mut dyn Write,
+        protocol: u32,
+        time_left: Option<Duration>,
+    ) -> Result<()> {
+        if protocol >= 2 {
+            match time_left {
+                Some(left) => writeln!(out, "{}", left.as_millis())?,
+                None => writeln!(out, "-1")?,
+            }
+        }
+        if protocol >= 6 {
+            let counts: Vec<String> = self
+                .noise
+                .iter()
+                .map(|(no_shows, exits)| format!("{} {}", no_shows, exits))
+                .collect();
+            writeln!(out, "{}", counts.join(" "))?;
+        }
+        if protocol >= 7 {
+            match self.budget_left {
+                Some(left) => writeln!(out, "{}", left)?,
+                None => writeln!(out, "-1")?,
+            }
+        }
+        if protocol >= 10 {
+            write!(out, "{}", self.changes.len())?;
+            for (floor, idx, target) in &self.changes {
+                write!(out, " {} {} {}", floor, idx, target)?;
+            }
+            writeln!(out)?;
+        }
+        if protocol >= 11 {
+            for pickup in &self.pickups {
+                write!(out, "{}", pickup.boarded.len())?;
+                for i in &pickup.boarded {
+                    write!(out, " {}", i)?;
+                }
+                write!(out, " {}", pickup.skipped.len())?;
+                for i in &pickup.skipped {
+                    write!(out, " {}", i)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Offers `offered` to the agent and returns the version it accepted.
+fn handshake(stdin: &mut dyn Write, stdout: &mut dyn BufRead, offered: u32) -> Result<u32> {
+    writeln!(stdin, "PROTOCOL {}", offered)?;
+    stdin.flush()?;
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    let version = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["PROTOCOL", v] => v
+            .parse::<u32>()
+            .with_context(|| format!("Invalid protocol version: {}", v))?,
+        _ => bail!(
+            "Agent did not answer the protocol handshake: {:?}",
+            line.trim_end()
+        ),
+    };
+    if version == 0 || version > offered {
+        bail!("Agent chose unsupported protocol version {}", version);
+    }
+    Ok(version)
+}
+
+/// Writes what an agent learns before the first turn: the parameters line, then from
+/// protocol 3 each elevator's capability, from 4 its label and from 5 the floor labels.
+pub(crate) fn write_header(
+    out: &mut dyn Write,
+    state: &SimulationState,
+    protocol: u32,
+) -> Result<()> {
+    let params = &state.params;
+    let SimParams { n, m, c, t, .. } = *params;
+    writeln!(out, "{} {} {} {} {}", n, m, c, t, params.lambda)?;
+    if protocol >= 3 {
+        for i in 0..m {
+            let spec = state.elevator_spec(i);
+            let capability = Capability {
+                capacity: spec.capacity,
+                travel_time: spec.travel_time,
+                floors: (0..n).filter(|&f| spec.serves(f)).collect(),
+            };
+            writeln!(out, "{}", capability)?;
+        }
+    }
+    if protocol >= 4 {
+        for i in 0..m {
+            writeln!(out, "{}", params.elevator_label(i).unwrap_or(""))?;
+        }
+    }
+    if protocol >= 5 {
+        let labels: Vec<String> = (0..n).map(|f| params.floor_label(f)).collect();
+        writeln!(out, "{}", labels.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Writes the floors, the onboard passengers and the waiting passengers of an
+/// observation, each passenger as its target and the turns it has waited so far.
+/// `sparse` (protocol 8) sends only the floors with someone waiting, each line led by
+/// the floor, after a line with their count.
+fn write_observation(out: &mut dyn Write, observation: &Snapshot, sparse: bool) -> Result<()> {
+    write_elevators(out, observation)?;
+    if !sparse {
+        for f in &observation.floors {
+            write_group(out, observation.turn, &f.waiting)?;
+        }
+        return Ok(());
+    }
+    let waiting: Vec<(usize, &FloorSnapshot)> = observation
+        .floors
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !f.waiting.is_empty())
+        .collect();
+    writeln!(out, "{}", waiting.len())?;
+    for (floor, f) in waiting {
+        write!(out, "{} ", floor)?;
+        write_group(out, observation.turn, &f.waiting)?;
+    }
+    Ok(())
+}
+
+/// The elevators' floors, then each elevator's passengers.
+fn write_elevators(out: &mut dyn Write, observation: &Snapshot) -> Result<()> {
+    let floors: Vec<String> = observation
+        .elevators
+        .iter()
+        .map(|e| e.floor.to_string())
+        .collect();
+    writeln!(out, "{}", floors.join(" "))?;
+    for e in &observation.elevators {
+        write_group(out, observation.turn, &e.passengers)?;
+    }
+    Ok(())
+}
+
+fn unsigned(values: &[i64]) -> Result<Vec<usize>> {
+    values
+        .iter()
+        .map(|&v| usize::try_from(v).with_context(|| format!("Unexpected negative {}", v)))
+        .collect()
+}
+
+/// Splits a count and that many values off the front of `values`.
+fn counted(values: &[usize]) -> Result<(&[usize], &[usize])> {
+    let (&count, rest) = values.split_first().context("Missing count")?;
+    if rest.len() < count {
+        bail!("Expected {} values, got {}", count, rest.len());
+    }
+    Ok(rest.split_at(count))
+}
+
+/// Reads what [`write_group`] writes, as passengers without IDs.
+fn read_group(values: &[usize], turn: usize) -> Result<Vec<Passenger>> {
+    let (&count, rest) = values.split_first().context("Missing passenger count")?;
+    if rest.len() != 2 * count {
+        bail!("Expected {} passengers, got {} values", count, rest.len());
+    }
+    Ok(rest
+        .chunks(2)
+        .map(|p| Passenger {
+            id: 0,
+            arrival_turn: turn.saturating_sub(p[1]),
+            target_floor: p[0],
+            legs: vec![],
+            waited_before: p[1].saturating_sub(turn),
+        })
+        .collect())
+}
+
+/// A count, then each passenger's target and the turns waited by `turn`.
+fn write_group(out: &mut dyn Write, turn: usize, passengers: &[Passenger]) -> Result<()> {
+    write!(out, "{}", passengers.len())?;
+    for p in passengers {
+        write!(out, " {} {}", p.target_floor, p.waited(turn))?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Writes everything a judge offering `protocol` sends an agent that accepts it: the
+/// handshake offer, the header and `observations` in turn, without a time limit. With
+/// the observations of a replayed output, these are the exact bytes its agent read.
+pub fn write_protocol(
+    out: &mut dyn Write,
+    instance: &Instance,
+    observations: &[Observation],
+    protocol: u32,
+) -> Result<()> {
+    if protocol > PROTOCOL_VERSION {
+        bail!("Protocol version {} is not supported", protocol);
+    }
+    if protocol > 1 {
+        writeln!(out, "PROTOCOL {}", protocol)?;
+    }
+    write_header(
+        out,
+        &SimulationState::try_from_params(&instance.params)?,
+        protocol,
+    )?;
+    for observation in observations {
+        observation.write(out, protocol, None)?;
+    }
+    Ok(())
+}
+
+/// Checks that `actions` would apply cleanly, without changing `state`.
+pub fn validate_turn(state: &SimulationState, actions: &[Action]) -> Result<()> {
+    state.check_turn(actions)
+}
+
+/// Applies one action per elevator, in the simulation's action order: all of them, or
+/// none when any is invalid.
+pub fn apply_turn(state: &mut SimulationState, actions: &[Action]) -> Result<()> {
+    state.check_turn(actions)?;
+    for i in state.action_order() {
+        state
+            .apply_action(i, &actions[i])
+            .with_context(|| format!("Turn {}: Invalid action by elevator {}", state.turn, i))?;
+    }
+    Ok(())
+}
+
+/// Runs one episode of `instance` against an agent process speaking the stdin/stdout
+/// protocol and returns the final score with the agent's resource use. Every action line
+/// is copied to `log_writer` and the state after every turn is passed to `on_turn`.
+///
+/// The time limit is checked after each turn's answers have been read and the deadline
+/// before each turn starts, so an agent that hangs forever is only interrupted under
+/// `turn_time_limit`.
+#[allow(clippy::needless_range_loop)]
+pub fn run_agent(
+    instance: &Instance,
+    agent: &AgentCommand,
+    options: &JudgeOptions,
+    mut log_writer: Option<&mut dyn Write>,
+    on_turn: &mut dyn FnMut(Snapshot),
+) -> Result<Verdict> {
+    let SimParams { m, t, .. } = instance.params;
+    // Arrivals are sent the turn they spawn, so a delay could hide nothing
+    if options.online_seed.is_some()
+        && (instance.params.observation_delay > 0 || instance.params.detection_delay > 0)
+    {
+        bail!("Interactive mode cannot hide arrivals behind an observation or detection delay");
+    }
+    let mut online = options
+        .online_seed
+        .map(|seed| OnlineArrivals::new(seed, &instance.params))
+        .transpose()?;
+    let mut state = match online {
+        Some(_) => SimulationState::try_from_params(&instance.params)?,
+        None => SimulationState::from_instance(instance)?,
+    };
+
+    let mut command = Command::new(&agent.program);
+    command
+        .args(&agent.args)
+        .envs(agent.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    if let Some(ref dir) = agent.dir {
+        command.current_dir(dir);
+    }
+    let mut child = command.spawn().context("Failed to spawn agent process")?;
+
+    // Buffered, since an observation of a tall building is thousands of small writes
+    let mut stdin = BufWriter::new(child.stdin.take().context("Failed to open stdin")?);
+    let mut stdout = BufReader::new(child.stdout.take().context("Failed to open stdout")?);
+    let child = Arc::new(Mutex::new(child));
+    let watchdog = options
+        .turn_time_limit
+        .map(|limit| Watchdog::start(Arc::clone(&child), limit));
+    let turn_timed_out = |turn: usize, took: Duration| {
+        anyhow!(
+            "TLE at turn {} ({} ms, {} ms allowed per turn)",
+            turn,
+            took.as_millis(),
+            options.turn_time_limit.unwrap_or_default().as_millis()
+        )
+    };
+
+    if options.protocol > PROTOCOL_VERSION {
+        bail!("Protocol version {} is not supported", options.protocol);
+    }
+    let protocol = if options.protocol > 1 {
+        handshake(&mut stdin, &mut stdout, options.protocol)?
+    } else {
+        1
+    };
+    let mut used = Duration::ZERO;
+    let mut profile = Profile::default();
+    let mut turn_times = Vec::with_capacity(t);
+    let (mut output_bytes, mut peak_turn_bytes) = (0, 0);
+
+    write_header(&mut stdin, &state, protocol)?;
+    stdin.flush()?;
+
+    let mut previous = vec![];
+    let mut stopped_at = None;
+    for turn in 0..t {
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            state.arrive_all()?;
+            if let Some(ref mut online) = online {
+                for (floor, p) in (turn..t).flat_map(|_| online.next_turn()) {
+                    state.push_arrival(floor, p)?;
+                }
+            }
+            stopped_at = Some(turn);
+            break;
+        }
+        let arrivals = match online {
+            Some(ref mut online) => {
+                let arrivals = online.next_turn();
+                for (floor, p) in &arrivals {
+                    state.push_arrival(*floor, p.clone())?;
+                }
+                Some(arrivals)
+            }
+            None => None,
+        };
+
+        // Send state to agent
+        let started = Instant::now();
+        let time_left = options.time_limit.map(|limit| limit.saturating_sub(used));
+        let observation = Observation::new(&mut state, &previous);
+        match arrivals {
+            Some(ref arrivals) => {
+                observation.write_arrivals(&mut stdin, protocol, time_left, arrivals)?
+            }
+            None => observation.write(&mut stdin, protocol, time_left)?,
+        }
+        stdin.flush()?;
+        // Turn time limits count from here, so that the judge's own writing does not
+        // count against the agent
+        let sent = Instant::now();
+        if let Some(ref watchdog) = watchdog {
+            watchdog.arm();
+        }
+        profile.protocol += sent - started;
+
+        // Process agent actions, one line each or all on one line (protocol 9). `#`
+        // lines are annotations: they count toward the byte budgets but are not actions.
+        let reads = if protocol >= 9 { 1 } else { m };
+        let mut action_lines = Vec::with_capacity(m);
+        let mut annotations = vec![];
+        let mut turn_bytes = 0;
+        let mut read = 0;
+        while read < reads {
+            // Read no further than one byte past a budget, so that an endless line
+            // cannot use up memory before it is rejected
+            let left = [
+                options
+                    .max_turn_bytes
+                    .map(|limit| limit.saturating_sub(turn_bytes)),
+                options
+                    .max_total_bytes
+                    .map(|limit| limit.saturating_sub(output_bytes + turn_bytes)),
+            ];
+            let cap = left
+                .into_iter()
+                .flatten()
+                .min()
+                .map_or(u64::MAX, |left| left as u64 + 1);
+            let mut bytes = vec![];
+            if stdout.by_ref().take(cap).read_until(b'\n', &mut bytes)? == 0 {
+                if watchdog.as_ref().is_some_and(Watchdog::fired) {
+                    return Err(turn_timed_out(turn, sent.elapsed()));
+                }
+                bail!(
+                    "Agent process terminated unexpectedly at turn {} for elevator {}",
+                    turn,
+                    read
+                );
+            }
+            turn_bytes += bytes.len();
+            if let Some(limit) = options.max_turn_bytes
+                && turn_bytes > limit
+            {
+                bail!(
+                    "Turn {}: output exceeds the per-turn budget of {} bytes",
+                    turn,
+                    limit
+                );
+            }
+            if let Some(limit) = options.max_total_bytes
+                && output_bytes + turn_bytes > limit
+            {
+                bail!(
+                    "Turn {}: output exceeds the episode budget of {} bytes",
+                    turn,
+                    limit
+                );
+            }
+            let action_line = String::from_utf8(bytes)
+                .map_err(|_| anyhow!("Turn {}: output is not valid UTF-8", turn))?;
+            if let Some(note) = annotation(&action_line) {
+                annotations.push(note.to_string());
+                continue;
+            }
+            read += 1;
+            if protocol >= 9 {
+                action_lines.extend(action_line.trim_end().split(';').map(String::from));
+            } else {
+                action_lines.push(action_line);
+            }
+        }
+        if let Some(ref watchdog) = watchdog {
+            watchdog.disarm();
+        }
+        let answered = Instant::now();
+        profile.agent += answered - sent;
+        turn_times.push(answered - sent);
+        // The watchdog may not have caught an answer that came in just late
+        if let Some(limit) = options.turn_time_limit
+            && answered - sent > limit
+        {
+            return Err(turn_timed_out(turn, answered - sent));
+        }
+        if action_lines.len() != m {
+            bail!(
+                "Turn {}: expected {} actions separated by ';', got {}",
+                turn,
+                m,
+                action_lines.len()
+            );
+        }
+        if let Some(ref mut writer) = log_writer {
+            for note in &annotations {
+                writeln!(writer, "# {}", note)?;
+            }
+            for line in &action_lines {
+                writeln!(writer, "{}", line.trim())?;
+            }
+        }
+        let actions = action_lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                state
+                    .parse_action(i, line, options.lenient)
+                    .with_context(|| format!("Turn {}: Invalid action by elevator {}", turn, i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        used += answered - sent;
+        profile.protocol += answered.elapsed();
+        output_bytes += turn_bytes;
+        peak_turn_bytes = peak_turn_bytes.max(turn_bytes);
+        if let Some(limit) = options.time_limit
+            && used > limit
+        {
+            bail!(
+                "Time limit exceeded at turn {} ({} ms used, {} ms allowed)",
+                turn,
+                used.as_millis(),
+                limit.as_millis()
+            );
+        }
+        let applying = Instant::now();
+        apply_turn(&mut state, &actions)?;
+        let snapshotting = Instant::now();
+        profile.apply += snapshotting - applying;
+        let mut report = state.finish_step()?;
+        previous = report.snapshot.events.clone();
+        report.snapshot.annotations = annotations;
+        on_turn(report.snapshot);
+        profile.snapshot += snapshotting.elapsed();
+    }
+
+    if let Some(ref mut writer) = log_writer {
+        writer.flush()?;
+    }
+
+    drop(watchdog);
+    let mut child = child.lock().unwrap();
+    let agent_cpu = cpu_time(child.id());
+    let _ = child.kill();
+    Ok(Verdict {
+        score: state.calculate_final_score(),
+        components: state.final_components(),
+        output_bytes,
+        peak_turn_bytes,
+        time_used: used,
+        stopped_at,
+        profile,
+        turn_times,
+        agent_cpu,
+    })
+}
+
+/// Kills the agent when a turn's answers take longer than the per-turn limit, which ends
+/// a read that would otherwise block forever.
+struct Watchdog {
+    turns: mpsc::Sender<bool>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn start(child: Arc<Mutex<Child>>, limit: Duration) -> Self {
+        let (turns, rx) = mpsc::channel();
+        let fired = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&fired);
+        // `true` starts a turn's clock and `false` stops it; the thread ends with the
+        // sender
+        std::thread::spawn(move || {
+            while let Ok(true) = rx.recv() {
+                match rx.recv_timeout(limit) {
+                    Ok(_) => {}
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        flag.store(true, Ordering::SeqCst);
+                        let _ = child.lock().unwrap().kill();
+                        return;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        Self { turns, fired }
+    }
+
+    fn arm(&self) {
+        let _ = self.turns.send(true);
+    }
+
+    fn disarm(&self) {
+        let _ = self.turns.send(false);
+    }
+
+    fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+/// Plays `instance` with an in-process agent and returns the final score. Like the
+/// judge, an invalid turn is an error rather than a `STAY`.
+pub fn run_episode(instance: &Instance, agent: &mut dyn Agent) -> Result<u64> {
+    let mut state = SimulationState::from_instance(instance)?;
+    agent.start(&instance.params);
+    let mut previous = vec![];
+    while state.turn < state.t {
+        let turn = state.turn;
+        let actions = agent.act(&Observation::new(&mut state, &previous));
+        let report = state
+            .step(&actions)
+            .with_context(|| format!("Turn {}", turn))?;
+        previous = report.snapshot.events;
+    }
+    Ok(state.calculate_final_score())
+}
+
+/// Scores of `agent` on the instances generated from `seeds` with `params`.
+pub fn evaluate_seeds(
+    seeds: impl IntoIterator<Item = u64>,
+    params: &SimParams,
+    agent: &mut dyn Agent,
+) -> Result<Vec<u64>> {
+    seeds
+        .into_iter()
+        .map(|seed| {
+            let instance = Instance::generate(seed, params)?;
+            run_episode(&instance, agent).with_context(|| format!("Seed {}", seed))
+        })
+        .collect()
+}
+
+/// Final score of playing `actions`, one action per elevator for each turn, on
+/// `instance`. Nothing but the score is kept: no snapshots, observations or event log,
+/// so optimizers can score many candidates cheaply. Turns past the end of `actions`
+/// are spent waiting, and an invalid turn is an error.
+pub fn fast_score(instance: &Instance, actions: &[Vec<Action>]) -> Result<u64> {
+    let mut state = SimulationState::try_from_params(&instance.params)?.without_log();
+    state.set_arrival_schedule(instance.passengers.clone())?;
+    // Waiting goes through the per-turn checks, such as crowding, like any other action
+    let stay = vec![Action::Stay; state.m];
+    while state.turn < state.t {
+        let turn = state.turn;
+        let actions = actions.get(turn).unwrap_or(&stay);
+        apply_turn(&mut state, actions).with_context(|| format!("Turn {}", turn))?;
+        state.advance()?;
+    }
+    Ok(state.calculate_final_score())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_observation() -> Result<()> {
+        let mut state = SimulationState::new(6, 1, 10, 10)?;
+        state.turn = 2;
+        state.add_passenger(4, 1, 0, 0)?;
+        let observation = Observation::new(&mut state, &[]);
+        let (mut dense, mut sparse) = (vec![], vec![]);
+        observation.write(&mut dense, 7, None)?;
+        observation.write(&mut sparse, 8, None)?;
+        assert_eq!(
+            String::from_utf8(dense)?,
+            "-1\n0 0\n-1\n3\n0\n0\n0\n0\n0\n1 1 2\n0\n"
+        );
+        assert_eq!(
+            String::from_utf8(sparse)?,
+            "-1\n0 0\n-1\n3\n0\n1\n4 1 1 2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_observation() -> Result<()> {
+        let instance = Instance::generate(5, &SimParams::default())?;
+        let output = "OPEN 0 1\nUP\nDOWN\nUP\nOPEN 0\nSTAY\n".repeat(20);
+        let report = instance.replay(&output, false);
+        let header: ProtocolHeader = "10 3 10 100 0.1".parse()?;
+        let left = Some(Duration::from_millis(1500));
+        for observation in report.observations.iter().step_by(7) {
+            for protocol in 1..=PROTOCOL_VERSION {
+                let mut text = vec![];
+                observation.write(&mut text, protocol, left)?;
+                let text = String::from_utf8(text)?;
+                let (parsed, time_left) =
+                    Observation::parse(&text, &header, protocol, observation.turn)?;
+                let mut again = vec![];
+                parsed.write(&mut again, protocol, left)?;
+                assert_eq!(String::from_utf8(again)?, text, "protocol {}", protocol);
+                assert_eq!(time_left, left.filter(|_| protocol >= 2));
+                let waited = |o: &Observation| -> Vec<usize> {
+                    o.state.floors[2]
+                        .waiting
+                        .iter()
+                        .map(|p| p.waited(o.turn))
+                        .collect()
+                };
+                assert_eq!(waited(&parsed), waited(observation));
+            }
+        }
+        assert!(Observation::parse("-1\n0 0 0\n", &header, 2, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_turn() -> Result<()> {
+        let mut state = SimulationState::new(6, 2, 10, 10)?;
+        state.add_passenger(3, 1, 0, 0)?;
+        let pick = Action::Open {
+            picks: vec![0],
+            drops: None,
+        };
+        // Both elevators pick the only passenger: the second pick is out of range
+        assert!(validate_turn(&state, &[pick.clone(), pick.clone()]).is_err());
+        assert!(validate_turn(&state, &[Action::Up]).is_err());
+        validate_turn(&state, &[pick, Action::Up])?;
+        assert_eq!(state.waiting_passengers[3].len(), 1);
+        assert_eq!(state.elevators[1].floor, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination_change() -> Result<()> {
+        let mut state = SimulationState::try_from_params(&SimParams {
+            n: 6,
+            m: 1,
+            destination_changes: vec![crate::DestinationChange {
+                turn: 1,
+                passenger: 0,
+                target: 5,
+            }],
+            ..SimParams::default()
+        })?;
+        state.add_passenger(3, 1, 0, 0)?;
+        state.change_destinations();
+        assert!(Observation::new(&mut state, &[]).changes.is_empty());
+
+        state.turn = 1;
+        state.change_destinations();
+        let observation = Observation::new(&mut state, &[]);
+        assert_eq!(observation.changes, vec![(3, 0, 5)]);
+        assert_eq!(observation.state.floors[3].waiting[0].target_floor, 5);
+        let mut out = vec![];
+        observation.write(&mut out, 10, None)?;
+        assert!(String::from_utf8(out)?.starts_with("-1\n0 0\n-1\n1 3 0 5\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pickup_report() -> Result<()> {
+        let mut state = SimulationState::new(6, 2, 1, 10)?;
+        state.add_passenger(3, 1, 0, 4)?;
+        state.add_passenger(3, 5, 0, 5)?;
+        state.apply_action(0, &Action::open(&[0, 1]))?;
+        state.turn = 1;
+        let observation = Observation::new(&mut state, &[]);
+        assert_eq!(observation.pickups[0].boarded, vec![5]);
+        assert_eq!(observation.pickups[0].skipped, vec![4]);
+        let mut out = vec![];
+        observation.write(&mut out, 11, None)?;
+        assert!(String::from_utf8(out)?.starts_with("-1\n0 0 0 0\n-1\n0\n1 5 1 4\n0 0\n"));
+        Ok(())
+    }
+
+    /// Waits everywhere, like an output of nothing but `STAY`.
+    struct Idle;
+
+    impl Agent for Idle {
+        fn act(&mut self, observation: &Observation) -> Vec<Action> {
+            vec![Action::Stay; observation.state.elevators.len()]
+        }
+    }
+
+    #[test]
+    fn test_run_episode() -> Result<()> {
+        let params = SimParams::default();
+        let instance = Instance::generate(3, &params)?;
+        let stay = "STAY\n".repeat(params.m * params.t);
+        assert_eq!(
+            run_episode(&instance, &mut Idle)?,
+            instance.replay(&stay, false).score
+        );
+        assert_eq!(evaluate_seeds(0..3, &params, &mut Idle)?.len(), 3);
+        let mut turns = 0;
+        let mut closure = |observation: &Observation| {
+            turns += 1;
+            Idle.act(observation)
+        };
+        assert_eq!(
+            run_episode(&instance, &mut closure)?,
+            run_episode(&instance, &mut Idle)?
+        );
+        assert_eq!(turns, params.t);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_command_line() -> Result<()> {
+        let posix = |line| split_command_line(line, false);
+        assert_eq!(
+            posix(r#"python3 "my agent.py" --name 'a "b"' x\ y ''"#)?,
+            vec!["python3", "my agent.py", "--name", "a \"b\"", "x y", ""]
+        );
+        assert_eq!(posix(r#""a\"b\\c\d""#)?, vec![r#"a"b\c\d"#]);
+        assert!(posix("agent 'open").is_err());
+        assert!(posix("   ")?.is_empty());
+
+        let windows = |line| split_command_line(line, true);
+        assert_eq!(
+            windows(r#"C:\agents\run.exe "C:\my dir\\" 'x' a\\\"b"#)?,
+            vec![r"C:\agents\run.exe", r"C:\my dir\", "'x'", r#"a\"b"#]
+        );
+        assert_eq!(windows(r#""a\\" b"#)?, vec![r"a\", "b"]);
+
+        let agent = AgentCommand::parse("python3 my_agent.py --depth 3")?;
+        assert_eq!((agent.program.as_str(), agent.args.len()), ("python3", 3));
+        assert!(AgentCommand::parse("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_protocol() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let report = instance.replay("OPEN 0\nUP\nOPEN", false);
+        let mut out = vec![];
+        write_protocol(&mut out, &instance, &report.observations, 2)?;
+        let text = String::from_utf8(out)?;
+        assert_eq!(
+            text,
+            "PROTOCOL 2\n4 1 10 3 0.1\n\
+             -1\n2\n0\n0\n0\n1 3 0\n0\n\
+             -1\n2\n1 3 1\n0\n0\n0\n0\n\
+             -1\n3\n1 3 2\n0\n0\n0\n0\n"
+        );
+        assert!(write_protocol(&mut vec![], &instance, &[], PROTOCOL_VERSION + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fast_score() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let output = "OPEN 0\nUP\nOPEN\n";
+        let actions: Vec<Vec<Action>> = output
+            .lines()
+            .map(|line| Ok(vec![line.parse()?]))
+            .collect::<Result<_>>()?;
+        assert_eq!(
+            fast_score(&instance, &actions)?,
+            instance.replay(output, false).score
+        );
+        // Waiting out the missing turns
+        assert_eq!(fast_score(&instance, &actions[..1])?, 9);
+        assert!(fast_score(&instance, &[vec![Action::Stay; 2]]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interactive_refuses_delays() -> Result<()> {
+        let params = SimParams {
+            detection_delay: 2,
+            ..SimParams::default()
+        };
+        let instance = Instance::generate(0, &params)?;
+        let options = JudgeOptions {
+            online_seed: Some(0),
+            ..JudgeOptions::default()
+        };
+        let agent = AgentCommand::new("true", &[]);
+        let error = run_agent(&instance, &agent, &options, None, &mut |_| {}).unwrap_err();
+        assert!(error.to_string().contains("detection delay"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_turn_time_limit() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let shell = |script: &str| AgentCommand::new("sh", &["-c".to_string(), script.to_string()]);
+        let options = JudgeOptions {
+            turn_time_limit: Some(Duration::from_millis(500)),
+            ..JudgeOptions::default()
+        };
+        let verdict = run_agent(&instance, &shell("yes STAY"), &options, None, &mut |_| {})?;
+        assert_eq!(verdict.turn_times.len(), 3);
+        // Killed by the watchdog rather than waited on for the whole sleep
+        let started = Instant::now();
+        let slow = shell("read line; echo STAY; exec sleep 10");
+        let error = run_agent(&instance, &slow, &options, None, &mut |_| {}).unwrap_err();
+        assert!(error.to_string().starts_with("TLE at turn 1"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_endless_line() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let agent = AgentCommand::new("cat", &["/dev/zero".to_string()]);
+        let options = JudgeOptions {
+            max_turn_bytes: Some(100),
+            ..JudgeOptions::default()
+        };
+        let error = run_agent(&instance, &agent, &options, None, &mut |_| {}).unwrap_err();
+        assert!(error.to_string().contains("per-turn budget"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_output_stays() -> Result<()> {
+        let params = SimParams {
+            n: 4,
+            m: 1,
+            c: 4,
+            t: 10,
+            comfort_threshold: Some(0.5),
+            comfort_penalty: 10,
+            ..SimParams::default()
+        };
+        let mut targets = vec![vec![vec![]; 10]; 4];
+        targets[2][0] = vec![3; 3];
+        let instance = Instance::from_targets(params, targets)?;
+        let truncated = instance.replay("OPEN 0 1 2\n", false);
+        let explicit = instance.replay(&format!("OPEN 0 1 2\n{}", "STAY\n".repeat(9)), false);
+        // Crowded on every turn of the run
+        assert_eq!(truncated.components.crowding, 100);
+        assert_eq!(truncated.score, explicit.score);
+        let open = vec![vec![Action::open(&[0, 1, 2])]];
+        assert_eq!(fast_score(&instance, &open)?, explicit.score);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latency() {
+        assert_eq!(Latency::from_turns(&[]), None);
+        let turn_times: Vec<_> = (1..=200).rev().map(Duration::from_millis).collect();
+        let latency = Latency::from_turns(&turn_times).unwrap();
+        assert_eq!(latency.turns, 200);
+        assert_eq!(latency.min, Duration::from_millis(1));
+        assert_eq!(latency.mean, Duration::from_micros(100_500));
+        assert_eq!(latency.max, Duration::from_millis(200));
+        assert_eq!(latency.p99, Duration::from_millis(198));
+    }
+}
@@ -1,17 +1,162 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use rand::SeedableRng;
-use rand::distr::{Distribution, Uniform};
-use rand_distr::Poisson;
+use rand::distr::{Bernoulli, Distribution};
+use rand::seq::SliceRandom;
 use rand_pcg::Pcg64;
 use serde::{Deserialize, Serialize};
+use stats::StatsCollector;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
+mod action;
+mod agent;
+mod bound;
+mod cache;
+#[cfg(feature = "server")]
+mod contest;
+mod difficulty;
+mod events;
+mod formats;
+mod generator;
+mod history;
+mod judge;
+mod output;
+mod params;
+pub mod prelude;
+mod reference;
+mod replay;
+#[cfg(feature = "results-db")]
+mod results;
+mod scenario;
+mod scoring;
+#[cfg(feature = "server")]
+mod server;
+mod spec;
+mod stats;
+mod trace;
+mod tutorial;
+mod variant;
+mod verify;
+
+pub use action::Action;
+pub use agent::{Agent, Capability, ProtocolHeader};
+pub use bound::{lower_bound_score, min_possible_penalty};
+pub use cache::InstanceCache;
+#[cfg(feature = "server")]
+pub use contest::{Contest, Episode, Split};
+pub use difficulty::{Difficulty, Tier};
+pub use events::{Event, ScoreReason};
+pub use formats::{InputVersion, Instance, convert_input};
+pub use generator::{
+    ArrivalProcess, OnlineArrivals, PassengerIds, PoissonUniform, Spawn, generate_passengers,
+    generate_with, spawn_schedule,
+};
+pub use history::{History, HistoryBuffer};
+pub use judge::{
+    AgentCommand, JudgeOptions, Latency, Observation, Profile, Verdict, apply_turn, evaluate_seeds,
+    fast_score, run_agent, run_episode, split_command_line, validate_turn, write_protocol,
+};
+pub use output::{Command, OutputFormat, ParsedOutput, annotation, parse_output};
+pub use params::{
+    ActionCosts, ActionOrder, DestinationChange, ElevatorSpec, SameFloorPolicy, SimParams,
+};
+pub use reference::{ReferenceTable, fnv1a_hex};
+pub use replay::{Diagnostic, Divergence, ReplayFile, ReplayReport, ScoreRequest, replay_output};
+#[cfg(feature = "results-db")]
+pub use results::{ResultsDb, RunRecord, Standing};
+pub use scenario::Scenario;
+pub use scoring::{ScoreComponents, ScoreWeights, Scoring, ScoringPolicy};
+#[cfg(feature = "server")]
+pub use server::{JudgeServer, Request, Response};
+pub use spec::{
+    DEFAULT_C, DEFAULT_LAMBDA, DEFAULT_M, DEFAULT_N, DEFAULT_PICK_SLACK, DEFAULT_T, INPUT_VERSION,
+    OUTPUT_VERSION, PROTOCOL_CHANGES, PROTOCOL_VERSION, ProtocolChange, REPLAY_VERSION, Spec, spec,
+};
+pub use stats::{PassengerRecord, RunStats, RunSummary, ScoreSummary, Statistics};
+pub use trace::TraceArrivals;
+pub use tutorial::{Goal, Lesson, LessonReport};
+pub use variant::ProblemVariant;
+pub use verify::{hash_chain, state_hash, verify_output};
+
 #[wasm_bindgen]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Passenger {
     pub id: usize,
     pub arrival_turn: usize,
+    /// Destination of the current leg.
     pub target_floor: usize,
+    /// Destinations of the remaining legs after `target_floor`, for journeys through
+    /// transfer floors.
+    #[wasm_bindgen(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<usize>,
+    /// Turns already waited before turn 0, for passengers an instance starts with.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub waited_before: usize,
+}
+
+fn is_zero(x: &usize) -> bool {
+    *x == 0
+}
+
+impl Passenger {
+    /// Final destination, after all legs.
+    pub fn destination(&self) -> usize {
+        self.legs.last().copied().unwrap_or(self.target_floor)
+    }
+
+    /// Turns waited by `turn` since arriving, counting any wait before turn 0.
+    pub fn waited(&self, turn: usize) -> usize {
+        (turn + self.waited_before).saturating_sub(self.arrival_turn)
+    }
+
+    /// Penalty of a wait that ends before turn `end` under `scoring` (by default the
+    /// square of the turns waited), of which only those from turn `warmup` on count
+    /// when there is a warm-up.
+    pub fn penalty(&self, end: usize, warmup: usize, scoring: &dyn ScoringPolicy) -> u64 {
+        let waited = if warmup == 0 {
+            self.waited(end)
+        } else {
+            self.waited(end).min(end.saturating_sub(warmup))
+        };
+        scoring.trip_penalty(waited)
+    }
+
+    /// How much [`Passenger::penalty`] grows if the wait that would end before turn
+    /// `end` lasts `turns` turns longer.
+    pub fn marginal_penalty(
+        &self,
+        end: usize,
+        turns: usize,
+        warmup: usize,
+        scoring: &dyn ScoringPolicy,
+    ) -> u64 {
+        self.penalty(end + turns, warmup, scoring) - self.penalty(end, warmup, scoring)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Direction {
+    Up,
+    Down,
+    #[default]
+    Idle,
+}
+
+/// Whether an elevator's doors are open, which outlasts its `OPEN` only under
+/// `SimParams::door_physics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DoorState {
+    #[default]
+    Closed,
+    Open,
+}
+
+impl DoorState {
+    fn is_closed(&self) -> bool {
+        *self == Self::Closed
+    }
 }
 
 #[wasm_bindgen]
@@ -21,6 +166,11 @@ pub struct Elevator {
     pub capacity: usize,
     #[serde(skip)]
     pub(crate) passengers: Vec<Passenger>,
+    // Sub-steps travelled above `floor` when `travel_time` > 1
+    pub(crate) offset: usize,
+    pub(crate) direction: Direction,
+    pub(crate) door: DoorState,
+    pub(crate) idle_turns: usize,
 }
 
 #[wasm_bindgen]
@@ -41,28 +191,84 @@ impl Elevator {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Snapshot {
     pub turn: usize,
     pub score: u64,
     pub elevators: Vec<ElevatorSnapshot>,
     pub floors: Vec<FloorSnapshot>,
+    pub arrivals: Vec<Passenger>,
+    pub events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_left: Option<u64>,
+    /// The run's [`Statistics`], in the snapshot of its last turn only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<Statistics>,
+    /// The agent's `#` lines written before this turn's actions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<String>,
+}
+
+impl Snapshot {
+    /// A text picture of the state: one row per floor from the top, one column per
+    /// elevator showing its direction (`^`, `v` or `#` when idle) and load, and the
+    /// number of waiting passengers at the end of each row.
+    pub fn render_ascii(&self) -> String {
+        let names: Vec<String> = (0..self.floors.len())
+            .map(|f| {
+                self.floors[f]
+                    .label
+                    .clone()
+                    .unwrap_or_else(|| f.to_string())
+            })
+            .collect();
+        let width = names.iter().map(String::len).max().unwrap_or(1);
+        let mut text = format!("turn {} score {}\n", self.turn, self.score);
+        for floor in (0..self.floors.len()).rev() {
+            text += &format!("{:>width$} |", names[floor]);
+            for e in &self.elevators {
+                if e.floor == floor {
+                    let arrow = match e.direction {
+                        Direction::Up => '^',
+                        Direction::Down => 'v',
+                        Direction::Idle => '#',
+                    };
+                    text += &format!(" {}{:>2}", arrow, e.passenger_count);
+                } else {
+                    text += "   .";
+                }
+            }
+            text += &format!(" | {}\n", self.floors[floor].waiting_count);
+        }
+        text
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ElevatorSnapshot {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
     pub floor: usize,
+    pub position: f64,
+    pub direction: Direction,
+    #[serde(default, skip_serializing_if = "DoorState::is_closed")]
+    pub door: DoorState,
     pub passenger_count: usize,
     pub passengers: Vec<Passenger>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FloorSnapshot {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
     pub waiting_count: usize,
     pub waiting: Vec<Passenger>,
+    pub up_calls: usize,
+    pub down_calls: usize,
 }
 
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct SimulationState {
     pub n: usize,
     pub m: usize,
@@ -70,339 +276,2572 @@ pub struct SimulationState {
     pub t: usize,
     pub turn: usize,
     pub score: u64,
+    params: SimParams,
+    specs: Vec<ElevatorSpec>,
     elevators: Vec<Elevator>,
     waiting_passengers: Vec<Vec<Passenger>>,
+    arrivals: Vec<Passenger>,
+    events: Vec<Event>,
+    // Turn that `arrivals` and `events` belong to
+    log_turn: usize,
+    // The last `observation_delay + 1` observations, oldest first
+    observations: VecDeque<Snapshot>,
+    budget_used: u64,
+    // The latest OPEN of each elevator
+    pickups: Vec<Pickup>,
+    // Turn each passenger last boarded, when rides are limited
+    boarded_at: HashMap<usize, usize>,
+    // Whether arrivals and events are kept for snapshots
+    logging: bool,
+    // Passengers still to arrive, `[floor][turn]`, for `step`
+    schedule: Vec<Vec<Vec<Passenger>>>,
+    // Score when the previous `step` ended
+    stepped_score: u64,
+    // Hall calls at each floor, kept in step with `waiting_passengers`
+    calls: Vec<FloorCalls>,
+    // Boardings and deliveries so far, while logging
+    stats: StatsCollector,
+    // `score` before weighting, by component
+    components: ScoreComponents,
+    // Set by `with_scoring` in place of `params.scoring`
+    policy: Option<Arc<dyn ScoringPolicy + Send + Sync>>,
 }
 
-impl SimulationState {
-    pub fn apply_action(
-        &mut self,
-        elevator_idx: usize,
-        action: &str,
-        picks: &[usize],
-    ) -> Result<()> {
-        if elevator_idx >= self.m {
-            bail!("Invalid elevator index: {}", elevator_idx);
-        }
+/// A state saved by [`SimulationState::checkpoint`]. It can be rolled back to once;
+/// clone it to return to the same state several times.
+#[derive(Clone)]
+pub struct StateToken(Box<SimulationState>);
 
-        match action {
-            "UP" => {
-                self.elevators[elevator_idx].floor =
-                    (self.elevators[elevator_idx].floor + 1).min(self.n - 1);
-            }
-            "DOWN" => {
-                self.elevators[elevator_idx].floor =
-                    self.elevators[elevator_idx].floor.saturating_sub(1);
-            }
-            "STAY" => {}
-            "OPEN" => {
-                let current_floor = self.elevators[elevator_idx].floor;
-
-                // 1. Drop off
-                let (delivered, remaining): (Vec<Passenger>, Vec<Passenger>) = self.elevators
-                    [elevator_idx]
-                    .passengers
-                    .drain(..)
-                    .partition(|p| p.target_floor == current_floor);
-
-                for p in delivered {
-                    let duration = self.turn - p.arrival_turn + 1;
-                    self.score += (duration as u64).pow(2);
-                }
-                self.elevators[elevator_idx].passengers = remaining;
+/// What one [`SimulationState::step`] did.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TurnReport {
+    pub turn: usize,
+    /// Passengers who reached their final destination this turn
+    pub delivered: Vec<usize>,
+    pub score_gained: u64,
+    /// The state after the turn's actions, before the next turn's arrivals
+    pub snapshot: Snapshot,
+}
 
-                // 2. Pick up
-                let mut sorted_picks = picks.to_vec();
-                sorted_picks.sort_unstable_by(|a, b| b.cmp(a)); // Descending to remove safely
+/// What an elevator's `OPEN` does, worked out before anything changes.
+struct OpenPlan {
+    /// Whether each passenger aboard gets off
+    alighting: Vec<bool>,
+    /// Each pick, from the highest index down, and what became of it
+    picks: Vec<(usize, Boarding)>,
+}
 
-                for &idx in &sorted_picks {
-                    if idx >= self.waiting_passengers[current_floor].len() {
-                        bail!("Invalid passenger index {} at floor {}", idx, current_floor);
-                    }
-                    if self.elevators[elevator_idx].passengers.len()
-                        >= self.elevators[elevator_idx].capacity
-                    {
-                        continue;
-                    }
-                    let p = self.waiting_passengers[current_floor].remove(idx);
-                    self.elevators[elevator_idx].passengers.push(p);
-                }
-            }
-            _ => bail!("Unknown action: {}", action),
+#[derive(Clone, Copy)]
+enum Boarding {
+    Boards,
+    Full,
+    NoShow,
+}
+
+impl OpenPlan {
+    fn boarded(&self) -> usize {
+        self.picks
+            .iter()
+            .filter(|(_, b)| matches!(b, Boarding::Boards))
+            .count()
+    }
+
+    /// Passengers getting off at `floor` who join its line again: those short of their
+    /// destination and those changing elevators.
+    fn requeued(&self, onboard: &[Passenger], floor: usize) -> usize {
+        onboard
+            .iter()
+            .zip(&self.alighting)
+            .filter(|&(p, &alight)| alight && (p.target_floor != floor || !p.legs.is_empty()))
+            .count()
+    }
+}
+
+/// Hall calls at one floor, as returned by [`SimulationState::call_summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallSummary {
+    pub up: usize,
+    pub down: usize,
+    /// Turns waited by the longest-waiting passenger; 0 when nobody waits
+    pub oldest_wait: usize,
+}
+
+#[derive(Clone, Default)]
+struct FloorCalls {
+    up: usize,
+    down: usize,
+    // Waiting passengers by the turn their wait began, which can be before turn 0
+    started: BTreeMap<isize, usize>,
+}
+
+impl FloorCalls {
+    fn add(&mut self, floor: usize, p: &Passenger) {
+        if p.target_floor > floor {
+            self.up += 1;
+        } else {
+            self.down += 1;
         }
-        Ok(())
+        *self.started.entry(Self::start(p)).or_default() += 1;
     }
 
-    pub fn create_snapshot(&self) -> Snapshot {
-        Snapshot {
-            turn: self.turn,
-            score: self.score,
-            elevators: self
-                .elevators
-                .iter()
-                .map(|e| ElevatorSnapshot {
-                    floor: e.floor,
-                    passenger_count: e.passengers.len(),
-                    passengers: e.passengers.clone(),
-                })
-                .collect(),
-            floors: self
-                .waiting_passengers
-                .iter()
-                .map(|f| FloorSnapshot {
-                    waiting_count: f.len(),
-                    waiting: f.clone(),
-                })
-                .collect(),
+    fn remove(&mut self, floor: usize, p: &Passenger) {
+        if p.target_floor > floor {
+            self.up -= 1;
+        } else {
+            self.down -= 1;
+        }
+        let start = Self::start(p);
+        if let Some(count) = self.started.get_mut(&start) {
+            *count -= 1;
+            if *count == 0 {
+                self.started.remove(&start);
+            }
         }
     }
+
+    fn start(p: &Passenger) -> isize {
+        p.arrival_turn as isize - p.waited_before as isize
+    }
+}
+
+/// How an elevator's `OPEN` went, by the passenger IDs of its picks in waiting order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pickup {
+    pub turn: usize,
+    pub boarded: Vec<usize>,
+    /// Picks left waiting because the elevator was full.
+    pub skipped: Vec<usize>,
 }
 
-#[wasm_bindgen]
 impl SimulationState {
-    #[wasm_bindgen(constructor)]
-    pub fn new(n: usize, m: usize, c: usize, t: usize) -> Self {
+    /// A fresh simulation with the default options; see [`SimParams::validate`].
+    pub fn new(n: usize, m: usize, c: usize, t: usize) -> Result<Self> {
+        Self::try_from_params(&SimParams {
+            n,
+            m,
+            c,
+            t,
+            ..SimParams::default()
+        })
+    }
+
+    /// Like [`SimulationState::from_params`], but checks the parameters first.
+    pub fn try_from_params(params: &SimParams) -> Result<Self> {
+        params.validate()?;
+        Ok(Self::from_params(params))
+    }
+
+    /// Expects parameters that pass [`SimParams::validate`], as those of a parsed or
+    /// generated [`Instance`] do.
+    pub fn from_params(params: &SimParams) -> Self {
+        let (n, m, c, t) = (params.n, params.m, params.c, params.t);
+        let specs: Vec<ElevatorSpec> = (0..m).map(|i| params.elevator_spec(i)).collect();
         Self {
             n,
             m,
             c,
             t,
-            elevators: (0..m)
-                .map(|_| Elevator {
+            params: params.clone(),
+            elevators: specs
+                .iter()
+                .map(|spec| Elevator {
                     floor: n / 2,
                     passengers: vec![],
-                    capacity: c,
+                    capacity: spec.capacity,
+                    offset: 0,
+                    direction: Direction::Idle,
+                    door: DoorState::Closed,
+                    idle_turns: 0,
                 })
                 .collect(),
+            specs,
             waiting_passengers: vec![vec![]; n],
+            arrivals: vec![],
+            events: vec![],
+            log_turn: 0,
+            observations: VecDeque::new(),
+            budget_used: 0,
+            pickups: vec![Pickup::default(); m],
+            boarded_at: HashMap::new(),
+            logging: true,
+            schedule: vec![],
+            stepped_score: 0,
+            calls: vec![FloorCalls::default(); n],
+            stats: StatsCollector::new(m),
+            components: ScoreComponents::default(),
+            policy: None,
             turn: 0,
             score: 0,
         }
     }
 
-    #[wasm_bindgen]
-    pub fn apply_action_wasm(
-        &mut self,
-        elevator_idx: usize,
-        action: &str,
-        picks: &[usize],
-    ) -> Result<(), String> {
-        self.apply_action(elevator_idx, action, picks)
-            .map_err(|e| e.to_string())
+    /// A simulation of `instance` at turn 0 with that turn's arrivals in, to be played
+    /// with [`SimulationState::step`].
+    pub fn from_instance(instance: &Instance) -> Result<Self> {
+        let mut state = Self::try_from_params(&instance.params)?;
+        state.set_arrival_schedule(instance.passengers.clone())?;
+        Ok(state)
     }
 
-    #[wasm_bindgen]
-    pub fn calculate_final_score(&self) -> u64 {
-        let mut final_score = self.score;
-        for floor_passengers in &self.waiting_passengers {
-            for p in floor_passengers {
-                let duration = self.t - p.arrival_turn;
-                final_score += (duration as u64).pow(2);
-            }
+    /// A simulation in the state `snapshot` shows, at its turn and with no arrivals to
+    /// come, for agents that look ahead with [`SimulationState::fork`]. The score starts
+    /// at 0, so that it counts only what happens from here.
+    pub fn from_snapshot(params: &SimParams, snapshot: &Snapshot) -> Result<Self> {
+        let mut state = Self::try_from_params(params)?;
+        if snapshot.elevators.len() != state.m || snapshot.floors.len() != state.n {
+            bail!(
+                "The snapshot has {} elevators and {} floors, expected {} and {}",
+                snapshot.elevators.len(),
+                snapshot.floors.len(),
+                state.m,
+                state.n
+            );
         }
-        for e in &self.elevators {
-            for p in &e.passengers {
-                let duration = self.t - p.arrival_turn;
-                final_score += (duration as u64).pow(2);
+        if snapshot.turn >= state.t {
+            bail!("The snapshot is of turn {}, after the last", snapshot.turn);
+        }
+        state.turn = snapshot.turn;
+        state.log_turn = snapshot.turn;
+        for ((e, spec), s) in state
+            .elevators
+            .iter_mut()
+            .zip(&state.specs)
+            .zip(&snapshot.elevators)
+        {
+            e.floor = s.floor;
+            e.offset = ((s.position - s.floor as f64) * spec.travel_time as f64).round() as usize;
+            e.direction = s.direction;
+            e.door = s.door;
+            e.passengers = s.passengers.clone();
+        }
+        for (floor, f) in snapshot.floors.iter().enumerate() {
+            for p in &f.waiting {
+                state.calls[floor].add(floor, p);
             }
+            state.waiting_passengers[floor] = f.waiting.clone();
         }
-        final_score
+        Ok(state)
     }
 
-    #[wasm_bindgen]
-    pub fn get_elevator_floor(&self, idx: usize) -> usize {
-        self.elevators[idx].floor
+    /// Hands the simulation its passengers, `[floor][turn]`, to bring in as
+    /// [`SimulationState::step`] reaches each turn, starting with the current turn's.
+    /// Passengers scheduled for turns already played are dropped.
+    pub fn set_arrival_schedule(&mut self, mut schedule: Vec<Vec<Vec<Passenger>>>) -> Result<()> {
+        if schedule.len() != self.n || schedule.iter().any(|turns| turns.len() != self.t) {
+            bail!(
+                "The arrival schedule must list {} floors of {} turns each",
+                self.n,
+                self.t
+            );
+        }
+        for turns in &mut schedule {
+            turns[..self.turn].iter_mut().for_each(Vec::clear);
+        }
+        self.schedule = schedule;
+        self.arrive()
     }
 
-    #[wasm_bindgen]
-    pub fn get_elevator_passenger_count(&self, idx: usize) -> usize {
-        self.elevators[idx].passengers.len()
+    /// Like [`SimulationState::set_arrival_schedule`], with the passengers of an input
+    /// file (v1 or v2) for the same `N` and `T`.
+    pub fn load_arrivals_from_str(&mut self, text: &str) -> Result<()> {
+        let instance = Instance::parse(text)?;
+        if (instance.params.n, instance.params.t) != (self.n, self.t) {
+            bail!(
+                "The input has N={} and T={}, but the simulation N={} and T={}",
+                instance.params.n,
+                instance.params.t,
+                self.n,
+                self.t
+            );
+        }
+        self.set_arrival_schedule(instance.passengers)
     }
 
-    #[wasm_bindgen]
-    pub fn get_elevator_passenger_target(&self, elevator_idx: usize, p_idx: usize) -> usize {
-        self.elevators[elevator_idx].passengers[p_idx].target_floor
+    /// Plays the current turn: applies one action per elevator, all of them or none,
+    /// then moves to the next turn and brings in its scheduled arrivals. The simulation
+    /// is over once `turn` reaches `t`.
+    pub fn step(&mut self, actions: &[Action]) -> Result<TurnReport> {
+        if self.turn >= self.t {
+            bail!("The simulation is over after {} turns", self.t);
+        }
+        apply_turn(self, actions)?;
+        self.finish_step()
     }
 
-    #[wasm_bindgen]
-    pub fn get_waiting_passenger_count(&self, floor: usize) -> usize {
-        self.waiting_passengers[floor].len()
+    /// The second half of [`SimulationState::step`], once the actions are applied:
+    /// takes the turn's snapshot and moves on to the next turn.
+    pub(crate) fn finish_step(&mut self) -> Result<TurnReport> {
+        let snapshot = self.turn_snapshot();
+        let delivered = snapshot
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Delivered { passenger_id, .. }
+                | Event::DeliveredOnArrival { passenger_id, .. } => Some(*passenger_id),
+                _ => None,
+            })
+            .collect();
+        let report = TurnReport {
+            turn: self.turn,
+            delivered,
+            score_gained: self.score - self.stepped_score,
+            snapshot,
+        };
+        self.stepped_score = self.score;
+        self.advance()?;
+        Ok(report)
     }
 
-    #[wasm_bindgen]
-    pub fn get_waiting_passenger_target(&self, floor: usize, p_idx: usize) -> usize {
-        self.waiting_passengers[floor][p_idx].target_floor
+    /// An independent copy of the simulation, for trying out actions without touching
+    /// this one.
+    pub fn fork(&self) -> Self {
+        self.clone()
     }
 
-    #[wasm_bindgen]
-    pub fn get_waiting_passenger_arrival_turn(&self, floor: usize, p_idx: usize) -> usize {
-        self.waiting_passengers[floor][p_idx].arrival_turn
+    /// Saves the current state, to return to with [`SimulationState::rollback`].
+    pub fn checkpoint(&self) -> StateToken {
+        StateToken(Box::new(self.clone()))
     }
 
-    #[wasm_bindgen]
-    pub fn add_passenger(&mut self, floor: usize, target: usize, arrival_turn: usize, id: usize) {
-        self.waiting_passengers[floor].push(Passenger {
-            id,
-            arrival_turn,
-            target_floor: target,
-        });
+    /// Returns to the state saved by `token`, undoing every turn and action since.
+    pub fn rollback(&mut self, token: StateToken) {
+        *self = *token.0;
     }
-}
 
-#[wasm_bindgen]
-#[allow(clippy::needless_range_loop)]
-pub fn run_simulation_wasm(seed: u64, output_text: &str) -> Result<JsValue, String> {
-    let n = 10;
-    let m = 3;
-    let c = 10;
-    let t = 100;
-    let lambda = 0.1;
-
-    let mut rng = Pcg64::seed_from_u64(seed);
-    let poi = Poisson::new(lambda).map_err(|e| e.to_string())?;
-    let target_dist = Uniform::new(0, n).map_err(|e| e.to_string())?;
+    /// Moves to the next turn and brings in its scheduled arrivals.
+    pub(crate) fn advance(&mut self) -> Result<()> {
+        self.turn += 1;
+        if self.turn < self.t {
+            self.arrive()?;
+        }
+        Ok(())
+    }
 
-    // Pre-generate all passengers for all floors and turns to match local_judge exactly
-    let mut passenger_source: Vec<Vec<Vec<Passenger>>> = vec![vec![vec![]; t]; n];
-    let mut next_id = 0;
-    for i in 0..n {
-        for turn in 0..t {
-            let count: u32 = poi.sample(&mut rng) as u32;
-            for _ in 0..count {
-                let mut target = target_dist.sample(&mut rng);
-                while target == i {
-                    target = target_dist.sample(&mut rng);
+    /// Brings in the current turn's scheduled arrivals and destination changes. A
+    /// passenger who cannot arrive does not keep the others out; the first such error
+    /// is returned.
+    fn arrive(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for floor in 0..self.schedule.len() {
+            let arrivals = std::mem::take(&mut self.schedule[floor][self.turn]);
+            for p in arrivals {
+                let arrived = self.push_arrival(floor, p);
+                if result.is_ok() {
+                    result = arrived;
                 }
-                passenger_source[i][turn].push(Passenger {
-                    id: next_id,
-                    arrival_turn: turn,
-                    target_floor: target,
-                });
-                next_id += 1;
             }
         }
+        self.change_destinations();
+        result
+    }
+
+    /// Brings in every passenger still to arrive, for runs cut short: they wait until
+    /// the end like everyone left behind.
+    pub(crate) fn arrive_all(&mut self) -> Result<()> {
+        for (floor, turns) in std::mem::take(&mut self.schedule).into_iter().enumerate() {
+            for p in turns.into_iter().flatten() {
+                self.push_arrival(floor, p)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn params(&self) -> &SimParams {
+        &self.params
     }
 
-    let mut sim = SimulationState::new(n, m, c, t);
-    let mut history = Vec::with_capacity(t);
+    pub fn elevator_spec(&self, elevator_idx: usize) -> &ElevatorSpec {
+        &self.specs[elevator_idx]
+    }
 
-    let output_lines: Vec<&str> = output_text.trim().split('\n').collect();
-    let mut current_line = 0;
+    /// Parses an action line for one elevator, giving up on an `OPEN` as soon as it
+    /// lists more indices than the car's capacity plus `pick_slack`.
+    pub fn parse_action(&self, elevator_idx: usize, line: &str, lenient: bool) -> Result<Action> {
+        let Some(elevator) = self.elevators.get(elevator_idx) else {
+            bail!("Invalid elevator index: {}", elevator_idx);
+        };
+        let max_indices = self
+            .params
+            .pick_slack
+            .map(|slack| elevator.capacity + slack);
+        Action::parse_line_capped(line, lenient, max_indices)
+    }
 
-    for turn in 0..t {
-        sim.turn = turn;
-        // Add pre-generated passengers for this turn
-        for floor in 0..n {
-            let passengers = std::mem::take(&mut passenger_source[floor][turn]);
-            for p in passengers {
-                sim.add_passenger(floor, p.target_floor, p.arrival_turn, p.id);
+    /// Elevator indices in the order their actions are applied this turn.
+    pub fn action_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.m).collect();
+        match self.params.action_order {
+            ActionOrder::Index => {}
+            ActionOrder::Reverse => order.reverse(),
+            ActionOrder::Random => {
+                let seed = self.params.order_seed.wrapping_mul(0x9e3779b97f4a7c15);
+                let mut rng = Pcg64::seed_from_u64(seed ^ self.turn as u64);
+                order.shuffle(&mut rng);
             }
         }
+        order
+    }
+
+    /// Applies one elevator's action. An `OPEN` without `drops` lets out everyone bound
+    /// for the current floor, which variants with `require_drops` reject.
+    pub fn apply_action(&mut self, elevator_idx: usize, action: &Action) -> Result<()> {
+        if elevator_idx >= self.m {
+            bail!("Invalid elevator index: {}", elevator_idx);
+        }
+        let cost = self.precheck(elevator_idx, action, self.budget_left())?;
+        let door_open = self.elevators[elevator_idx].door == DoorState::Open;
+
+        self.elevators[elevator_idx].direction = Direction::Idle;
+        if *action != Action::Stay {
+            self.elevators[elevator_idx].idle_turns = 0;
+        }
 
-        // Apply actions
-        for el_idx in 0..m {
-            if current_line >= output_lines.len() {
-                return Err(format!(
-                    "Output too short. Expected {} lines ({} turns * {} elevators), found {}.",
-                    t * m,
-                    t,
-                    m,
-                    output_lines.len()
-                ));
+        match action {
+            Action::Up => self.move_elevator(elevator_idx, Direction::Up),
+            Action::Down => self.move_elevator(elevator_idx, Direction::Down),
+            Action::Stay if door_open => self.elevators[elevator_idx].door = DoorState::Closed,
+            Action::Stay => self.park(elevator_idx),
+            Action::Open { picks, drops } => {
+                self.open(elevator_idx, picks, drops.as_deref())?;
+                if self.params.door_physics {
+                    self.elevators[elevator_idx].door = DoorState::Open;
+                }
             }
-            let line = output_lines[current_line].trim();
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if !parts.is_empty() {
-                let action = parts[0];
-                let picks: Vec<usize> = parts[1..].iter().map(|x| x.parse().unwrap_or(0)).collect();
-                sim.apply_action(el_idx, action, &picks)
-                    .map_err(|e| format!("Turn {}: {}", turn, e))?;
+        }
+        self.budget_used += cost;
+        self.check_comfort(elevator_idx);
+        self.check_ride_time(elevator_idx);
+        Ok(())
+    }
+
+    /// Checks that `actions`, one per elevator, would all apply in the action order,
+    /// without changing the state. [`SimulationState::step`] applies a turn only once
+    /// this passes, so that a turn is all or nothing.
+    pub fn check_turn(&self, actions: &[Action]) -> Result<()> {
+        if actions.len() != self.m {
+            bail!("Expected {} actions, got {}", self.m, actions.len());
+        }
+        let mut budget_left = self.budget_left();
+        // Detected passengers waiting at each floor, as the elevators before have left
+        // them this turn
+        let mut detected: Vec<Option<usize>> = vec![None; self.n];
+        for i in self.action_order() {
+            let mut check = || -> Result<()> {
+                let cost = self.precheck(i, &actions[i], budget_left)?;
+                budget_left = budget_left.map(|left| left - cost);
+                if let Action::Open { picks, drops } = &actions[i] {
+                    let floor = self.elevators[i].floor;
+                    let waiting = detected[floor].get_or_insert_with(|| self.detected_count(floor));
+                    let plan = self.plan_open(i, picks, drops.as_deref(), *waiting)?;
+                    *waiting = (*waiting + plan.requeued(&self.elevators[i].passengers, floor))
+                        - plan.boarded();
+                }
+                Ok(())
+            };
+            check()
+                .with_context(|| format!("Turn {}: Invalid action by elevator {}", self.turn, i))?;
+        }
+        Ok(())
+    }
+
+    /// The checks of [`SimulationState::apply_action`] that only depend on the
+    /// elevator itself, with `budget_left` of the budget. Returns the action's cost,
+    /// which the caller adds to `budget_used` once the action has succeeded.
+    fn precheck(
+        &self,
+        elevator_idx: usize,
+        action: &Action,
+        budget_left: Option<u64>,
+    ) -> Result<u64> {
+        let cost = match budget_left {
+            Some(left) => {
+                let cost = self.params.action_costs.of(action.name());
+                if cost > left {
+                    bail!(
+                        "{} costs {} but only {} budget is left",
+                        action.name(),
+                        cost,
+                        left
+                    );
+                }
+                cost
             }
-            current_line += 1;
+            None => 0,
+        };
+        let door_open = self.elevators[elevator_idx].door == DoorState::Open;
+        if door_open && matches!(action, Action::Up | Action::Down) {
+            bail!("Cannot move with the doors open; STAY closes them");
+        }
+        if let Action::Open { drops: None, .. } = action
+            && self.params.require_drops
+        {
+            bail!("OPEN must list the passengers to let out in this variant");
         }
+        Ok(cost)
+    }
+
+    /// [`SimulationState::apply_action`] by keyword (`UP`, `DOWN`, `STAY` or `OPEN`, which
+    /// boards `picks`), for the wasm layer.
+    pub fn apply_command(
+        &mut self,
+        elevator_idx: usize,
+        action: &str,
+        picks: &[usize],
+    ) -> Result<()> {
+        let action = match action {
+            "UP" => Action::Up,
+            "DOWN" => Action::Down,
+            "STAY" => Action::Stay,
+            "OPEN" => Action::open(picks),
+            _ => bail!("Unknown action: {}", action),
+        };
+        self.apply_action(elevator_idx, &action)
+    }
 
-        history.push(sim.create_snapshot());
+    /// Adds the comfort penalty if the elevator is crowded after its action.
+    fn check_comfort(&mut self, elevator_idx: usize) {
+        let Some(threshold) = self.params.comfort_threshold else {
+            return;
+        };
+        let load = self.elevators[elevator_idx].passengers.len();
+        let capacity = self.elevator_spec(elevator_idx).capacity;
+        if load as f64 > threshold * capacity as f64 {
+            let penalty = self.params.comfort_penalty;
+            self.log_event(Event::Crowded {
+                elevator: elevator_idx,
+                load,
+                penalty,
+            });
+            self.add_score(penalty, None, ScoreReason::Crowding);
+        }
     }
 
-    serde_wasm_bindgen::to_value(&history).map_err(|e| e.to_string())
-}
+    /// Adds the ride penalty for passengers still on board `max_ride` turns after boarding.
+    fn check_ride_time(&mut self, elevator_idx: usize) {
+        let Some(max_ride) = self.params.max_ride else {
+            return;
+        };
+        let late: Vec<usize> = self.elevators[elevator_idx]
+            .passengers
+            .iter()
+            .filter(|p| {
+                self.boarded_at
+                    .get(&p.id)
+                    .is_some_and(|&boarded| self.turn - boarded == max_ride)
+            })
+            .map(|p| p.id)
+            .collect();
+        for passenger_id in late {
+            let penalty = self.params.ride_penalty;
+            self.log_event(Event::RideTooLong {
+                passenger_id,
+                elevator: elevator_idx,
+                penalty,
+            });
+            self.add_score(penalty, Some(passenger_id), ScoreReason::LongRide);
+        }
+    }
 
-#[wasm_bindgen]
-#[allow(clippy::needless_range_loop)]
-pub fn generate_passengers_wasm(seed: u64) -> Result<JsValue, String> {
-    let mut rng = Pcg64::seed_from_u64(seed);
-    let n = 10;
-    let t = 100;
-    let lambda = 0.1;
-    let poi = Poisson::new(lambda).map_err(|e| e.to_string())?;
-    let target_dist = Uniform::new(0, n).map_err(|e| e.to_string())?;
-
-    let mut passenger_source: Vec<Vec<Vec<Passenger>>> = vec![vec![vec![]; t]; n];
-    let mut next_passenger_id = 0;
-
-    for i in 0..n {
-        for turn in 0..t {
-            let count: u32 = poi.sample(&mut rng) as u32;
-            for _ in 0..count {
-                let mut target = target_dist.sample(&mut rng);
-                while target == i {
-                    target = target_dist.sample(&mut rng);
+    /// Works out what elevator `elevator_idx`'s `OPEN` does when `detected` passengers
+    /// can be picked at its floor, failing on anything that makes it invalid.
+    fn plan_open(
+        &self,
+        elevator_idx: usize,
+        picks: &[usize],
+        drops: Option<&[usize]>,
+        detected: usize,
+    ) -> Result<OpenPlan> {
+        if self.elevators[elevator_idx].offset > 0 {
+            bail!("Elevator {} cannot open between floors", elevator_idx);
+        }
+        let current_floor = self.elevators[elevator_idx].floor;
+        if !self.specs[elevator_idx].serves(current_floor) {
+            bail!(
+                "Elevator {} does not serve floor {}",
+                elevator_idx,
+                current_floor
+            );
+        }
+        let capacity = self.elevators[elevator_idx].capacity;
+        if let Some(slack) = self.params.pick_slack
+            && picks.len() > capacity + slack
+        {
+            bail!(
+                "Elevator {} lists {} picks, more than its capacity of {} plus {} spare",
+                elevator_idx,
+                picks.len(),
+                capacity,
+                slack
+            );
+        }
+        let mut picked = vec![false; detected];
+        for &idx in picks {
+            if idx >= detected {
+                bail!("Invalid passenger index {} at floor {}", idx, current_floor);
+            }
+            if std::mem::replace(&mut picked[idx], true) {
+                bail!(
+                    "Passenger index {} is picked twice at floor {}",
+                    idx,
+                    current_floor
+                );
+            }
+        }
+        if let Some(limit) = self.specs[elevator_idx].max_boardings
+            && picks.len() > limit
+        {
+            bail!(
+                "Elevator {} can board at most {} passengers per OPEN",
+                elevator_idx,
+                limit
+            );
+        }
+        let onboard = &self.elevators[elevator_idx].passengers;
+        let mut alighting: Vec<bool> = match drops {
+            Some(drops) => {
+                let mut mask = vec![false; onboard.len()];
+                for &idx in drops {
+                    let Some(p) = onboard.get(idx) else {
+                        bail!("Invalid onboard passenger index {}", idx);
+                    };
+                    if p.target_floor != current_floor {
+                        bail!(
+                            "Onboard passenger {} is not bound for floor {}",
+                            idx,
+                            current_floor
+                        );
+                    }
+                    mask[idx] = true;
+                }
+                mask
+            }
+            None => onboard
+                .iter()
+                .map(|p| p.target_floor == current_floor)
+                .collect(),
+        };
+
+        // Noise mode: some passengers get off at the wrong floor, and some picks do not
+        // show up
+        let mut rng = self.noise_rng(elevator_idx);
+        if self.params.early_exit_prob > 0.0 {
+            let exit = Bernoulli::new(self.params.early_exit_prob)?;
+            for (alight, p) in alighting.iter_mut().zip(onboard) {
+                if p.target_floor != current_floor && exit.sample(&mut rng) {
+                    *alight = true;
                 }
-                passenger_source[i][turn].push(Passenger {
-                    id: next_passenger_id,
-                    arrival_turn: turn,
-                    target_floor: target,
-                });
-                next_passenger_id += 1;
             }
         }
+        let staying = alighting.iter().filter(|&&alight| !alight).count();
+        let no_show = Bernoulli::new(self.params.no_show_prob)?;
+        let mut boarded = 0;
+        // From the highest index down
+        let picks = (0..detected)
+            .rev()
+            .filter(|&idx| picked[idx])
+            .map(|idx| {
+                let boarding = if staying + boarded >= capacity {
+                    Boarding::Full
+                } else if self.params.no_show_prob > 0.0 && no_show.sample(&mut rng) {
+                    Boarding::NoShow
+                } else {
+                    boarded += 1;
+                    Boarding::Boards
+                };
+                (idx, boarding)
+            })
+            .collect();
+        Ok(OpenPlan { alighting, picks })
     }
 
-    serde_wasm_bindgen::to_value(&passenger_source).map_err(|e| e.to_string())
-}
+    fn open(
+        &mut self,
+        elevator_idx: usize,
+        picks: &[usize],
+        drops: Option<&[usize]>,
+    ) -> Result<()> {
+        let current_floor = self.elevators[elevator_idx].floor;
+        let plan = self.plan_open(
+            elevator_idx,
+            picks,
+            drops,
+            self.detected_count(current_floor),
+        )?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // 1. Drop off
+        let mut delivered = vec![];
+        let mut remaining = vec![];
+        for (p, &alight) in self.elevators[elevator_idx]
+            .passengers
+            .drain(..)
+            .zip(&plan.alighting)
+        {
+            if alight {
+                delivered.push(p);
+            } else {
+                remaining.push(p);
+            }
+        }
 
-    #[test]
-    fn test_elevator_movement() -> Result<()> {
-        let mut sim = SimulationState::new(10, 3, 10, 100);
-        sim.elevators[0].floor = 5;
-        sim.apply_action(0, "UP", &[])?;
-        assert_eq!(sim.elevators[0].floor, 6);
-        sim.apply_action(0, "DOWN", &[])?;
-        assert_eq!(sim.elevators[0].floor, 5);
+        for mut p in delivered {
+            if p.target_floor != current_floor {
+                self.log_event(Event::ExitedEarly {
+                    passenger_id: p.id,
+                    elevator: elevator_idx,
+                    floor: current_floor,
+                });
+                self.requeue(current_floor, p);
+                continue;
+            }
+            if !p.legs.is_empty() {
+                // Door-to-door time keeps counting from the original arrival
+                p.target_floor = p.legs.remove(0);
+                self.log_event(Event::Transferred {
+                    passenger_id: p.id,
+                    elevator: elevator_idx,
+                    floor: current_floor,
+                });
+                self.requeue(current_floor, p);
+                continue;
+            }
+            let penalty = p.penalty(self.turn + 1, self.params.warmup, self.scoring());
+            if self.logging {
+                self.stats.delivered(&p, Some(elevator_idx), self.turn);
+            }
+            self.log_event(Event::Delivered {
+                passenger_id: p.id,
+                elevator: elevator_idx,
+                floor: current_floor,
+                penalty,
+            });
+            self.add_score(penalty, Some(p.id), ScoreReason::Delivery);
+        }
+        self.elevators[elevator_idx].passengers = remaining;
+
+        // 2. Pick up, then take the boarders out of the line in one sweep. Passengers
+        // requeued by the drop-off come after every pick, so the indices still hold.
+        let mut pickup = Pickup {
+            turn: self.turn,
+            ..Pickup::default()
+        };
+        let mut boarding = vec![false; self.waiting_passengers[current_floor].len()];
+        for &(idx, outcome) in &plan.picks {
+            let passenger_id = self.waiting_passengers[current_floor][idx].id;
+            let (elevator, floor) = (elevator_idx, current_floor);
+            match outcome {
+                Boarding::Full => {
+                    self.log_event(Event::Skipped {
+                        passenger_id,
+                        elevator,
+                        floor,
+                    });
+                    pickup.skipped.push(passenger_id);
+                }
+                Boarding::NoShow => self.log_event(Event::NoShow {
+                    passenger_id,
+                    elevator,
+                    floor,
+                }),
+                Boarding::Boards => {
+                    self.log_event(Event::Boarded {
+                        passenger_id,
+                        elevator,
+                        floor,
+                    });
+                    boarding[idx] = true;
+                    pickup.boarded.push(passenger_id);
+                }
+            }
+        }
+        let line = std::mem::take(&mut self.waiting_passengers[current_floor]);
+        let mut riders = Vec::with_capacity(plan.boarded());
+        for (p, board) in line.into_iter().zip(boarding) {
+            if board {
+                riders.push(p);
+            } else {
+                self.waiting_passengers[current_floor].push(p);
+            }
+        }
+        for p in riders.into_iter().rev() {
+            self.calls[current_floor].remove(current_floor, &p);
+            if self.logging {
+                self.stats.boarded(&p, self.turn);
+            }
+            if self.params.max_ride.is_some() {
+                self.boarded_at.insert(p.id, self.turn);
+            }
+            self.elevators[elevator_idx].passengers.push(p);
+        }
+        pickup.boarded.reverse();
+        pickup.skipped.reverse();
+        self.pickups[elevator_idx] = pickup;
         Ok(())
     }
 
-    #[test]
-    fn test_invalid_elevator_index() {
-        let mut sim = SimulationState::new(10, 3, 10, 100);
-        assert!(sim.apply_action(3, "UP", &[]).is_err());
+    /// How each elevator's `OPEN` in the previous turn went; empty for the elevators
+    /// that did not open.
+    pub fn previous_pickups(&self) -> Vec<Pickup> {
+        self.pickups
+            .iter()
+            .map(|p| {
+                if p.turn + 1 == self.turn {
+                    p.clone()
+                } else {
+                    Pickup::default()
+                }
+            })
+            .collect()
     }
 
-    #[test]
-    fn test_invalid_passenger_pick() {
-        let mut sim = SimulationState::new(10, 3, 10, 100);
-        sim.elevators[0].floor = 0;
-        assert!(sim.apply_action(0, "OPEN", &[0]).is_err());
+    /// Noise-mode randomness for one elevator's `OPEN`, fixed by the seed, turn and
+    /// elevator so that it does not depend on the action order.
+    fn noise_rng(&self, elevator_idx: usize) -> Pcg64 {
+        let stream = ((self.turn as u64) << 16) | elevator_idx as u64;
+        Pcg64::seed_from_u64(self.params.noise_seed.wrapping_mul(0x9e3779b97f4a7c15) ^ stream)
     }
 
-    #[test]
-    fn test_delivery_score() -> Result<()> {
-        let mut sim = SimulationState::new(10, 3, 10, 100);
-        sim.turn = 10;
-        sim.elevators[0].floor = 1;
-        sim.elevators[0].passengers.push(Passenger {
-            id: 1,
-            arrival_turn: 5,
-            target_floor: 1,
-        });
-        sim.apply_action(0, "OPEN", &[])?;
-        // Duration = 10 - 5 + 1 = 6. Score = 6^2 = 36
-        assert_eq!(sim.score, 36);
-        assert!(sim.elevators[0].passengers.is_empty());
+    fn move_elevator(&mut self, elevator_idx: usize, direction: Direction) {
+        let travel_time = self.specs[elevator_idx].travel_time;
+        let top = self.n - 1;
+        let elevator = &mut self.elevators[elevator_idx];
+        match direction {
+            Direction::Up => {
+                if elevator.floor < top {
+                    elevator.offset += 1;
+                    if elevator.offset >= travel_time {
+                        elevator.floor += 1;
+                        elevator.offset = 0;
+                    }
+                    elevator.direction = Direction::Up;
+                }
+            }
+            Direction::Down => {
+                if elevator.offset > 0 {
+                    elevator.offset -= 1;
+                    elevator.direction = Direction::Down;
+                } else if elevator.floor > 0 {
+                    elevator.floor -= 1;
+                    elevator.offset = travel_time - 1;
+                    elevator.direction = Direction::Down;
+                }
+            }
+            Direction::Idle => {}
+        }
+        if elevator.direction != Direction::Idle {
+            let load = elevator.passengers.len();
+            let penalty = self.params.move_penalty + self.scoring().move_penalty(load);
+            self.add_score(penalty, None, ScoreReason::Movement);
+        }
+    }
+
+    fn park(&mut self, elevator_idx: usize) {
+        let Some(&home) = self.params.home_floors.get(elevator_idx) else {
+            return;
+        };
+        let elevator = &mut self.elevators[elevator_idx];
+        if !elevator.passengers.is_empty() {
+            elevator.idle_turns = 0;
+            return;
+        }
+        elevator.idle_turns += 1;
+        if elevator.idle_turns <= self.params.park_after {
+            return;
+        }
+
+        let from = elevator.floor;
+        let direction = if elevator.floor < home {
+            Direction::Up
+        } else if elevator.floor > home || elevator.offset > 0 {
+            Direction::Down
+        } else {
+            return;
+        };
+        self.move_elevator(elevator_idx, direction);
+        let to = self.elevators[elevator_idx].floor;
+        self.log_event(Event::Parked {
+            elevator: elevator_idx,
+            from,
+            to,
+        });
+    }
+
+    fn begin_log(&mut self) {
+        if self.log_turn != self.turn {
+            self.arrivals.clear();
+            self.events.clear();
+            self.log_turn = self.turn;
+        }
+    }
+
+    /// Events of the current turn so far.
+    pub fn current_events(&self) -> &[Event] {
+        if self.log_turn == self.turn {
+            &self.events
+        } else {
+            &[]
+        }
+    }
+
+    /// Applies the destination changes scripted for this turn to the passengers still
+    /// waiting, rerouting them through transfer floors as needed. A change to the floor
+    /// a passenger is waiting on is ignored.
+    pub fn change_destinations(&mut self) {
+        let changes: Vec<DestinationChange> = self
+            .params
+            .destination_changes
+            .iter()
+            .filter(|c| c.turn == self.turn)
+            .cloned()
+            .collect();
+        for change in changes {
+            let Some((floor, idx)) =
+                (0..self.n).find_map(|f| Some((f, self.waiting_index(f, change.passenger)?)))
+            else {
+                continue;
+            };
+            if change.target == floor {
+                continue;
+            }
+            let (target_floor, legs) = self.params.route(floor, change.target);
+            let p = &mut self.waiting_passengers[floor][idx];
+            self.calls[floor].remove(floor, p);
+            p.target_floor = target_floor;
+            p.legs = legs;
+            self.calls[floor].add(floor, p);
+            self.log_event(Event::TargetChanged {
+                passenger_id: change.passenger,
+                floor,
+                target: change.target,
+            });
+        }
+    }
+
+    /// Stops keeping arrivals and events, leaving snapshots without them, for callers
+    /// that only need the score.
+    pub(crate) fn without_log(mut self) -> Self {
+        self.logging = false;
+        self
+    }
+
+    /// Scores the run under `policy` instead of `SimParams::scoring`. Call it before the
+    /// first turn is played, as the score so far is not recomputed.
+    pub fn with_scoring(mut self, policy: impl ScoringPolicy + Send + Sync + 'static) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    fn scoring(&self) -> &dyn ScoringPolicy {
+        match &self.policy {
+            Some(policy) => policy.as_ref(),
+            None => &self.params.scoring,
+        }
+    }
+
+    /// Adds `amount` to the score, logging why.
+    fn add_score(&mut self, amount: u64, passenger_id: Option<usize>, reason: ScoreReason) {
+        self.components.add(reason, amount);
+        let amount = amount * self.params.weights.weight(reason);
+        if amount == 0 {
+            return;
+        }
+        self.score += amount;
+        self.log_event(Event::ScoreDelta {
+            passenger_id,
+            amount,
+            reason,
+        });
+    }
+
+    /// The state after the current turn's actions. On the last turn the penalties of
+    /// the passengers left unfinished are logged first, so that the events of a run
+    /// add up to its final score.
+    pub(crate) fn turn_snapshot(&mut self) -> Snapshot {
+        if self.turn + 1 != self.t {
+            return self.create_snapshot();
+        }
+        for event in self.final_score_deltas() {
+            self.log_event(event);
+        }
+        Snapshot {
+            statistics: Some(self.stats.statistics(self.t)),
+            ..self.create_snapshot()
+        }
+    }
+
+    /// Wait, ride and delivery turn of every passenger delivered so far, with the
+    /// longest and 95th-percentile waits and each elevator's deliveries per turn.
+    /// Empty for a simulation that keeps no log, as under [`crate::fast_score`].
+    pub fn statistics(&self) -> Statistics {
+        self.stats.statistics(self.turn)
+    }
+
+    fn log_event(&mut self, event: Event) {
+        if !self.logging {
+            return;
+        }
+        self.begin_log();
+        self.events.push(event);
+    }
+
+    /// Budget left under `action_budget`, if there is one.
+    pub fn budget_left(&self) -> Option<u64> {
+        self.params
+            .action_budget
+            .map(|b| b.saturating_sub(self.budget_used))
+    }
+
+    /// Records the current state as this turn's observation and returns what the agent
+    /// sees: the observation from `observation_delay` turns ago, or the oldest one
+    /// early in the run.
+    pub fn observe(&mut self) -> Snapshot {
+        let mut snapshot = self.create_snapshot();
+        self.hide_undetected(&mut snapshot);
+        self.observations.push_back(snapshot);
+        if self.observations.len() > self.params.observation_delay + 1 {
+            self.observations.pop_front();
+        }
+        self.observations[0].clone()
+    }
+
+    /// Adds a newly arrived passenger, keeping any remaining legs of their journey. One
+    /// already at their destination is rejected or, under [`SameFloorPolicy::Deliver`],
+    /// delivered on the spot.
+    pub fn push_arrival(&mut self, floor: usize, passenger: Passenger) -> Result<()> {
+        if floor >= self.n || passenger.destination() >= self.n {
+            bail!("Passenger {} has a floor out of range", passenger.id);
+        }
+        if passenger.destination() == floor {
+            if self.params.same_floor == SameFloorPolicy::Reject {
+                bail!(
+                    "Passenger {} arrives at floor {}, their own destination",
+                    passenger.id,
+                    floor
+                );
+            }
+            let penalty = passenger.penalty(self.turn + 1, self.params.warmup, self.scoring());
+            self.begin_log();
+            if self.logging {
+                self.stats.delivered(&passenger, None, self.turn);
+            }
+            self.log_event(Event::DeliveredOnArrival {
+                passenger_id: passenger.id,
+                floor,
+                penalty,
+            });
+            self.add_score(penalty, Some(passenger.id), ScoreReason::Delivery);
+            if self.logging {
+                self.arrivals.push(passenger);
+            }
+            return Ok(());
+        }
+        if self.logging {
+            self.begin_log();
+            self.arrivals.push(passenger.clone());
+        }
+        self.calls[floor].add(floor, &passenger);
+        self.waiting_passengers[floor].push(passenger);
+        Ok(())
+    }
+
+    /// Puts a passenger who got off before their destination back in line, ahead of
+    /// any arrivals agents cannot see yet, so the hidden ones stay at the end.
+    fn requeue(&mut self, floor: usize, passenger: Passenger) {
+        let at = self.detected_count(floor);
+        self.calls[floor].add(floor, &passenger);
+        self.waiting_passengers[floor].insert(at, passenger);
+    }
+
+    /// Waiting passengers at `floor` visible to agents: a prefix of the queue, since
+    /// arrivals still inside the detection delay are always at the end.
+    pub fn detected_count(&self, floor: usize) -> usize {
+        let delay = self.params.detection_delay;
+        self.waiting_passengers[floor]
+            .iter()
+            .position(|p| p.waited(self.turn) < delay)
+            .unwrap_or(self.waiting_passengers[floor].len())
+    }
+
+    fn hide_undetected(&self, snapshot: &mut Snapshot) {
+        if self.params.detection_delay == 0 {
+            return;
+        }
+        for (floor, f) in snapshot.floors.iter_mut().enumerate() {
+            f.waiting.truncate(self.detected_count(floor));
+            f.waiting_count = f.waiting.len();
+            f.up_calls = f.waiting.iter().filter(|p| p.target_floor > floor).count();
+            f.down_calls = f.waiting_count - f.up_calls;
+        }
+        let delay = self.params.detection_delay;
+        snapshot.arrivals.retain(|p| p.waited(self.turn) >= delay);
+    }
+
+    pub fn add_passenger(
+        &mut self,
+        floor: usize,
+        target: usize,
+        arrival_turn: usize,
+        id: usize,
+    ) -> Result<()> {
+        self.push_arrival(
+            floor,
+            Passenger {
+                id,
+                arrival_turn,
+                target_floor: target,
+                legs: vec![],
+                waited_before: 0,
+            },
+        )
+    }
+
+    pub fn waiting_index(&self, floor: usize, passenger_id: usize) -> Option<usize> {
+        self.waiting_passengers[floor]
+            .iter()
+            .position(|p| p.id == passenger_id)
+    }
+
+    pub fn onboard_index(&self, elevator_idx: usize, passenger_id: usize) -> Option<usize> {
+        self.elevators[elevator_idx]
+            .passengers
+            .iter()
+            .position(|p| p.id == passenger_id)
+    }
+
+    /// Up and down calls at each floor, with the longest wait among them, kept up to date
+    /// as passengers arrive and board rather than counted from the waiting lines. Counts
+    /// arrivals still hidden by the detection delay.
+    pub fn call_summary(&self) -> Vec<CallSummary> {
+        self.calls
+            .iter()
+            .map(|calls| CallSummary {
+                up: calls.up,
+                down: calls.down,
+                oldest_wait: calls
+                    .started
+                    .keys()
+                    .next()
+                    .map_or(0, |&start| (self.turn as isize - start).max(0) as usize),
+            })
+            .collect()
+    }
+
+    /// The `ScoreDelta` events that [`SimulationState::calculate_final_score`] adds to
+    /// the score: one for each passenger still waiting or riding.
+    pub fn final_score_deltas(&self) -> Vec<Event> {
+        self.unfinished()
+            .map(|p| Event::ScoreDelta {
+                passenger_id: Some(p.id),
+                amount: p.penalty(self.t, self.params.warmup, self.scoring())
+                    * self.params.weights.wait,
+                reason: ScoreReason::Unfinished,
+            })
+            .filter(|event| !matches!(event, Event::ScoreDelta { amount: 0, .. }))
+            .collect()
+    }
+
+    /// The final score by component, before weighting: the components so far plus the
+    /// penalties of the passengers still waiting or riding.
+    pub fn final_components(&self) -> ScoreComponents {
+        let mut components = self.components;
+        components.wait += self
+            .unfinished()
+            .map(|p| p.penalty(self.t, self.params.warmup, self.scoring()))
+            .sum::<u64>();
+        components
+    }
+
+    fn unfinished(&self) -> impl Iterator<Item = &Passenger> {
+        self.waiting_passengers
+            .iter()
+            .flatten()
+            .chain(self.elevators.iter().flat_map(|e| &e.passengers))
+    }
+
+    pub fn waited_turns(&self, passenger: &Passenger) -> usize {
+        passenger.waited(self.turn)
+    }
+
+    pub fn create_snapshot(&self) -> Snapshot {
+        Snapshot {
+            turn: self.turn,
+            score: self.score,
+            elevators: self
+                .elevators
+                .iter()
+                .zip(&self.specs)
+                .enumerate()
+                .map(|(i, (e, spec))| ElevatorSnapshot {
+                    label: self.params.elevator_label(i).map(String::from),
+                    floor: e.floor,
+                    position: e.floor as f64 + e.offset as f64 / spec.travel_time as f64,
+                    direction: e.direction,
+                    door: e.door,
+                    passenger_count: e.passengers.len(),
+                    passengers: e.passengers.clone(),
+                })
+                .collect(),
+            floors: self
+                .waiting_passengers
+                .iter()
+                .enumerate()
+                .map(|(floor, f)| FloorSnapshot {
+                    label: self.params.floor_labels.get(floor).cloned(),
+                    waiting_count: f.len(),
+                    waiting: f.clone(),
+                    up_calls: self.calls[floor].up,
+                    down_calls: self.calls[floor].down,
+                })
+                .collect(),
+            arrivals: if self.log_turn == self.turn {
+                self.arrivals.clone()
+            } else {
+                vec![]
+            },
+            events: if self.log_turn == self.turn {
+                self.events.clone()
+            } else {
+                vec![]
+            },
+            budget_left: self.budget_left(),
+            statistics: None,
+            annotations: vec![],
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SimulationState {
+    #[wasm_bindgen(constructor)]
+    pub fn new_js(n: usize, m: usize, c: usize, t: usize) -> Result<SimulationState, String> {
+        Self::new(n, m, c, t).map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen]
+    pub fn apply_action_wasm(
+        &mut self,
+        elevator_idx: usize,
+        action: &str,
+        picks: &[usize],
+    ) -> Result<(), String> {
+        self.apply_command(elevator_idx, action, picks)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Applies one action line per elevator together: if any of them is invalid, the
+    /// state is left as it was.
+    #[wasm_bindgen]
+    pub fn apply_turn_wasm(&mut self, text: &str, lenient: Option<bool>) -> Result<(), String> {
+        let actions = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                if i >= self.m {
+                    bail!("Expected {} actions, got more", self.m);
+                }
+                self.parse_action(i, line, lenient.unwrap_or(false))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| e.to_string())?;
+        apply_turn(self, &actions).map_err(|e| format!("{:#}", e))
+    }
+
+    #[wasm_bindgen]
+    pub fn calculate_final_score(&self) -> u64 {
+        self.final_components().total(&self.params.weights)
+    }
+
+    /// How much the score grows through the penalty of passenger `passenger_id`,
+    /// waiting or riding, if they are delivered `turns` turns later than this turn.
+    /// `None` if they are neither.
+    #[wasm_bindgen]
+    pub fn marginal_penalty_if_delayed(&self, passenger_id: usize, turns: usize) -> Option<u64> {
+        let passenger = self.unfinished().find(|p| p.id == passenger_id)?;
+        let penalty =
+            passenger.marginal_penalty(self.turn + 1, turns, self.params.warmup, self.scoring());
+        Some(penalty * self.params.weights.wait)
+    }
+
+    /// [`SimulationState::statistics`], for the visualizer.
+    #[wasm_bindgen]
+    pub fn statistics_wasm(&self) -> Result<JsValue, String> {
+        serde_wasm_bindgen::to_value(&self.statistics()).map_err(|e| e.to_string())
+    }
+
+    /// What the passengers still waiting or riding would add to the score if the run
+    /// ended now, as `ScoreDelta` events.
+    #[wasm_bindgen]
+    pub fn final_score_deltas_wasm(&self) -> Result<JsValue, String> {
+        serde_wasm_bindgen::to_value(&self.final_score_deltas()).map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_elevator_floor(&self, idx: usize) -> usize {
+        self.elevators[idx].floor
+    }
+
+    #[wasm_bindgen]
+    pub fn get_elevator_passenger_count(&self, idx: usize) -> usize {
+        self.elevators[idx].passengers.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_elevator_passenger_target(&self, elevator_idx: usize, p_idx: usize) -> usize {
+        self.elevators[elevator_idx].passengers[p_idx].target_floor
+    }
+
+    #[wasm_bindgen]
+    pub fn get_elevator_passenger_waited(&self, elevator_idx: usize, p_idx: usize) -> usize {
+        self.waited_turns(&self.elevators[elevator_idx].passengers[p_idx])
+    }
+
+    #[wasm_bindgen]
+    pub fn get_waiting_passenger_count(&self, floor: usize) -> usize {
+        self.waiting_passengers[floor].len()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_waiting_passenger_target(&self, floor: usize, p_idx: usize) -> usize {
+        self.waiting_passengers[floor][p_idx].target_floor
+    }
+
+    #[wasm_bindgen]
+    pub fn get_waiting_passenger_arrival_turn(&self, floor: usize, p_idx: usize) -> usize {
+        self.waiting_passengers[floor][p_idx].arrival_turn
+    }
+
+    #[wasm_bindgen]
+    pub fn get_waiting_passenger_waited(&self, floor: usize, p_idx: usize) -> usize {
+        self.waited_turns(&self.waiting_passengers[floor][p_idx])
+    }
+
+    #[wasm_bindgen(js_name = add_passenger)]
+    pub fn add_passenger_js(
+        &mut self,
+        floor: usize,
+        target: usize,
+        arrival_turn: usize,
+        id: usize,
+    ) -> Result<(), String> {
+        self.add_passenger(floor, target, arrival_turn, id)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn seed_instance(seed: u64, variant: Option<String>) -> Result<Instance, String> {
+    let variant: ProblemVariant = match variant {
+        Some(name) => name.parse().map_err(|e: anyhow::Error| e.to_string())?,
+        None => ProblemVariant::Classic,
+    };
+    // Pre-generate all passengers for all floors and turns to match local_judge exactly
+    let params = variant.params(&SimParams::default());
+    Instance::generate(seed, &params).map_err(|e| e.to_string())
+}
+
+fn replay_seed(
+    seed: u64,
+    output_text: &str,
+    lenient: bool,
+    variant: Option<String>,
+) -> Result<ReplayReport, String> {
+    Ok(seed_instance(seed, variant)?.replay(output_text, lenient))
+}
+
+/// Replays an output and returns its snapshots. An invalid line fails the replay unless
+/// `recover` is set, in which case that elevator stays for the turn and the replay goes
+/// on; `score_output_wasm` lists what went wrong.
+#[wasm_bindgen]
+pub fn run_simulation_wasm(
+    seed: u64,
+    output_text: &str,
+    lenient: Option<bool>,
+    variant: Option<String>,
+    recover: Option<bool>,
+) -> Result<JsValue, String> {
+    let report = replay_seed(seed, output_text, lenient.unwrap_or(false), variant)?;
+    if let Some(d) = report
+        .diagnostics
+        .first()
+        .filter(|_| !recover.unwrap_or(false))
+    {
+        return Err(format!("Turn {}: {}", d.turn, d.message));
+    }
+    serde_wasm_bindgen::to_value(&report.history).map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn run_history_wasm(
+    seed: u64,
+    output_text: &str,
+    lenient: Option<bool>,
+    variant: Option<String>,
+    recover: Option<bool>,
+) -> Result<History, String> {
+    let instance = seed_instance(seed, variant)?;
+    let report = instance.replay(output_text, lenient.unwrap_or(false));
+    if let Some(d) = report
+        .diagnostics
+        .first()
+        .filter(|_| !recover.unwrap_or(false))
+    {
+        return Err(format!("Turn {}: {}", d.turn, d.message));
+    }
+    Ok(History::new(report.history, instance.params.c))
+}
+
+#[wasm_bindgen]
+pub fn score_output_wasm(
+    seed: u64,
+    output_text: &str,
+    lenient: Option<bool>,
+    variant: Option<String>,
+) -> Result<JsValue, String> {
+    let mut report = replay_seed(seed, output_text, lenient.unwrap_or(false), variant)?;
+    report.history.clear();
+    report.observations.clear();
+    serde_wasm_bindgen::to_value(&report).map_err(|e| e.to_string())
+}
+
+/// What an agent producing `output_text` was sent each turn, for stepping through its
+/// view of the run.
+#[wasm_bindgen]
+pub fn observations_wasm(
+    seed: u64,
+    output_text: &str,
+    lenient: Option<bool>,
+    variant: Option<String>,
+) -> Result<JsValue, String> {
+    let report = replay_seed(seed, output_text, lenient.unwrap_or(false), variant)?;
+    serde_wasm_bindgen::to_value(&report.observations).map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn run_instance_wasm(
+    input_text: &str,
+    output_text: &str,
+    lenient: Option<bool>,
+    recover: Option<bool>,
+) -> Result<JsValue, String> {
+    let instance = Instance::parse(input_text).map_err(|e| e.to_string())?;
+    let report = instance.replay(output_text, lenient.unwrap_or(false));
+    if let Some(d) = report
+        .diagnostics
+        .first()
+        .filter(|_| !recover.unwrap_or(false))
+    {
+        return Err(format!("Turn {}: {}", d.turn, d.message));
+    }
+    serde_wasm_bindgen::to_value(&report.history).map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn score_request_wasm(request: JsValue) -> Result<JsValue, String> {
+    let request: ScoreRequest =
+        serde_wasm_bindgen::from_value(request).map_err(|e| e.to_string())?;
+    let mut report = request.replay().map_err(|e| e.to_string())?;
+    report.history.clear();
+    report.observations.clear();
+    serde_wasm_bindgen::to_value(&report).map_err(|e| e.to_string())
+}
+
+/// What this build supports; see [`Spec`].
+#[wasm_bindgen]
+pub fn spec_wasm() -> Result<JsValue, String> {
+    serde_wasm_bindgen::to_value(&spec()).map_err(|e| e.to_string())
+}
+
+/// Names of the problem variants, for selecting one in the visualizer.
+#[wasm_bindgen]
+pub fn problem_variants_wasm() -> Vec<String> {
+    ProblemVariant::ALL
+        .iter()
+        .map(|v| v.name().to_string())
+        .collect()
+}
+
+/// The tutorial's lessons in order, with their goals, for the web frontend.
+#[wasm_bindgen]
+pub fn lessons_wasm() -> Result<JsValue, String> {
+    serde_wasm_bindgen::to_value(&Lesson::all()).map_err(|e| e.to_string())
+}
+
+/// Lesson `name`'s instance as v2 input, for [`run_instance_wasm`].
+#[wasm_bindgen]
+pub fn lesson_input_wasm(name: &str) -> Result<String, String> {
+    let lesson = Lesson::get(name).map_err(|e| e.to_string())?;
+    lesson
+        .instance()
+        .and_then(|instance| instance.to_v2())
+        .map_err(|e| e.to_string())
+}
+
+/// [`Lesson::check`]: which goals `output_text` meets, and its snapshots for playback.
+#[wasm_bindgen]
+pub fn check_lesson_wasm(name: &str, output_text: &str) -> Result<JsValue, String> {
+    let lesson = Lesson::get(name).map_err(|e| e.to_string())?;
+    let report = lesson.check(output_text).map_err(|e| e.to_string())?;
+    serde_wasm_bindgen::to_value(&report).map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn generate_passengers_wasm(seed: u64) -> Result<JsValue, String> {
+    let params = SimParams::default();
+    let passenger_source = generate_passengers(seed, &params).map_err(|e| e.to_string())?;
+    serde_wasm_bindgen::to_value(&passenger_source).map_err(|e| e.to_string())
+}
+
+/// The arrivals of a seed as a flat `[{turn, floor, target, id}]` list.
+#[wasm_bindgen]
+pub fn spawn_schedule_wasm(seed: u64) -> Result<JsValue, String> {
+    let schedule = spawn_schedule(seed, &SimParams::default()).map_err(|e| e.to_string())?;
+    serde_wasm_bindgen::to_value(&schedule).map_err(|e| e.to_string())
+}
+
+/// Scores of a batch of outputs, for a Web Worker: the scores come back as one typed
+/// array and the packed histories (see [`History::compress`]) end to end in one byte
+/// buffer, so the worker can transfer both to the main thread without copying.
+#[wasm_bindgen]
+pub struct BatchScores {
+    scores: Vec<u64>,
+    errors: Vec<String>,
+    histories: Vec<u8>,
+    offsets: Vec<u32>,
+}
+
+impl BatchScores {
+    /// Replays `outputs[i]` on the instance of `seeds[i]`. An output with invalid lines
+    /// gets its partial score and the first problem in `errors` (empty when valid).
+    pub fn score(
+        seeds: &[u64],
+        outputs: &[String],
+        lenient: bool,
+        variant: Option<String>,
+        histories: bool,
+    ) -> Result<Self, String> {
+        if seeds.len() != outputs.len() {
+            return Err(format!(
+                "Got {} seeds but {} outputs",
+                seeds.len(),
+                outputs.len()
+            ));
+        }
+        let mut batch = Self {
+            scores: Vec::with_capacity(seeds.len()),
+            errors: Vec::with_capacity(seeds.len()),
+            histories: vec![],
+            offsets: vec![0],
+        };
+        for (&seed, output) in seeds.iter().zip(outputs) {
+            let instance = seed_instance(seed, variant.clone())?;
+            let report = instance.replay(output, lenient);
+            batch.scores.push(report.score);
+            batch.errors.push(
+                report
+                    .diagnostics
+                    .first()
+                    .map_or(String::new(), |d| format!("Turn {}: {}", d.turn, d.message)),
+            );
+            if histories {
+                let packed = History::new(report.history, instance.params.c)
+                    .compress()
+                    .map_err(|e| e.to_string())?;
+                batch.histories.extend(packed);
+            }
+            batch.offsets.push(batch.histories.len() as u32);
+        }
+        Ok(batch)
+    }
+}
+
+#[wasm_bindgen]
+impl BatchScores {
+    #[wasm_bindgen(getter)]
+    pub fn scores(&self) -> Vec<u64> {
+        self.scores.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.clone()
+    }
+
+    /// Where each run's history starts in `take_histories()`, plus its end: run `i`
+    /// is `offsets[i]..offsets[i + 1]`, empty without histories.
+    #[wasm_bindgen(getter)]
+    pub fn offsets(&self) -> Vec<u32> {
+        self.offsets.clone()
+    }
+
+    /// Moves the packed histories out, leaving the batch without them; each run's
+    /// bytes are read back with `History.decompress`.
+    pub fn take_histories(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.histories)
+    }
+}
+
+/// Scores `outputs[i]` on seed `seeds[i]` in one call; see [`BatchScores`].
+#[wasm_bindgen]
+pub fn score_batch_wasm(
+    seeds: &[u64],
+    outputs: Vec<String>,
+    lenient: Option<bool>,
+    variant: Option<String>,
+    histories: Option<bool>,
+) -> Result<BatchScores, String> {
+    BatchScores::score(
+        seeds,
+        &outputs,
+        lenient.unwrap_or(false),
+        variant,
+        histories.unwrap_or(false),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_ascii() -> Result<()> {
+        let mut sim = SimulationState::new(3, 2, 10, 10)?;
+        sim.add_passenger(1, 2, 0, 0)?;
+        sim.add_passenger(0, 2, 0, 1)?;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        sim.apply_action(1, &Action::Down)?;
+        assert_eq!(
+            sim.create_snapshot().render_ascii(),
+            "turn 0 score 0\n2 |   .   . | 0\n1 | # 1   . | 0\n0 |   . v 0 | 1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_elevator_movement() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.elevators[0].floor = 5;
+        sim.apply_action(0, &Action::Up)?;
+        assert_eq!(sim.elevators[0].floor, 6);
+        sim.apply_action(0, &Action::Down)?;
+        assert_eq!(sim.elevators[0].floor, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_elevator_index() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        assert!(sim.apply_action(3, &Action::Up).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_passenger_pick() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.elevators[0].floor = 0;
+        assert!(sim.apply_action(0, &Action::open(&[0])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delivery_score() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.turn = 10;
+        sim.elevators[0].floor = 1;
+        sim.elevators[0].passengers.push(Passenger {
+            id: 1,
+            arrival_turn: 5,
+            target_floor: 1,
+            legs: vec![],
+            waited_before: 0,
+        });
+        sim.apply_action(0, &Action::open(&[]))?;
+        // Duration = 10 - 5 + 1 = 6. Score = 6^2 = 36
+        assert_eq!(sim.score, 36);
+        assert!(sim.elevators[0].passengers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_arrivals() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.turn = 3;
+        sim.elevators[0].floor = 2;
+        sim.add_passenger(2, 7, 3, 0)?;
+        sim.add_passenger(4, 1, 3, 1)?;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        let snapshot = sim.create_snapshot();
+        let ids: Vec<usize> = snapshot.arrivals.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+
+        sim.turn = 4;
+        assert!(sim.create_snapshot().arrivals.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_waited_turns() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.turn = 2;
+        sim.add_passenger(5, 8, 2, 0)?;
+        assert_eq!(sim.get_waiting_passenger_waited(5, 0), 0);
+        sim.turn = 6;
+        assert_eq!(sim.get_waiting_passenger_waited(5, 0), 4);
+        sim.apply_action(0, &Action::open(&[0]))?;
+        assert_eq!(sim.get_elevator_passenger_waited(0, 0), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_travel_time() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            travel_time: 2,
+            ..SimParams::default()
+        });
+        sim.elevators[0].floor = 5;
+        sim.apply_action(0, &Action::Up)?;
+        assert_eq!(sim.elevators[0].floor, 5);
+        assert!(sim.apply_action(0, &Action::open(&[])).is_err());
+        assert_eq!(sim.create_snapshot().elevators[0].position, 5.5);
+        sim.apply_action(0, &Action::Up)?;
+        assert_eq!(sim.elevators[0].floor, 6);
+        sim.apply_action(0, &Action::Down)?;
+        assert_eq!(sim.create_snapshot().elevators[0].position, 5.5);
+        assert_eq!(
+            sim.create_snapshot().elevators[0].direction,
+            Direction::Down
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parking() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            home_floors: vec![0, 9, 5],
+            park_after: 1,
+            ..SimParams::default()
+        });
+        sim.apply_action(0, &Action::Stay)?;
+        assert_eq!(sim.elevators[0].floor, 5);
+        sim.turn = 1;
+        sim.apply_action(0, &Action::Stay)?;
+        assert_eq!(sim.elevators[0].floor, 4);
+        assert_eq!(
+            sim.create_snapshot().events,
+            vec![Event::Parked {
+                elevator: 0,
+                from: 5,
+                to: 4
+            }]
+        );
+
+        // Elevator 2 is already home and never moves
+        sim.apply_action(2, &Action::Stay)?;
+        sim.apply_action(2, &Action::Stay)?;
+        assert_eq!(sim.elevators[2].floor, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_selective_dropoff() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.turn = 4;
+        for id in 0..3 {
+            sim.elevators[0].passengers.push(Passenger {
+                id,
+                arrival_turn: 0,
+                target_floor: if id == 2 { 7 } else { 5 },
+                legs: vec![],
+                waited_before: 0,
+            });
+        }
+        let drop = |id| Action::Open {
+            picks: vec![],
+            drops: Some(vec![id]),
+        };
+        assert!(sim.apply_action(0, &drop(2)).is_err());
+        sim.apply_action(0, &drop(1))?;
+        assert_eq!(sim.score, 25);
+        let ids: Vec<usize> = sim.elevators[0].passengers.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_floor_calls() -> Result<()> {
+        let mut sim = SimulationState::new(10, 3, 10, 100)?;
+        sim.add_passenger(4, 9, 0, 0)?;
+        sim.add_passenger(4, 5, 0, 1)?;
+        sim.add_passenger(4, 0, 0, 2)?;
+        let floor = &sim.create_snapshot().floors[4];
+        assert_eq!((floor.up_calls, floor.down_calls), (2, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_degenerate_params() -> Result<()> {
+        assert!(SimulationState::new(0, 1, 10, 10).is_err());
+        assert!(SimulationState::new(10, 0, 10, 10).is_err());
+        assert!(SimulationState::new(10, 1, 10, 0).is_err());
+        assert!(Instance::parse("0 1 10 10 0.1\n").is_err());
+
+        // A single floor: no passengers are generated and moves are no-ops
+        let params = SimParams {
+            n: 1,
+            m: 1,
+            t: 5,
+            ..SimParams::default()
+        };
+        let instance = Instance::generate(0, &params)?;
+        assert!(instance.passengers[0].iter().all(Vec::is_empty));
+        let mut sim = SimulationState::try_from_params(&params)?;
+        sim.apply_action(0, &Action::Up)?;
+        sim.apply_action(0, &Action::Down)?;
+        sim.apply_action(0, &Action::open(&[]))?;
+        assert_eq!(sim.elevators[0].floor, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_floor_arrivals() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 10, 100)?;
+        assert!(sim.add_passenger(4, 4, 0, 0).is_err());
+        assert!(sim.add_passenger(4, 10, 0, 0).is_err());
+        assert!(sim.waiting_passengers[4].is_empty());
+
+        let mut sim = SimulationState::from_params(&SimParams {
+            same_floor: SameFloorPolicy::Deliver,
+            ..SimParams::default()
+        });
+        sim.turn = 2;
+        sim.add_passenger(4, 4, 2, 0)?;
+        assert!(sim.waiting_passengers[4].is_empty());
+        assert_eq!(sim.score, 1);
+        assert_eq!(sim.create_snapshot().arrivals.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_action_order() {
+        let mut params = SimParams {
+            m: 4,
+            action_order: ActionOrder::Reverse,
+            ..SimParams::default()
+        };
+        assert_eq!(
+            SimulationState::from_params(&params).action_order(),
+            vec![3, 2, 1, 0]
+        );
+
+        params.action_order = ActionOrder::Random;
+        let mut sim = SimulationState::from_params(&params);
+        sim.turn = 7;
+        let order = sim.action_order();
+        assert_eq!(order, sim.action_order());
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, vec![0, 1, 2, 3]);
+
+        // Neighbouring seeds do not replay each other's orders a turn apart
+        let orders = |order_seed: u64, flip: usize| -> Vec<Vec<usize>> {
+            let mut sim = SimulationState::from_params(&SimParams {
+                order_seed,
+                ..params.clone()
+            });
+            (0..16)
+                .map(|turn| {
+                    sim.turn = turn ^ flip;
+                    sim.action_order()
+                })
+                .collect()
+        };
+        assert_ne!(orders(6, 0), orders(7, 1));
+    }
+
+    #[test]
+    fn test_heterogeneous_fleet() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            n: 4,
+            m: 2,
+            t: 5,
+            fleet: vec![
+                ElevatorSpec {
+                    capacity: 1,
+                    travel_time: 1,
+                    floors: vec![],
+                    max_boardings: None,
+                },
+                ElevatorSpec {
+                    capacity: 4,
+                    travel_time: 2,
+                    floors: vec![0, 3],
+                    max_boardings: None,
+                },
+            ],
+            ..SimParams::default()
+        });
+        sim.add_passenger(2, 0, 0, 0)?;
+        sim.add_passenger(2, 0, 0, 1)?;
+
+        // Elevator 1 does not stop at floor 2
+        assert!(sim.apply_action(1, &Action::open(&[0])).is_err());
+        // Elevator 0 only has room for one
+        sim.apply_action(0, &Action::open(&[0, 1]))?;
+        assert_eq!(sim.get_elevator_passenger_count(0), 1);
+
+        sim.apply_action(0, &Action::Up)?;
+        sim.apply_action(1, &Action::Up)?;
+        assert_eq!(sim.get_elevator_floor(0), 3);
+        assert_eq!(sim.get_elevator_floor(1), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_elevator_labels() {
+        let sim = SimulationState::from_params(&SimParams {
+            m: 2,
+            labels: vec!["service car".into(), "express A".into()],
+            ..SimParams::default()
+        });
+        let snapshot = sim.create_snapshot();
+        assert_eq!(snapshot.elevators[1].label.as_deref(), Some("express A"));
+        let stats = RunStats::from_history(&[snapshot], 10);
+        assert_eq!(stats.labels, vec!["service car", "express A"]);
+    }
+
+    #[test]
+    fn test_transfer_floor_journey() -> Result<()> {
+        // A low bank (floors 0-2) and a high bank (floors 2-4) meeting at floor 2
+        let params = SimParams {
+            n: 5,
+            m: 2,
+            t: 9,
+            fleet: vec![
+                ElevatorSpec {
+                    capacity: 4,
+                    travel_time: 1,
+                    floors: vec![0, 1, 2],
+                    max_boardings: None,
+                },
+                ElevatorSpec {
+                    capacity: 4,
+                    travel_time: 1,
+                    floors: vec![2, 3, 4],
+                    max_boardings: None,
+                },
+            ],
+            transfer_floors: vec![2],
+            ..SimParams::default()
+        };
+        let instance = Instance::from_targets(params, {
+            let mut targets = vec![vec![vec![]; 9]; 5];
+            targets[0][0].push(4);
+            targets
+        })?;
+        let p = &instance.passengers[0][0][0];
+        assert_eq!((p.target_floor, p.legs.clone()), (2, vec![4]));
+
+        // Low car: down to 0, pick up, up to 2 and drop; high car picks up at 2 and goes to 4
+        let report = instance.replay(
+            "DOWN\nSTAY\nDOWN\nSTAY\nOPEN 0\nSTAY\nUP\nSTAY\nUP\nSTAY\nOPEN\nOPEN 0\nSTAY\nUP\nSTAY\nUP\nSTAY\nOPEN\n",
+            false,
+        );
+        assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+        assert!(matches!(
+            report.history[5].events[..],
+            [
+                Event::Transferred { floor: 2, .. },
+                Event::Boarded { elevator: 1, .. }
+            ]
+        ));
+        // Delivered at turn 8 after arriving at turn 0: (8 - 0 + 1)^2
+        assert_eq!(report.score, 81);
+        Ok(())
+    }
+
+    #[test]
+    fn test_noise_mode() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            no_show_prob: 1.0,
+            early_exit_prob: 1.0,
+            ..SimParams::default()
+        });
+        sim.add_passenger(5, 8, 0, 0)?;
+        sim.elevators[0].passengers.push(Passenger {
+            id: 1,
+            arrival_turn: 0,
+            target_floor: 9,
+            legs: vec![],
+            waited_before: 0,
+        });
+        sim.apply_action(0, &Action::open(&[0]))?;
+
+        assert_eq!(sim.get_elevator_passenger_count(0), 0);
+        let ids: Vec<usize> = sim.waiting_passengers[5].iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        let events = sim.create_snapshot().events;
+        assert!(matches!(
+            events[..],
+            [
+                Event::ExitedEarly {
+                    passenger_id: 1,
+                    ..
+                },
+                Event::NoShow {
+                    passenger_id: 0,
+                    ..
+                }
+            ]
+        ));
+        assert_eq!(sim.score, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_observation_delay() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            observation_delay: 2,
+            ..SimParams::default()
+        });
+        for turn in 0..4 {
+            sim.turn = turn;
+            let observed = sim.observe();
+            assert_eq!(observed.turn, turn.saturating_sub(2));
+            sim.apply_action(0, &Action::Up)?;
+        }
+        // Two observations back: the start of turn 2
+        assert_eq!(sim.observe().elevators[0].floor, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_comfort_penalty() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            m: 1,
+            c: 4,
+            comfort_threshold: Some(0.5),
+            comfort_penalty: 10,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        for id in 0..3 {
+            sim.add_passenger(floor, floor + 1, 0, id)?;
+        }
+        // Two of four is not above half
+        sim.apply_action(0, &Action::open(&[0, 1]))?;
+        assert_eq!(sim.score, 0);
+        sim.turn = 1;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        assert_eq!(sim.score, 10);
+        assert_eq!(
+            sim.current_events()[sim.current_events().len() - 2..],
+            [
+                Event::Crowded {
+                    elevator: 0,
+                    load: 3,
+                    penalty: 10
+                },
+                Event::ScoreDelta {
+                    passenger_id: None,
+                    amount: 10,
+                    reason: ScoreReason::Crowding
+                }
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_step() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let mut sim = SimulationState::from_instance(&instance)?;
+        assert_eq!(sim.get_waiting_passenger_count(2), 1);
+        // A turn with the wrong number of actions changes nothing
+        assert!(sim.step(&[Action::Up, Action::Up]).is_err());
+        assert_eq!((sim.turn, sim.get_waiting_passenger_count(2)), (0, 1));
+
+        let reports = ["OPEN 0", "UP", "OPEN"]
+            .iter()
+            .map(|line| sim.step(&[line.parse()?]))
+            .collect::<Result<Vec<_>>>()?;
+        let delivered: Vec<_> = reports.iter().map(|r| r.delivered.clone()).collect();
+        assert_eq!(delivered, vec![vec![], vec![], vec![0]]);
+        assert_eq!(reports[2].score_gained, 9);
+        assert_eq!(sim.turn, 3);
+        assert!(sim.step(&[Action::Stay]).is_err());
+        assert_eq!(
+            sim.calculate_final_score(),
+            instance.replay("OPEN 0\nUP\nOPEN", false).score
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_scores() -> Result<()> {
+        let outputs = vec!["STAY\n".repeat(300), "UP\nDOWN\nOPEN\n".repeat(100)];
+        assert!(BatchScores::score(&[1], &outputs, false, None, false).is_err());
+        let mut batch =
+            BatchScores::score(&[1, 2], &outputs, false, None, true).map_err(anyhow::Error::msg)?;
+        let histories = batch.take_histories();
+        let offsets = batch.offsets();
+        assert_eq!(offsets.len(), 3);
+        for (i, (seed, output)) in [1, 2].into_iter().zip(&outputs).enumerate() {
+            let report = Instance::generate(seed, &SimParams::default())?.replay(output, false);
+            assert_eq!(batch.scores()[i], report.score);
+            assert!(batch.errors()[i].is_empty());
+            let packed = &histories[offsets[i] as usize..offsets[i + 1] as usize];
+            assert_eq!(
+                History::decompress(packed)?.snapshots().len(),
+                report.history.len()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_door_physics() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            m: 1,
+            door_physics: true,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        sim.add_passenger(floor, floor + 1, 0, 0)?;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        assert_eq!(sim.create_snapshot().elevators[0].door, DoorState::Open);
+        assert!(sim.apply_action(0, &Action::Up).is_err());
+        // Closing takes a turn of its own
+        sim.apply_action(0, &Action::Stay)?;
+        assert_eq!(sim.elevators[0].door, DoorState::Closed);
+        assert_eq!(sim.elevators[0].floor, floor);
+        sim.apply_action(0, &Action::Up)?;
+        assert_eq!(sim.elevators[0].floor, floor + 1);
+
+        // Without the flag the doors close within the OPEN's turn
+        let mut instant = SimulationState::new(10, 1, 10, 5)?;
+        instant.apply_action(0, &Action::open(&[]))?;
+        instant.apply_action(0, &Action::Up)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_marginal_penalty() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 10, 20)?;
+        sim.turn = 3;
+        sim.add_passenger(2, 7, 1, 4)?;
+        // Delivered now after 3 turns (9), or after 5 turns (25)
+        assert_eq!(sim.marginal_penalty_if_delayed(4, 2), Some(16));
+        assert_eq!(sim.marginal_penalty_if_delayed(4, 0), Some(0));
+        assert_eq!(sim.marginal_penalty_if_delayed(5, 2), None);
+        let p = &sim.waiting_passengers[2][0];
+        assert_eq!(p.marginal_penalty(4, 2, 0, &Scoring::Linear), 2);
+        // Weighted as in the score
+        sim.params.weights.wait = 3;
+        assert_eq!(sim.marginal_penalty_if_delayed(4, 2), Some(48));
+        Ok(())
+    }
+
+    #[test]
+    fn test_score_weights() -> Result<()> {
+        let mut instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        instance.params.move_penalty = 5;
+        instance.params.weights = "wait=2,movement=3".parse()?;
+        let report = instance.replay("OPEN 0\nUP\nSTAY", false);
+        // Unfinished after 3 turns, and one turn moving
+        let components = ScoreComponents {
+            wait: 9,
+            movement: 5,
+            ..ScoreComponents::default()
+        };
+        assert_eq!(report.components, components);
+        assert_eq!(report.score, 2 * 9 + 3 * 5);
+        let deltas: u64 = report
+            .history
+            .iter()
+            .flat_map(|s| &s.events)
+            .filter_map(|e| match e {
+                Event::ScoreDelta { amount, .. } => Some(amount),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(deltas, report.score);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoring_policies() -> Result<()> {
+        let mut instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let mut score = |scoring| {
+            instance.params.scoring = scoring;
+            instance.replay("OPEN 0\nUP\nOPEN", false).score
+        };
+        // A trip of 3 turns, with one turn moving one passenger
+        assert_eq!(score(Scoring::Quadratic), 9);
+        assert_eq!(score(Scoring::Linear), 3);
+        assert_eq!(score(Scoring::Capped { cap: 5 }), 5);
+        assert_eq!(score(Scoring::EnergyWeighted { weight: 2 }), 13);
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_scoring() -> Result<()> {
+        struct Cubic;
+        impl ScoringPolicy for Cubic {
+            fn trip_penalty(&self, turns: usize) -> u64 {
+                (turns as u64).pow(3)
+            }
+            fn move_penalty(&self, load: usize) -> u64 {
+                10 * load as u64
+            }
+        }
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n")?;
+        let mut sim = SimulationState::from_instance(&instance)?.with_scoring(Cubic);
+        for line in ["OPEN 0", "UP", "OPEN"] {
+            sim.step(&[line.parse()?])?;
+        }
+        // A trip of 3 turns, with one turn moving one passenger
+        assert_eq!(sim.calculate_final_score(), 27 + 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_score_deltas() -> Result<()> {
+        let instance = Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n1 0 0 0\n")?;
+        let report = instance.replay("OPEN 0\nUP\nOPEN", false);
+        let deltas: Vec<_> = report
+            .history
+            .iter()
+            .flat_map(|s| &s.events)
+            .filter_map(|event| match event {
+                Event::ScoreDelta { amount, reason, .. } => Some((*amount, *reason)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![(9, ScoreReason::Delivery), (9, ScoreReason::Unfinished)]
+        );
+        assert_eq!(
+            deltas.iter().map(|(amount, _)| amount).sum::<u64>(),
+            report.score
+        );
+        // The last turn's deltas are logged only once the turn is played
+        let sim = SimulationState::from_instance(&instance)?;
+        assert_eq!(sim.final_score_deltas().len(), 2);
+        assert!(sim.create_snapshot().events.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_summary() -> Result<()> {
+        let instance = Instance::parse(
+            "6 1 10 4 0.1\n0 1 2 0 0\n0 0 0 0\n0 0 0 0\n2 5 1 0 1 4 0\n0 0 0 0\n0 0 0 0\n",
+        )?;
+        let mut sim = SimulationState::from_instance(&instance)?;
+        let calls = |up, down, oldest_wait| CallSummary {
+            up,
+            down,
+            oldest_wait,
+        };
+        let mut expected = vec![
+            vec![calls(0, 0, 0), calls(1, 1, 0)],
+            vec![calls(1, 0, 0), calls(1, 0, 1)],
+            vec![calls(1, 0, 1), calls(2, 0, 2)],
+        ]
+        .into_iter();
+        for line in ["OPEN 1", "STAY", "STAY"] {
+            let summary = sim.call_summary();
+            assert_eq!(
+                vec![summary[0], summary[3]],
+                expected.next().unwrap(),
+                "turn {}",
+                sim.turn
+            );
+            let snapshot = sim.create_snapshot();
+            for (floor, f) in snapshot.floors.iter().enumerate() {
+                let up = f.waiting.iter().filter(|p| p.target_floor > floor).count();
+                assert_eq!((f.up_calls, f.down_calls), (up, f.waiting.len() - up));
+            }
+            sim.step(&[line.parse()?])?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_arrivals() -> Result<()> {
+        let input = "4 1 10 3 0.1\n0 0 0\n0 0 0\n0 1 3 0\n0 0 0\n";
+        let mut sim = SimulationState::new(4, 1, 10, 3)?;
+        assert!(
+            sim.load_arrivals_from_str("5 1 10 3 0.1\n0 0 0\n0 0 0\n0 0 0\n0 0 0\n0 0 0\n")
+                .is_err()
+        );
+        sim.load_arrivals_from_str(input)?;
+        assert_eq!(sim.get_waiting_passenger_count(2), 0);
+        sim.step(&[Action::Stay])?;
+        // Brought in at turn 1, as scheduled
+        assert_eq!(sim.get_waiting_passenger_count(2), 1);
+        assert_eq!(sim.get_waiting_passenger_arrival_turn(2, 0), 1);
+
+        // A schedule set later skips the turns already played
+        let mut late = SimulationState::new(4, 1, 10, 3)?;
+        late.step(&[Action::Stay])?;
+        late.step(&[Action::Stay])?;
+        late.load_arrivals_from_str(input)?;
+        assert_eq!(late.get_waiting_passenger_count(2), 0);
+        assert!(late.set_arrival_schedule(vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_ride() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            m: 1,
+            max_ride: Some(2),
+            ride_penalty: 1000,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        sim.add_passenger(floor, floor + 1, 0, 0)?;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        // Past the floor and back: still on board two turns after boarding
+        for turn in 1..=2 {
+            sim.turn = turn;
+            sim.apply_action(0, &Action::Up)?;
+        }
+        assert_eq!(sim.score, 1000);
+        assert_eq!(
+            sim.current_events()[sim.current_events().len() - 2..],
+            [
+                Event::RideTooLong {
+                    passenger_id: 0,
+                    elevator: 0,
+                    penalty: 1000
+                },
+                Event::ScoreDelta {
+                    passenger_id: Some(0),
+                    amount: 1000,
+                    reason: ScoreReason::LongRide
+                }
+            ]
+        );
+        // The penalty applies once per ride
+        sim.turn = 3;
+        sim.apply_action(0, &Action::Down)?;
+        assert_eq!(sim.score, 1000);
+
+        // A limit of 0 would charge every ride as it boards
+        let zero = SimParams {
+            max_ride: Some(0),
+            ..SimParams::default()
+        };
+        assert!(SimulationState::try_from_params(&zero).is_err());
+        assert!(Instance::generate(0, &zero).is_err());
+        // With a limit of 1 the boarding turn is free and the next one is not
+        let mut sim = SimulationState::from_params(&SimParams {
+            m: 1,
+            max_ride: Some(1),
+            ride_penalty: 1000,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        sim.add_passenger(floor, floor + 1, 0, 0)?;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        assert_eq!(sim.score, 0);
+        sim.turn = 1;
+        sim.apply_action(0, &Action::Up)?;
+        assert_eq!(sim.score, 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_warmup() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            m: 1,
+            t: 10,
+            warmup: 3,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        sim.add_passenger(floor, floor + 1, 0, 0)?;
+        sim.add_passenger(floor, floor + 2, 0, 1)?;
+        sim.add_passenger(floor, floor + 3, 0, 2)?;
+        sim.apply_action(0, &Action::open(&[0, 1, 2]))?;
+        sim.turn = 1;
+        sim.apply_action(0, &Action::Up)?;
+        // Delivered within the warm-up: no penalty
+        sim.turn = 2;
+        sim.apply_action(0, &Action::open(&[]))?;
+        assert_eq!(sim.score, 0);
+        sim.turn = 3;
+        sim.apply_action(0, &Action::Up)?;
+        // Only turns 3 and 4 count: 2^2, not 5^2
+        sim.turn = 4;
+        sim.apply_action(0, &Action::open(&[]))?;
+        assert_eq!(sim.score, 4);
+        // Never delivered: turns 3 to 9
+        assert_eq!(sim.calculate_final_score(), 4 + 49);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detection_delay() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            detection_delay: 2,
+            ..SimParams::default()
+        });
+        let floor = sim.elevators[0].floor;
+        sim.add_passenger(floor, 0, 0, 0)?;
+        sim.turn = 1;
+        sim.add_passenger(floor, 0, 1, 1)?;
+        assert_eq!(sim.observe().floors[floor].waiting_count, 0);
+        assert!(sim.apply_action(0, &Action::open(&[0])).is_err());
+
+        sim.turn = 2;
+        let observed = sim.observe();
+        assert_eq!(observed.floors[floor].waiting_count, 1);
+        assert_eq!(observed.floors[floor].waiting[0].id, 0);
+        assert!(sim.apply_action(0, &Action::open(&[1])).is_err());
+        sim.apply_action(0, &Action::open(&[0]))?;
+        assert_eq!(sim.elevators[0].passengers[0].arrival_turn, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_action_budget() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            action_budget: Some(3),
+            action_costs: ActionCosts {
+                open: 2,
+                up: 1,
+                ..ActionCosts::default()
+            },
+            ..SimParams::default()
+        });
+        sim.apply_action(0, &Action::open(&[]))?;
+        sim.apply_action(1, &Action::Stay)?;
+        assert!(sim.apply_action(1, &Action::open(&[])).is_err());
+        sim.apply_action(1, &Action::Up)?;
+        assert_eq!(sim.create_snapshot().budget_left, Some(0));
+        assert!(sim.apply_action(2, &Action::Up).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_boardings() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            max_boardings: Some(2),
+            ..SimParams::default()
+        });
+        for id in 0..3 {
+            sim.add_passenger(5, 7, 0, id)?;
+        }
+        assert!(sim.apply_action(0, &Action::open(&[0, 1, 2])).is_err());
+        sim.apply_action(0, &Action::open(&[0, 1]))?;
+        assert_eq!(sim.elevators[0].passengers.len(), 2);
+        assert_eq!(sim.waiting_passengers[5].len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_list() -> Result<()> {
+        let mut sim = SimulationState::from_params(&SimParams {
+            c: 2,
+            pick_slack: Some(1),
+            ..SimParams::default()
+        });
+        for id in 10..14 {
+            sim.add_passenger(5, 7, 0, id)?;
+        }
+        assert!(sim.apply_action(0, &Action::open(&[0, 1, 2, 3])).is_err());
+        assert!(sim.apply_action(0, &Action::open(&[1, 1])).is_err());
+        // The highest picks board first; the rest wait, in their order
+        sim.apply_action(0, &Action::open(&[0, 3, 2]))?;
+        let ids = |ps: &[Passenger]| ps.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(&sim.elevators[0].passengers), [13, 12]);
+        assert_eq!(ids(&sim.waiting_passengers[5]), [10, 11]);
+        assert_eq!(sim.pickups[0].boarded, [12, 13]);
+        assert_eq!(sim.pickups[0].skipped, [10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let instance = Instance::generate(5, &SimParams::default())?;
+        let mut sim = SimulationState::from_instance(&instance)?;
+        sim.step(&[Action::Up, Action::Down, Action::Stay])?;
+        let saved = sim.checkpoint();
+        let fork = sim.fork();
+        for _ in 0..10 {
+            sim.step(&[Action::Up, Action::Stay, Action::Down])?;
+        }
+        assert_eq!(sim.turn, 11);
+        sim.rollback(saved);
+        assert_eq!((sim.turn, sim.score), (1, fork.score));
+        assert_eq!(
+            sim.create_snapshot().render_ascii(),
+            fork.create_snapshot().render_ascii()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_snapshot() -> Result<()> {
+        let instance = Scenario::new(5, 1)
+            .turns(10)
+            .passenger(2, 4, 0)
+            .passenger(2, 0, 0)
+            .instance()?;
+        let mut sim = SimulationState::from_instance(&instance)?;
+        sim.step(&[Action::open(&[0])])?;
+        let mut copy = SimulationState::from_snapshot(&instance.params, &sim.create_snapshot())?;
+        assert_eq!((copy.turn, copy.score), (1, 0));
+        for action in [Action::Up, Action::Up, Action::open(&[])] {
+            let gained = sim.step(std::slice::from_ref(&action))?.score_gained;
+            assert_eq!(copy.step(&[action])?.score_gained, gained);
+        }
+        assert_eq!(copy.score, 16);
+        assert_eq!(
+            copy.create_snapshot().render_ascii(),
+            sim.create_snapshot().render_ascii()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_turn_matches_apply() -> Result<()> {
+        let mut sim = SimulationState::new(6, 2, 2, 10)?;
+        sim.add_passenger(3, 5, 0, 0)?;
+        // Aboard elevator 0, changing elevators at its floor
+        sim.elevators[0].passengers.push(Passenger {
+            id: 1,
+            arrival_turn: 0,
+            target_floor: 3,
+            legs: vec![0],
+            waited_before: 0,
+        });
+        let turns = [
+            "OPEN 0\nOPEN 0",
+            "OPEN 0\nOPEN 1",
+            "OPEN\nOPEN 1",
+            "OPEN 0 1\nSTAY",
+            "OPEN / 0\nUP",
+        ];
+        for turn in turns {
+            let actions: Vec<Action> = turn.lines().map(str::parse).collect::<Result<_>>()?;
+            let checked = sim.check_turn(&actions);
+            let mut applied = sim.fork();
+            let result = sim
+                .action_order()
+                .into_iter()
+                .try_for_each(|i| applied.apply_action(i, &actions[i]));
+            assert_eq!(checked.is_ok(), result.is_ok(), "{}", turn);
+        }
+        // The rejected turn changes nothing
+        assert!(sim.step(&[Action::open(&[0]), Action::open(&[1])]).is_err());
+        assert_eq!(
+            (
+                sim.waiting_passengers[3].len(),
+                sim.elevators[0].passengers.len()
+            ),
+            (1, 1)
+        );
         Ok(())
     }
 }
@@ -1,3 +1,443 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use elevator_sim::{
+    AgentCommand, InputVersion, Instance, JudgeOptions, PROTOCOL_VERSION, ReferenceTable,
+    ScoreRequest, SimParams, Snapshot, Tier, TraceArrivals, convert_input, fast_score, run_agent,
+    spec, verify_output,
+};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Convert an input file between format versions
+    Convert {
+        input_file: String,
+        /// Target format version (1 or 2)
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..=2))]
+        to: u32,
+        /// Write to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Run a reference agent over a seed range and write a checksummed score table
+    ReferenceTable {
+        /// First seed (inclusive)
+        #[clap(long, default_value_t = 0)]
+        start: u64,
+        /// Last seed (exclusive)
+        #[clap(long)]
+        end: u64,
+        /// Write to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<String>,
+        command: String,
+        #[clap(trailing_var_arg = true)]
+        args: Vec<String>,
+    },
+    /// Estimate how hard each input is and tag it with a difficulty tier
+    Difficulty {
+        /// Input files (v1 or v2)
+        #[clap(required = true)]
+        input_files: Vec<String>,
+    },
+    /// Turn a traffic log (CSV of time,origin,destination) into a v2 input
+    Trace {
+        csv_file: String,
+        /// Length of one turn in the log's time
+        #[clap(long)]
+        seconds_per_turn: f64,
+        /// Number of turns; by default just enough to cover the log
+        #[clap(long)]
+        turns: Option<usize>,
+        /// Comma-separated floor names from the bottom, as used in the log
+        #[clap(long, value_delimiter = ',')]
+        floor_labels: Option<Vec<String>>,
+        /// Write to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Time generation, replay and the per-turn protocol on a tall building
+    Bench {
+        #[clap(long, default_value_t = 1000)]
+        floors: usize,
+        #[clap(long, default_value_t = 3)]
+        elevators: usize,
+        #[clap(long, default_value_t = 1000)]
+        turns: usize,
+        #[clap(long, default_value_t = 0.001)]
+        lambda: f64,
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Print the protocol and format versions and defaults this build supports, as JSON
+    Spec,
+    /// Check that the judge and the wasm scorer agree on an output, turn by turn
+    Verify {
+        output_file: String,
+        /// Seed of a generated instance
+        #[clap(long, required_unless_present = "input", conflicts_with = "input")]
+        seed: Option<u64>,
+        /// Input file (v1 or v2) instead of a seed
+        #[clap(long)]
+        input: Option<String>,
+        /// Accept lower-case and abbreviated actions
+        #[clap(long)]
+        lenient: bool,
+        /// Final score the judge reported for this output
+        #[clap(long)]
+        score: u64,
+        /// Snapshots the judge saved with `local_judge --save-history`
+        #[clap(long)]
+        history: Option<String>,
+    },
+    /// Serve the judge over HTTP: POST /score and GET /metrics
+    #[cfg(feature = "server")]
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// Requests judged at the same time
+        #[clap(long, default_value_t = 4)]
+        threads: usize,
+        /// File of API keys, one per line; scoring then needs `Authorization: Bearer KEY`
+        #[clap(long)]
+        api_keys: Option<String>,
+        /// Most episodes one client may start per hour
+        #[clap(long)]
+        per_hour: Option<usize>,
+        /// Most episodes one client may have running at once
+        #[clap(long)]
+        concurrent: Option<usize>,
+        /// Secret the hidden contest seeds are derived from; enables /episodes
+        #[clap(long)]
+        secret: Option<String>,
+        /// Number of hidden provisional cases
+        #[clap(long, default_value_t = 50, requires = "secret")]
+        provisional_cases: usize,
+        /// Number of hidden system-test cases
+        #[clap(long, default_value_t = 500, requires = "secret")]
+        system_cases: usize,
+        /// Which hidden cases to play: provisional or system
+        #[clap(long, default_value = "provisional", requires = "secret")]
+        split: elevator_sim::Split,
+        /// Most episodes one client may start on each hidden case
+        #[clap(long, requires = "secret")]
+        plays_per_case: Option<usize>,
+    },
+    /// Query the runs recorded by local_judge --results-db
+    #[cfg(feature = "results-db")]
+    Results {
+        /// SQLite database written by the local judge
+        #[clap(long)]
+        db: String,
+        #[command(subcommand)]
+        query: ResultsQuery,
+    },
+}
+
+#[cfg(feature = "results-db")]
+#[derive(Subcommand)]
+enum ResultsQuery {
+    /// The best run on each seed under each set of parameters, across all agents
+    Best,
+    /// Total score of every submission over the seeds it was run on, best first
+    Standings,
+    /// Every run of one agent, oldest first
+    History {
+        /// Agent command line as recorded, e.g. "./agent --fast"
+        agent: String,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Convert {
+            input_file,
+            to,
+            output,
+        } => {
+            let text = std::fs::read_to_string(&input_file)
+                .with_context(|| format!("Failed to read input file: {}", input_file))?;
+            let version = if to == 1 {
+                InputVersion::V1
+            } else {
+                InputVersion::V2
+            };
+            let converted = convert_input(&text, version)?;
+            match output {
+                Some(path) => std::fs::write(&path, converted)
+                    .with_context(|| format!("Failed to write {}", path))?,
+                None => print!("{}", converted),
+            }
+        }
+        Commands::ReferenceTable {
+            start,
+            end,
+            output,
+            command,
+            args,
+        } => {
+            let agent = AgentCommand::new(&command, &args);
+            let mut scores = BTreeMap::new();
+            for seed in start..end {
+                let instance = Instance::generate(seed, &SimParams::default())?;
+                let score = run_agent(
+                    &instance,
+                    &agent,
+                    &JudgeOptions::default(),
+                    None,
+                    &mut |_| {},
+                )
+                .with_context(|| format!("Reference agent failed on seed {}", seed))?
+                .score;
+                eprintln!("Seed {}: {}", seed, score);
+                scores.insert(seed, score);
+            }
+            let name = std::iter::once(&command)
+                .chain(&args)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let json = ReferenceTable::new(&name, scores).to_json()?;
+            match output {
+                Some(path) => std::fs::write(&path, json + "\n")
+                    .with_context(|| format!("Failed to write {}", path))?,
+                None => println!("{}", json),
+            }
+        }
+        Commands::Difficulty { input_files } => {
+            let mut counts: BTreeMap<Tier, usize> = BTreeMap::new();
+            for path in &input_files {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read input file: {}", path))?;
+                let difficulty = Instance::parse(&text)
+                    .with_context(|| format!("Invalid input file: {}", path))?
+                    .difficulty();
+                println!("{}: {}", path, difficulty);
+                *counts.entry(difficulty.tier).or_default() += 1;
+            }
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(tier, count)| format!("{} {}", tier, count))
+                .collect();
+            println!("Tiers: {}", counts.join(", "));
+        }
+        Commands::Trace {
+            csv_file,
+            seconds_per_turn,
+            turns,
+            floor_labels,
+            output,
+        } => {
+            let text = std::fs::read_to_string(&csv_file)
+                .with_context(|| format!("Failed to read trace: {}", csv_file))?;
+            let mut params = SimParams::default();
+            if let Some(labels) = floor_labels {
+                params.n = labels.len();
+                params.floor_labels = labels;
+            }
+            params.t = match turns {
+                Some(turns) => turns,
+                None => TraceArrivals::span(&text, &params, seconds_per_turn)?,
+            };
+            let mut trace = TraceArrivals::parse(&text, &params, seconds_per_turn)?;
+            if trace.dropped > 0 {
+                eprintln!(
+                    "Skipped {} arrivals outside the turns or going nowhere",
+                    trace.dropped
+                );
+            }
+            let input = Instance::from_process(&params, &mut trace)?.to_v2()?;
+            match output {
+                Some(path) => std::fs::write(&path, input)
+                    .with_context(|| format!("Failed to write {}", path))?,
+                None => print!("{}", input),
+            }
+        }
+        Commands::Bench {
+            floors,
+            elevators,
+            turns,
+            lambda,
+            seed,
+        } => {
+            let params = SimParams {
+                n: floors,
+                m: elevators,
+                t: turns,
+                lambda,
+                ..SimParams::default()
+            };
+            let started = Instant::now();
+            let instance = Instance::generate(seed, &params)?;
+            let per_turn = |started: Instant| started.elapsed().as_micros() / turns as u128;
+            println!("Generate: {} us/turn", per_turn(started));
+
+            // Everyone keeps waiting, the worst case for the size of observations
+            let started = Instant::now();
+            let report = instance.replay(&"STAY\n".repeat(elevators * turns), false);
+            println!("Replay: {} us/turn", per_turn(started));
+
+            let started = Instant::now();
+            fast_score(&instance, &[])?;
+            println!("Fast score: {} us/turn", per_turn(started));
+
+            // Protocol 7 is the last to send every floor's waiting line
+            for protocol in [7, PROTOCOL_VERSION] {
+                let started = Instant::now();
+                let mut sink = vec![];
+                for observation in &report.observations {
+                    observation.write(&mut sink, protocol, None)?;
+                }
+                println!(
+                    "Protocol {}: {} us/turn, {} bytes/turn",
+                    protocol,
+                    per_turn(started),
+                    sink.len() / turns
+                );
+            }
+        }
+        Commands::Spec => println!("{}", serde_json::to_string_pretty(&spec())?),
+        Commands::Verify {
+            output_file,
+            seed,
+            input,
+            lenient,
+            score,
+            history,
+        } => {
+            let read = |path: &str| {
+                std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))
+            };
+            let snapshots: Vec<Snapshot> = match history {
+                Some(path) => serde_json::from_str(&read(&path)?)
+                    .with_context(|| format!("Invalid history file: {}", path))?,
+                None => vec![],
+            };
+            let request = ScoreRequest {
+                seed,
+                input: input.as_deref().map(read).transpose()?,
+                output: read(&output_file)?,
+                lenient,
+            };
+            let hashes = verify_output(
+                &request.instance()?,
+                &request.output,
+                lenient,
+                score,
+                &snapshots,
+            )?;
+            println!(
+                "OK: {} turns agree (final state {})",
+                hashes.len(),
+                hashes.last().map_or("-", String::as_str)
+            );
+        }
+        #[cfg(feature = "server")]
+        Commands::Serve {
+            addr,
+            threads,
+            api_keys,
+            per_hour,
+            concurrent,
+            secret,
+            provisional_cases,
+            system_cases,
+            split,
+            plays_per_case,
+        } => {
+            let mut server = elevator_sim::JudgeServer::new();
+            if let Some(path) = api_keys {
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read API keys: {}", path))?;
+                let keys: Vec<String> = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if keys.is_empty() {
+                    anyhow::bail!("No API keys in {}", path);
+                }
+                server = server.api_keys(keys);
+            }
+            if let Some(limit) = per_hour {
+                server = server.per_hour(limit);
+            }
+            if let Some(limit) = concurrent {
+                server = server.concurrent(limit);
+            }
+            if let Some(limit) = plays_per_case {
+                server = server.plays_per_case(limit);
+            }
+            if let Some(secret) = secret {
+                let contest = elevator_sim::Contest::new(&secret, provisional_cases, system_cases);
+                server = server.contest(contest.split(split));
+            }
+            eprintln!("Listening on http://{}", addr);
+            server.serve(&addr, threads)?;
+        }
+        #[cfg(feature = "results-db")]
+        Commands::Results { db, query } => {
+            let db = elevator_sim::ResultsDb::open(&db)
+                .with_context(|| format!("Failed to open results database: {}", db))?;
+            let show = |value: Option<u64>| value.map_or("-".to_string(), |v| v.to_string());
+            match query {
+                ResultsQuery::Best => {
+                    let mut params = None;
+                    for run in db.best()? {
+                        if params.as_ref() != Some(&run.params) {
+                            println!("Params {}", run.params);
+                            params = Some(run.params.clone());
+                        }
+                        println!(
+                            "Seed {}: {} by {} ({})",
+                            run.seed,
+                            show(run.score),
+                            run.agent,
+                            run.agent_hash.as_deref().unwrap_or("-")
+                        );
+                    }
+                }
+                ResultsQuery::Standings => {
+                    for (rank, s) in db.standings()?.iter().enumerate() {
+                        println!(
+                            "{}. {} ({}): total {} over {} cases{}",
+                            rank + 1,
+                            s.agent,
+                            s.agent_hash.as_deref().unwrap_or("-"),
+                            s.total,
+                            s.cases,
+                            match s.failures {
+                                0 => String::new(),
+                                f => format!(", {} failed", f),
+                            }
+                        );
+                    }
+                }
+                ResultsQuery::History { agent } => {
+                    for run in db.history(&agent)? {
+                        println!(
+                            "{} {} seed {}: {} in {} ms ({})",
+                            run.recorded_at,
+                            run.agent_hash.as_deref().unwrap_or("-"),
+                            run.seed,
+                            show(run.score),
+                            run.duration_ms,
+                            run.verdict
+                        );
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
 }
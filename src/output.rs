@@ -0,0 +1,312 @@
+use crate::{Action, Diagnostic, SimulationState};
+use anyhow::{Context, Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One untagged line per elevator per turn (`OPEN 0 3`).
+    V1,
+    /// Self-describing lines (`turn 12 elev 1 OPEN id 37 id 41`). Omitted entries are `STAY`.
+    V2,
+}
+
+impl OutputFormat {
+    pub fn detect(output_text: &str) -> Self {
+        let first = output_text
+            .lines()
+            .find(|line| !line.trim().is_empty() && annotation(line).is_none());
+        match first.and_then(|line| line.split_whitespace().next()) {
+            Some("turn") => OutputFormat::V2,
+            _ => OutputFormat::V1,
+        }
+    }
+}
+
+/// The text of an annotation line (`# heading to floor 7 for id 23`). Agents may write
+/// these between their actions; they are kept for display and never simulated.
+pub fn annotation(line: &str) -> Option<&str> {
+    line.trim_start().strip_prefix('#').map(str::trim)
+}
+
+/// One elevator's action for one turn, as written in an output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub line: usize,
+    pub action: Action,
+    /// Passengers to board, referenced by ID (v2 `id N` tokens).
+    pub pick_ids: Vec<usize>,
+    /// Onboard passengers to let out, referenced by ID (v2 `id N` tokens after `/`).
+    pub drop_ids: Vec<usize>,
+}
+
+impl Command {
+    /// Turns ID references into indices against the current state, appending them to
+    /// the index-based picks and drops.
+    pub fn resolve(&self, sim: &SimulationState, elevator_idx: usize) -> Result<Action> {
+        if self.pick_ids.is_empty() && self.drop_ids.is_empty() {
+            return Ok(self.action.clone());
+        }
+        let Action::Open { picks, drops } = &self.action else {
+            bail!("Passenger IDs are only allowed with OPEN");
+        };
+        let floor = sim.get_elevator_floor(elevator_idx);
+        let mut picks = picks.clone();
+        for &id in &self.pick_ids {
+            picks.push(
+                sim.waiting_index(floor, id).with_context(|| {
+                    format!("Passenger {} is not waiting at floor {}", id, floor)
+                })?,
+            );
+        }
+        let mut drops = drops.clone();
+        if !self.drop_ids.is_empty() {
+            let drops = drops.get_or_insert_with(Vec::new);
+            for &id in &self.drop_ids {
+                drops.push(sim.onboard_index(elevator_idx, id).with_context(|| {
+                    format!("Passenger {} is not in elevator {}", id, elevator_idx)
+                })?);
+            }
+        }
+        Ok(Action::Open { picks, drops })
+    }
+}
+
+pub struct ParsedOutput {
+    pub format: OutputFormat,
+    /// `commands[turn][elevator]`; `None` means the elevator stays.
+    pub commands: Vec<Vec<Option<Command>>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// `annotations[turn]`: the agent's `#` lines, attached to the turn of the action
+    /// that follows them.
+    pub annotations: Vec<Vec<String>>,
+}
+
+/// Parses an output file in either format. Lines that cannot be parsed are reported as
+/// diagnostics and leave the corresponding entry empty.
+pub fn parse_output(output_text: &str, t: usize, m: usize, lenient: bool) -> ParsedOutput {
+    let format = OutputFormat::detect(output_text);
+    let mut commands = vec![vec![None; m]; t];
+    let mut diagnostics = vec![];
+    let mut annotations = vec![vec![]; t];
+    let mut pending = vec![];
+    let mut annotate = |turn: usize, pending: &mut Vec<String>| {
+        if let Some(notes) = annotations.get_mut(turn.min(t.saturating_sub(1))) {
+            notes.append(pending);
+        }
+    };
+
+    match format {
+        OutputFormat::V1 => {
+            let mut output_lines = vec![];
+            for (line_idx, line) in output_text.trim().split('\n').enumerate() {
+                match annotation(line) {
+                    Some(note) => pending.push(note.to_string()),
+                    None => {
+                        annotate(output_lines.len() / m, &mut pending);
+                        output_lines.push((line_idx + 1, line));
+                    }
+                }
+            }
+            annotate(output_lines.len().saturating_sub(1) / m, &mut pending);
+            for (action_idx, &(line_no, line)) in output_lines.iter().enumerate().take(t * m) {
+                let (turn, elevator) = (action_idx / m, action_idx % m);
+                match Action::parse_line(line, lenient) {
+                    Ok(action) => {
+                        commands[turn][elevator] = Some(Command {
+                            line: line_no,
+                            action,
+                            pick_ids: vec![],
+                            drop_ids: vec![],
+                        })
+                    }
+                    Err(e) => diagnostics.push(Diagnostic {
+                        line: line_no,
+                        turn,
+                        elevator,
+                        label: None,
+                        message: e.to_string(),
+                    }),
+                }
+            }
+            if output_lines.len() < t * m {
+                diagnostics.push(Diagnostic {
+                    line: output_lines.last().map_or(1, |&(line_no, _)| line_no + 1),
+                    turn: output_lines.len() / m,
+                    elevator: output_lines.len() % m,
+                    label: None,
+                    message: format!(
+                        "Output too short. Expected {} lines ({} turns * {} elevators), found {}.",
+                        t * m,
+                        t,
+                        m,
+                        output_lines.len()
+                    ),
+                });
+            }
+        }
+        OutputFormat::V2 => {
+            for (line_idx, line) in output_text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(note) = annotation(line) {
+                    pending.push(note.to_string());
+                    continue;
+                }
+                let line_no = line_idx + 1;
+                match parse_tagged_line(line, line_no, lenient) {
+                    Ok((turn, elevator, command)) => {
+                        annotate(turn, &mut pending);
+                        let diagnostic = |message: String| Diagnostic {
+                            line: line_no,
+                            turn,
+                            elevator,
+                            label: None,
+                            message,
+                        };
+                        if turn >= t || elevator >= m {
+                            diagnostics.push(diagnostic(format!(
+                                "Turn {} / elevator {} is out of range",
+                                turn, elevator
+                            )));
+                        } else if let Some(prev) = &commands[turn][elevator] {
+                            diagnostics.push(diagnostic(format!(
+                                "Duplicate action for turn {} elevator {} (first on line {})",
+                                turn, elevator, prev.line
+                            )));
+                        } else {
+                            commands[turn][elevator] = Some(command);
+                        }
+                    }
+                    Err(e) => diagnostics.push(Diagnostic {
+                        line: line_no,
+                        turn: 0,
+                        elevator: 0,
+                        label: None,
+                        message: e.to_string(),
+                    }),
+                }
+            }
+            annotate(t, &mut pending);
+        }
+    }
+
+    ParsedOutput {
+        format,
+        commands,
+        diagnostics,
+        annotations,
+    }
+}
+
+fn parse_tagged_line(line: &str, line_no: usize, lenient: bool) -> Result<(usize, usize, Command)> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let ["turn", turn, "elev", elevator, keyword, rest @ ..] = tokens.as_slice() else {
+        bail!("Expected `turn <T> elev <E> <ACTION>`");
+    };
+    let turn: usize = turn.parse().context("Invalid turn")?;
+    let elevator: usize = elevator.parse().context("Invalid elevator index")?;
+
+    let mut plain = vec![*keyword];
+    let mut pick_ids = vec![];
+    let mut drop_ids = vec![];
+    let mut after_slash = false;
+    let mut rest = rest.iter();
+    while let Some(&token) = rest.next() {
+        if token == "id" {
+            let id: usize = rest
+                .next()
+                .context("Missing passenger ID after `id`")?
+                .parse()
+                .context("Invalid passenger ID")?;
+            if after_slash {
+                drop_ids.push(id);
+            } else {
+                pick_ids.push(id);
+            }
+        } else {
+            after_slash |= token == "/";
+            plain.push(token);
+        }
+    }
+    let action = Action::parse_line(&plain.join(" "), lenient)?;
+
+    Ok((
+        turn,
+        elevator,
+        Command {
+            line: line_no,
+            action,
+            pick_ids,
+            drop_ids,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v2() {
+        let text = "turn 0 elev 1 OPEN id 37 2 / id 5\nturn 1 elev 0 UP\nturn 1 elev 0 DOWN\n";
+        let parsed = parse_output(text, 2, 2, false);
+        assert_eq!(parsed.format, OutputFormat::V2);
+        let command = parsed.commands[0][1].as_ref().unwrap();
+        assert_eq!(
+            command.action,
+            Action::Open {
+                picks: vec![2],
+                drops: Some(vec![])
+            }
+        );
+        assert_eq!(command.pick_ids, vec![37]);
+        assert_eq!(command.drop_ids, vec![5]);
+        assert!(parsed.commands[0][0].is_none());
+        assert_eq!(parsed.commands[1][0].as_ref().unwrap().action, Action::Up);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(parsed.diagnostics[0].line, 3);
+    }
+
+    #[test]
+    fn test_annotations() {
+        let text = "# heading to 7 for id 23\nUP\nSTAY\n  #second\nOPEN\nSTAY\n# done\n";
+        let parsed = parse_output(text, 2, 2, false);
+        assert_eq!(parsed.format, OutputFormat::V1);
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.commands[1][0].as_ref().unwrap().line, 5);
+        assert_eq!(
+            parsed.annotations,
+            vec![vec!["heading to 7 for id 23"], vec!["second", "done"]]
+        );
+
+        let text = "# first\nturn 1 elev 0 UP\n# last\n";
+        let parsed = parse_output(text, 2, 1, false);
+        assert_eq!(parsed.format, OutputFormat::V2);
+        assert_eq!(parsed.annotations, vec![vec![], vec!["first", "last"]]);
+    }
+
+    #[test]
+    fn test_resolve_ids() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 10, 10)?;
+        sim.add_passenger(5, 8, 0, 40)?;
+        sim.add_passenger(5, 2, 0, 41)?;
+        let parsed = parse_output("turn 0 elev 0 OPEN id 41", 10, 1, false);
+        let command = parsed.commands[0][0].as_ref().unwrap();
+        assert_eq!(
+            command.resolve(&sim, 0)?,
+            Action::Open {
+                picks: vec![1],
+                drops: None
+            }
+        );
+        let parsed = parse_output("turn 0 elev 0 OPEN id 7", 10, 1, false);
+        assert!(
+            parsed.commands[0][0]
+                .as_ref()
+                .unwrap()
+                .resolve(&sim, 0)
+                .is_err()
+        );
+        Ok(())
+    }
+}
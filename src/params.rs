@@ -0,0 +1,381 @@
+use crate::spec::{DEFAULT_C, DEFAULT_LAMBDA, DEFAULT_M, DEFAULT_N, DEFAULT_PICK_SLACK, DEFAULT_T};
+use crate::{ProblemVariant, ScoreWeights, Scoring};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Order in which the elevators' actions are applied within a turn. Earlier elevators
+/// win contested pickups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionOrder {
+    #[default]
+    Index,
+    Reverse,
+    /// A fresh permutation every turn, derived from `order_seed` and the turn.
+    Random,
+}
+
+impl FromStr for ActionOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "index" => Ok(ActionOrder::Index),
+            "reverse" => Ok(ActionOrder::Reverse),
+            "random" => Ok(ActionOrder::Random),
+            _ => bail!("Unknown action order: {}", s),
+        }
+    }
+}
+
+/// What happens to a passenger who arrives at their own destination floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameFloorPolicy {
+    /// Such arrivals are invalid input.
+    #[default]
+    Reject,
+    /// They are delivered on arrival, adding a penalty of 1.
+    Deliver,
+}
+
+impl FromStr for SameFloorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject" => Ok(SameFloorPolicy::Reject),
+            "deliver" => Ok(SameFloorPolicy::Deliver),
+            _ => bail!("Unknown same-floor policy: {}", s),
+        }
+    }
+}
+
+/// Budget units each action consumes when `SimParams::action_budget` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActionCosts {
+    pub up: u64,
+    pub down: u64,
+    pub stay: u64,
+    pub open: u64,
+}
+
+impl ActionCosts {
+    pub fn of(&self, action: &str) -> u64 {
+        match action {
+            "UP" => self.up,
+            "DOWN" => self.down,
+            "OPEN" => self.open,
+            _ => self.stay,
+        }
+    }
+}
+
+/// Capabilities of one elevator in a heterogeneous fleet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElevatorSpec {
+    pub capacity: usize,
+    pub travel_time: usize,
+    /// Floors where the elevator can `OPEN`; empty means every floor.
+    #[serde(default)]
+    pub floors: Vec<usize>,
+    /// Most passengers that can board in one `OPEN`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_boardings: Option<usize>,
+}
+
+impl ElevatorSpec {
+    pub fn serves(&self, floor: usize) -> bool {
+        self.floors.is_empty() || self.floors.contains(&floor)
+    }
+}
+
+/// A scripted change of mind: at the start of `turn`, the passenger with ID `passenger`
+/// wants to go to `target` instead, if they are still waiting by then.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DestinationChange {
+    pub turn: usize,
+    pub passenger: usize,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimParams {
+    pub n: usize,
+    pub m: usize,
+    pub c: usize,
+    pub t: usize,
+    pub lambda: f64,
+    /// Turns an elevator needs to travel one floor. With values above 1 an elevator
+    /// can be between floors, where it cannot `OPEN`.
+    pub travel_time: usize,
+    /// Home floor of each elevator for the parking policy; empty disables parking.
+    pub home_floors: Vec<usize>,
+    /// Consecutive idle `STAY` turns after which an empty elevator drifts home.
+    pub park_after: usize,
+    pub action_order: ActionOrder,
+    pub order_seed: u64,
+    /// One spec per elevator overriding `c` and `travel_time`; empty makes every
+    /// elevator identical.
+    pub fleet: Vec<ElevatorSpec>,
+    /// Display name of each elevator, e.g. "express A"; empty leaves them unnamed.
+    pub labels: Vec<String>,
+    /// Name of each floor from the bottom, e.g. `["B1", "L", "2"]`. The simulation
+    /// always uses indices; labels only change what input files and reports show.
+    pub floor_labels: Vec<String>,
+    /// Floors where passengers may change between elevators. With a fleet whose
+    /// elevators serve different floors, a passenger no single elevator can carry is
+    /// routed through the first transfer floor that connects both ends.
+    pub transfer_floors: Vec<usize>,
+    /// Probability that a picked passenger fails to board and keeps waiting.
+    pub no_show_prob: f64,
+    /// Probability, per `OPEN`, that an onboard passenger not bound for the floor gets
+    /// off anyway and waits there for another ride.
+    pub early_exit_prob: f64,
+    pub noise_seed: u64,
+    /// Agents observe the state as of this many turns ago.
+    pub observation_delay: usize,
+    /// Newly arrived passengers stay hidden from agents for this many turns, though
+    /// their wait counts from arrival. Hidden passengers cannot be picked.
+    pub detection_delay: usize,
+    /// Turns at the start that accrue no penalty: waits are only counted from this
+    /// turn on, for studying steady-state traffic.
+    pub warmup: usize,
+    pub same_floor: SameFloorPolicy,
+    /// Total budget shared by all elevators over the episode; an action that would
+    /// exceed it is invalid. `None` disables the budget.
+    pub action_budget: Option<u64>,
+    pub action_costs: ActionCosts,
+    /// The variant these parameters were built from; see [`ProblemVariant::params`].
+    pub variant: ProblemVariant,
+    /// Plain `OPEN` is invalid; every `OPEN` must list the passengers to let out.
+    pub require_drops: bool,
+    /// Score added for every turn an elevator spends moving.
+    pub move_penalty: u64,
+    /// Load factor (passengers over capacity) above which an elevator is crowded.
+    /// Every turn an elevator ends crowded adds `comfort_penalty`; `None` disables it.
+    pub comfort_threshold: Option<f64>,
+    pub comfort_penalty: u64,
+    /// Turns a passenger may ride before `ride_penalty` is added, once per ride; at
+    /// least 1, as the boarding turn counts as 0. `None` disables the limit.
+    pub max_ride: Option<usize>,
+    pub ride_penalty: u64,
+    /// Most passengers that can board in one `OPEN`, for elevators without a fleet entry.
+    pub max_boardings: Option<usize>,
+    /// Picks an `OPEN` may list beyond the elevator's capacity, the extra ones being
+    /// left waiting; a longer list is invalid. `None` allows any number.
+    pub pick_slack: Option<usize>,
+    pub destination_changes: Vec<DestinationChange>,
+    /// Doors stay open after an `OPEN` until a `STAY` closes them, which takes that
+    /// turn, and `UP` or `DOWN` with open doors is invalid. Otherwise doors open and
+    /// close within the `OPEN`'s turn.
+    pub door_physics: bool,
+    /// How trips and movement are turned into score; see [`Scoring`].
+    pub scoring: Scoring,
+    /// How much each score component counts toward the score.
+    pub weights: ScoreWeights,
+}
+
+impl SimParams {
+    /// Capabilities of elevator `idx`, falling back to the fleet-wide `c` and `travel_time`.
+    pub fn elevator_spec(&self, idx: usize) -> ElevatorSpec {
+        self.fleet.get(idx).cloned().unwrap_or(ElevatorSpec {
+            capacity: self.c,
+            travel_time: self.travel_time,
+            floors: vec![],
+            max_boardings: self.max_boardings,
+        })
+    }
+
+    /// Checks that the parameters describe a simulation that can run: at least one
+    /// floor, elevator and turn, and per-floor or per-elevator lists of the right length.
+    /// One floor or one elevator is fine; with one floor nobody has anywhere to go.
+    pub fn validate(&self) -> Result<()> {
+        if self.n == 0 || self.m == 0 || self.t == 0 {
+            bail!(
+                "N, M and T must be at least 1 (got N={}, M={}, T={})",
+                self.n,
+                self.m,
+                self.t
+            );
+        }
+        if self
+            .comfort_threshold
+            .is_some_and(|x| x.is_nan() || x < 0.0)
+        {
+            bail!("The comfort threshold must be a load factor of at least 0");
+        }
+        if self.max_ride == Some(0) {
+            bail!("The maximum ride time must be at least 1 turn");
+        }
+        if self.warmup >= self.t {
+            bail!(
+                "The warm-up ({} turns) must be shorter than T ({})",
+                self.warmup,
+                self.t
+            );
+        }
+        if !(0.0..=1.0).contains(&self.no_show_prob) || !(0.0..=1.0).contains(&self.early_exit_prob)
+        {
+            bail!("Noise probabilities must be between 0 and 1");
+        }
+        if self.travel_time == 0 {
+            bail!("Travel time must be at least 1");
+        }
+        if !self.home_floors.is_empty() {
+            if self.home_floors.len() != self.m {
+                bail!(
+                    "Home floors need one floor per elevator ({} given, {} elevators)",
+                    self.home_floors.len(),
+                    self.m
+                );
+            }
+            if let Some(&floor) = self.home_floors.iter().find(|&&f| f >= self.n) {
+                bail!("Home floor {} is out of range", floor);
+            }
+        }
+        if !self.labels.is_empty() && self.labels.len() != self.m {
+            bail!(
+                "Labels need one name per elevator ({} given, {} elevators)",
+                self.labels.len(),
+                self.m
+            );
+        }
+        if !self.floor_labels.is_empty() {
+            if self.floor_labels.len() != self.n {
+                bail!(
+                    "Floor labels need one name per floor ({} given, {} floors)",
+                    self.floor_labels.len(),
+                    self.n
+                );
+            }
+            if let Some(label) = self
+                .floor_labels
+                .iter()
+                .find(|l| l.is_empty() || l.contains(char::is_whitespace))
+            {
+                bail!("Floor label {:?} must be non-empty without spaces", label);
+            }
+        }
+        if let Some(&floor) = self.transfer_floors.iter().find(|&&f| f >= self.n) {
+            bail!("Transfer floor {} is out of range", floor);
+        }
+        if !self.fleet.is_empty() {
+            if self.fleet.len() != self.m {
+                bail!(
+                    "The fleet needs one spec per elevator ({} given, {} elevators)",
+                    self.fleet.len(),
+                    self.m
+                );
+            }
+            for (i, spec) in self.fleet.iter().enumerate() {
+                if spec.travel_time == 0 {
+                    bail!("Elevator {} needs a travel time of at least 1", i);
+                }
+                if let Some(&floor) = spec.floors.iter().find(|&&f| f >= self.n) {
+                    bail!(
+                        "Elevator {} serves floor {}, which is out of range",
+                        i,
+                        floor
+                    );
+                }
+            }
+        }
+        if let Some(change) = self.destination_changes.iter().find(|c| c.target >= self.n) {
+            bail!(
+                "Destination change of passenger {} targets floor {}, which is out of range",
+                change.passenger,
+                change.target
+            );
+        }
+        Ok(())
+    }
+
+    pub fn elevator_label(&self, idx: usize) -> Option<&str> {
+        self.labels.get(idx).map(String::as_str)
+    }
+
+    /// Splits a journey into legs: the first destination and the ones after it.
+    pub fn route(&self, from: usize, to: usize) -> (usize, Vec<usize>) {
+        let connects = |a: usize, b: usize| {
+            (0..self.m).any(|i| {
+                let spec = self.elevator_spec(i);
+                spec.serves(a) && spec.serves(b)
+            })
+        };
+        if self.transfer_floors.is_empty() || connects(from, to) {
+            return (to, vec![]);
+        }
+        match self
+            .transfer_floors
+            .iter()
+            .find(|&&x| x != from && x != to && connects(from, x) && connects(x, to))
+        {
+            Some(&transfer) => (transfer, vec![to]),
+            None => (to, vec![]),
+        }
+    }
+
+    /// Label of floor `idx`, or the index itself when floors are unnamed.
+    pub fn floor_label(&self, idx: usize) -> String {
+        self.floor_labels
+            .get(idx)
+            .cloned()
+            .unwrap_or_else(|| idx.to_string())
+    }
+
+    /// Inverse of [`Self::floor_label`].
+    pub fn floor_index(&self, label: &str) -> Option<usize> {
+        if self.floor_labels.is_empty() {
+            label.parse().ok().filter(|&f| f < self.n)
+        } else {
+            self.floor_labels.iter().position(|l| l == label)
+        }
+    }
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        Self {
+            n: DEFAULT_N,
+            m: DEFAULT_M,
+            c: DEFAULT_C,
+            t: DEFAULT_T,
+            lambda: DEFAULT_LAMBDA,
+            travel_time: 1,
+            home_floors: vec![],
+            park_after: 0,
+            action_order: ActionOrder::Index,
+            order_seed: 0,
+            fleet: vec![],
+            labels: vec![],
+            floor_labels: vec![],
+            transfer_floors: vec![],
+            no_show_prob: 0.0,
+            early_exit_prob: 0.0,
+            noise_seed: 0,
+            observation_delay: 0,
+            detection_delay: 0,
+            warmup: 0,
+            same_floor: SameFloorPolicy::Reject,
+            action_budget: None,
+            action_costs: ActionCosts::default(),
+            variant: ProblemVariant::Classic,
+            require_drops: false,
+            move_penalty: 0,
+            comfort_threshold: None,
+            comfort_penalty: 0,
+            max_ride: None,
+            ride_penalty: 0,
+            max_boardings: None,
+            pick_slack: Some(DEFAULT_PICK_SLACK),
+            destination_changes: vec![],
+            door_physics: false,
+            scoring: Scoring::Quadratic,
+            weights: ScoreWeights::default(),
+        }
+    }
+}
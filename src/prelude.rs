@@ -0,0 +1,11 @@
+//! The types an agent or experiment written against the library usually needs:
+//!
+//! ```
+//! use elevator_sim::prelude::*;
+//! ```
+
+pub use crate::{
+    Action, Agent, Event, History, HistoryBuffer, Instance, Observation, Passenger, RunStats,
+    RunSummary, SimParams, SimulationState, Snapshot, TurnReport, evaluate_seeds, fast_score,
+    run_episode,
+};
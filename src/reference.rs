@@ -0,0 +1,75 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Reference-agent scores for a seed range. `checksum` covers the agent name and every
+/// score so that an edited or truncated table is rejected on load; it is an integrity
+/// check, not a signature.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceTable {
+    pub agent: String,
+    pub scores: BTreeMap<u64, u64>,
+    pub checksum: String,
+}
+
+impl ReferenceTable {
+    pub fn new(agent: &str, scores: BTreeMap<u64, u64>) -> Self {
+        let checksum = checksum(agent, &scores);
+        Self {
+            agent: agent.to_string(),
+            scores,
+            checksum,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let table: Self = serde_json::from_str(text)?;
+        if checksum(&table.agent, &table.scores) != table.checksum {
+            bail!("Reference table checksum mismatch");
+        }
+        Ok(table)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn get(&self, seed: u64) -> Option<u64> {
+        self.scores.get(&seed).copied()
+    }
+}
+
+/// FNV-1a (64-bit) of `data`, as 16 hex digits.
+pub fn fnv1a_hex(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn checksum(agent: &str, scores: &BTreeMap<u64, u64>) -> String {
+    let mut text = format!("{}\n", agent);
+    for (seed, score) in scores {
+        text.push_str(&format!("{} {}\n", seed, score));
+    }
+    fnv1a_hex(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_roundtrip() -> Result<()> {
+        let table = ReferenceTable::new("greedy", BTreeMap::from([(0, 120), (1, 95)]));
+        let parsed = ReferenceTable::parse(&table.to_json()?)?;
+        assert_eq!(parsed, table);
+        assert_eq!(parsed.get(1), Some(95));
+
+        let tampered = table.to_json()?.replace("95", "94");
+        assert!(ReferenceTable::parse(&tampered).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    Action, Instance, Observation, Passenger, REPLAY_VERSION, ScoreComponents, SimParams,
+    SimulationState, Snapshot, annotation, parse_output, state_hash,
+};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub turn: usize,
+    pub elevator: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub score: u64,
+    /// `score` by component, before `SimParams::weights`
+    #[serde(default)]
+    pub components: ScoreComponents,
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Snapshot>,
+    /// What an agent producing this output was sent each turn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub observations: Vec<Observation>,
+}
+
+/// A self-contained scoring request. The instance is either generated from `seed`
+/// with the default parameters or embedded as `input` (v1 text or v2 JSON), so
+/// hand-crafted and externally generated instances can be scored without a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreRequest {
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub input: Option<String>,
+    pub output: String,
+    #[serde(default)]
+    pub lenient: bool,
+}
+
+impl ScoreRequest {
+    pub fn instance(&self) -> Result<Instance> {
+        self.instance_capped(None)
+    }
+
+    /// The instance, refusing an input with more than `max_cells` floor-turns.
+    pub fn instance_capped(&self, max_cells: Option<usize>) -> Result<Instance> {
+        match (&self.input, self.seed) {
+            (Some(input), None) => Instance::parse_capped(input, max_cells),
+            (None, Some(seed)) => Instance::generate(seed, &SimParams::default()),
+            (Some(_), Some(_)) => bail!("Specify either `seed` or `input`, not both"),
+            (None, None) => bail!("Either `seed` or `input` is required"),
+        }
+    }
+
+    pub fn replay(&self) -> Result<ReplayReport> {
+        Ok(self.instance()?.replay(&self.output, self.lenient))
+    }
+}
+
+/// A saved run that can be re-simulated on its own: the instance it was played on,
+/// each turn's actions and, optionally, the state after every turn.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayFile {
+    pub version: u32,
+    /// The instance as v2 input
+    pub input: String,
+    /// Each turn's action lines, one per elevator
+    pub turns: Vec<Vec<String>>,
+    /// Each turn's annotations, without the leading `#`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Vec<String>>,
+    #[serde(default)]
+    pub lenient: bool,
+    pub score: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// Where a re-simulated [`ReplayFile`] first parts from the saved run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The state after `turn` hashes differently from the saved snapshot
+    State {
+        turn: usize,
+        saved: String,
+        replayed: String,
+    },
+    Score {
+        saved: u64,
+        replayed: u64,
+    },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::State {
+                turn,
+                saved,
+                replayed,
+            } => write!(
+                f,
+                "State diverges at turn {}: saved {} vs replayed {}",
+                turn, saved, replayed
+            ),
+            Self::Score { saved, replayed } => write!(
+                f,
+                "Final scores differ: saved {} vs replayed {}",
+                saved, replayed
+            ),
+        }
+    }
+}
+
+impl ReplayFile {
+    /// Records `output_text` (v1) played on `instance`, keeping every turn's snapshot
+    /// if `snapshots` is set.
+    pub fn record(
+        instance: &Instance,
+        output_text: &str,
+        lenient: bool,
+        snapshots: bool,
+    ) -> Result<Self> {
+        let report = instance.replay(output_text, lenient);
+        let lines: Vec<String> = output_text
+            .lines()
+            .filter(|line| annotation(line).is_none())
+            .map(String::from)
+            .collect();
+        let annotations =
+            parse_output(output_text, instance.params.t, instance.params.m, lenient).annotations;
+        Ok(Self {
+            version: REPLAY_VERSION,
+            input: instance.to_v2()?,
+            turns: lines
+                .chunks(instance.params.m.max(1))
+                .map(<[String]>::to_vec)
+                .collect(),
+            annotations: if annotations.iter().all(Vec::is_empty) {
+                vec![]
+            } else {
+                annotations
+            },
+            lenient,
+            score: report.score,
+            snapshots: if snapshots { report.history } else { vec![] },
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let replay: Self = serde_json::from_str(text)?;
+        if replay.version > REPLAY_VERSION {
+            bail!(
+                "Replay version {} is newer than this build supports ({})",
+                replay.version,
+                REPLAY_VERSION
+            );
+        }
+        Ok(replay)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)? + "\n")
+    }
+
+    pub fn instance(&self) -> Result<Instance> {
+        Instance::parse(&self.input).context("Invalid input in replay")
+    }
+
+    /// The actions as a v1 output, one line per elevator per turn, each turn preceded
+    /// by its annotations.
+    pub fn output_text(&self) -> String {
+        let mut text = String::new();
+        for (turn, lines) in self.turns.iter().enumerate() {
+            for note in self.annotations.get(turn).into_iter().flatten() {
+                text += &format!("# {}\n", note);
+            }
+            for line in lines {
+                text += line;
+                text += "\n";
+            }
+        }
+        text
+    }
+
+    /// Plays the actions again, returning the report and the first place where the
+    /// result differs from the saved snapshots or score.
+    pub fn rerun(&self) -> Result<(ReplayReport, Option<Divergence>)> {
+        let report = self.instance()?.replay(&self.output_text(), self.lenient);
+        let divergence = self
+            .snapshots
+            .iter()
+            .zip(&report.history)
+            .map(|(saved, replayed)| (saved.turn, state_hash(saved), state_hash(replayed)))
+            .find(|(_, saved, replayed)| saved != replayed)
+            .map(|(turn, saved, replayed)| Divergence::State {
+                turn,
+                saved,
+                replayed,
+            })
+            .or_else(|| {
+                (report.score != self.score).then_some(Divergence::Score {
+                    saved: self.score,
+                    replayed: report.score,
+                })
+            });
+        Ok((report, divergence))
+    }
+}
+
+/// Replays an output file (v1 or v2, see [`crate::OutputFormat`]) against a
+/// pre-generated passenger table (`[floor][turn]`).
+///
+/// Invalid or missing lines do not stop the replay: each one is recorded as a
+/// diagnostic (with its 1-based line number) and the elevator stays for that turn,
+/// so `score` is a best-effort partial score.
+pub fn replay_output(
+    mut sim: SimulationState,
+    passenger_source: &[Vec<Vec<Passenger>>],
+    output_text: &str,
+    lenient: bool,
+) -> ReplayReport {
+    let (m, t) = (sim.m, sim.t);
+    let mut parsed = parse_output(output_text, t, m, lenient);
+    let mut diagnostics = std::mem::take(&mut parsed.diagnostics);
+    let mut history: Vec<Snapshot> = Vec::with_capacity(t);
+    let mut observations = Vec::with_capacity(t);
+
+    let mut arrived = sim.set_arrival_schedule(passenger_source.to_vec());
+    for turn in 0..t {
+        // Only instances built past the input checks can fail here
+        if let Err(e) = arrived {
+            diagnostics.push(Diagnostic {
+                line: 0,
+                turn,
+                elevator: 0,
+                label: None,
+                message: e.to_string(),
+            });
+        }
+        let previous = history.last().map_or(&[][..], |s| &s.events[..]);
+        observations.push(Observation::new(&mut sim, previous));
+
+        for el_idx in sim.action_order() {
+            let applied = parsed.commands[turn][el_idx]
+                .as_ref()
+                .is_some_and(|command| {
+                    let result = command
+                        .resolve(&sim, el_idx)
+                        .and_then(|action| sim.apply_action(el_idx, &action));
+                    if let Err(ref e) = result {
+                        diagnostics.push(Diagnostic {
+                            line: command.line,
+                            turn,
+                            elevator: el_idx,
+                            label: None,
+                            message: e.to_string(),
+                        });
+                    }
+                    result.is_ok()
+                });
+            if !applied {
+                // Staying goes through the per-turn checks, such as the ride time, that
+                // skipping the elevator would dodge
+                let _ = sim.apply_action(el_idx, &Action::Stay);
+            }
+        }
+
+        let mut snapshot = sim.turn_snapshot();
+        snapshot.annotations = std::mem::take(&mut parsed.annotations[turn]);
+        history.push(snapshot);
+        arrived = sim.advance();
+    }
+
+    diagnostics.sort_by_key(|d| d.line);
+    for d in &mut diagnostics {
+        d.label = sim.params().elevator_label(d.elevator).map(String::from);
+    }
+
+    ReplayReport {
+        score: sim.calculate_final_score(),
+        components: sim.final_components(),
+        diagnostics,
+        history,
+        observations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_request_with_embedded_input() -> Result<()> {
+        let request: ScoreRequest = serde_json::from_str(
+            r#"{"input": "4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n", "output": "OPEN 0\nUP\nOPEN"}"#,
+        )?;
+        let report = request.replay()?;
+        assert!(report.diagnostics.is_empty());
+        // Boarded at turn 0 and delivered at turn 2: (2 - 0 + 1)^2
+        assert_eq!(report.score, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_visualizer_sample_log() -> Result<()> {
+        // The visualizer's autoplay plays this output on seed 0, so it has to follow
+        // the generator
+        let output = include_str!("../visualizer/public/sample_log.txt");
+        let report = Instance::generate(0, &SimParams::default())?.replay(output, false);
+        assert_eq!(report.diagnostics, vec![]);
+        assert!(report.score > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_collects_all_diagnostics() -> Result<()> {
+        let mut source = vec![vec![vec![]; 3]; 4];
+        source[2][0].push(Passenger {
+            id: 0,
+            arrival_turn: 0,
+            target_floor: 3,
+            legs: vec![],
+            waited_before: 0,
+        });
+        let sim = SimulationState::new(4, 1, 10, 3)?;
+        let report = replay_output(sim, &source, "OPEN 0\nJUMP", false);
+
+        let lines: Vec<usize> = report.diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert_eq!(report.history.len(), 3);
+        assert_eq!(report.observations[0].state.floors[2].waiting_count, 1);
+        assert_eq!(report.observations[1].state.elevators[0].passenger_count, 1);
+        // Picked up at turn 0 and never delivered: (3 - 0)^2
+        assert_eq!(report.score, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_lines_stay() -> Result<()> {
+        let params = SimParams {
+            n: 4,
+            m: 1,
+            t: 10,
+            max_ride: Some(2),
+            ride_penalty: 1000,
+            ..SimParams::default()
+        };
+        let mut targets = vec![vec![vec![]; 10]; 4];
+        targets[2][0].push(3);
+        let instance = Instance::from_targets(params, targets)?;
+        let truncated = instance.replay("OPEN 0\n", false);
+        let explicit = instance.replay(&format!("OPEN 0\n{}", "STAY\n".repeat(9)), false);
+        assert_eq!(truncated.components.long_ride, 1000);
+        assert_eq!(truncated.score, explicit.score);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_file_round_trip() -> Result<()> {
+        let instance = Instance::generate(3, &SimParams::default())?;
+        let output = "UP\nSTAY\nOPEN\n".repeat(instance.params.t);
+        let replay = ReplayFile::record(&instance, &output, false, true)?;
+        assert_eq!(replay.turns.len(), instance.params.t);
+        let loaded = ReplayFile::parse(&replay.to_json()?)?;
+        let (report, divergence) = loaded.rerun()?;
+        assert_eq!((report.score, divergence), (replay.score, None));
+
+        let mut changed = loaded.clone();
+        changed.turns[5][0] = "DOWN".to_string();
+        match changed.rerun()?.1 {
+            Some(Divergence::State { turn, .. }) => assert_eq!(turn, 5),
+            other => panic!("expected a divergence at turn 5, got {:?}", other),
+        }
+        // Without snapshots only the score is checked
+        let mut scored = loaded.clone();
+        scored.snapshots.clear();
+        scored.score += 1;
+        assert!(matches!(scored.rerun()?.1, Some(Divergence::Score { .. })));
+
+        let noted = ReplayFile::record(&instance, &format!("# going up\n{}", output), false, true)?;
+        assert_eq!(noted.turns, replay.turns);
+        assert_eq!(noted.snapshots[0].annotations, vec!["going up"]);
+        assert_eq!(noted.rerun()?.1, None);
+
+        // A lenient run is re-run leniently, so its abbreviations are not diagnostics
+        let short = output.replace("UP", "u").replace("OPEN", "o");
+        let lenient = ReplayFile::record(&instance, &short, true, true)?;
+        assert_eq!(lenient.score, replay.score);
+        assert_eq!(ReplayFile::parse(&lenient.to_json()?)?.rerun()?.1, None);
+
+        let mut future = replay;
+        future.version = REPLAY_VERSION + 1;
+        assert!(ReplayFile::parse(&future.to_json()?).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::Result;
+use rusqlite::{Connection, params};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One judged run as stored in a [`ResultsDb`]. `score` is `None` when the run failed,
+/// in which case `verdict` holds the error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRecord {
+    pub agent: String,
+    /// FNV-1a of the agent executable, when it could be read
+    pub agent_hash: Option<String>,
+    /// Seed of the instance; runs without one are not recorded, as they could not be
+    /// told apart
+    pub seed: u64,
+    /// The instance's `SimParams` as JSON
+    pub params: String,
+    pub score: Option<u64>,
+    pub duration_ms: u64,
+    pub verdict: String,
+    /// Seconds since the Unix epoch
+    pub recorded_at: u64,
+}
+
+/// One submission's line in [`ResultsDb::standings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Standing {
+    pub agent: String,
+    pub agent_hash: Option<String>,
+    /// Seeds it was run on, and the runs among them that failed
+    pub cases: u64,
+    pub failures: u64,
+    pub total: u64,
+}
+
+/// SQLite store of judged runs, for projects that outgrow one-off score files.
+pub struct ResultsDb {
+    conn: Connection,
+}
+
+const COLUMNS: &str = "agent, agent_hash, seed, params, score, duration_ms, verdict, recorded_at";
+
+impl ResultsDb {
+    /// Opens the database at `path`, creating it and its table if needed.
+    pub fn open(path: &str) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY,
+                agent TEXT NOT NULL,
+                agent_hash TEXT,
+                seed INTEGER NOT NULL,
+                params TEXT NOT NULL,
+                score INTEGER,
+                duration_ms INTEGER NOT NULL,
+                verdict TEXT NOT NULL,
+                recorded_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS runs_agent ON runs (agent);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn record(&self, run: &RunRecord) -> Result<()> {
+        self.conn.execute(
+            &format!(
+                "INSERT INTO runs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                COLUMNS
+            ),
+            params![
+                run.agent,
+                run.agent_hash,
+                run.seed as i64,
+                run.params,
+                run.score.map(|s| s as i64),
+                run.duration_ms as i64,
+                run.verdict,
+                run.recorded_at as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The lowest-scoring successful run for each seed under each set of parameters, by
+    /// parameters and then seed, since a seed makes a different instance under other
+    /// parameters. Ties go to the earliest run.
+    pub fn best(&self) -> Result<Vec<RunRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM runs r WHERE score IS NOT NULL
+             AND id = (SELECT id FROM runs
+                       WHERE seed = r.seed AND params = r.params AND score IS NOT NULL
+                       ORDER BY score, id LIMIT 1)
+             ORDER BY params, seed",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map([], from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Whether `agent` has been judged with this executable or output hash on `seed`
+    /// under `params`, so that a submission judged partway can be resumed.
+    pub fn has_run(&self, agent: &str, agent_hash: &str, seed: u64, params: &str) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM runs
+                            WHERE agent = ?1 AND agent_hash = ?2 AND seed = ?3 AND params = ?4)",
+            params![agent, agent_hash, seed as i64, params],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Total score of every submission (agent and hash) over the latest run on each
+    /// seed and set of parameters, ranked by fewest failures and then lowest total.
+    pub fn standings(&self) -> Result<Vec<Standing>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT agent, agent_hash, COUNT(*), COUNT(*) - COUNT(score),
+                    COALESCE(SUM(score), 0) AS total
+             FROM runs
+             WHERE id IN (SELECT MAX(id) FROM runs GROUP BY agent, agent_hash, seed, params)
+             GROUP BY agent, agent_hash
+             ORDER BY COUNT(*) - COUNT(score), total",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Standing {
+                agent: row.get(0)?,
+                agent_hash: row.get(1)?,
+                cases: row.get::<_, i64>(2)? as u64,
+                failures: row.get::<_, i64>(3)? as u64,
+                total: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Every run of `agent`, oldest first.
+    pub fn history(&self, agent: &str) -> Result<Vec<RunRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM runs WHERE agent = ?1 ORDER BY id",
+            COLUMNS
+        ))?;
+        let rows = stmt.query_map([agent], from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+impl RunRecord {
+    /// Current time in seconds since the Unix epoch, for `recorded_at`.
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+fn from_row(row: &rusqlite::Row) -> rusqlite::Result<RunRecord> {
+    Ok(RunRecord {
+        agent: row.get(0)?,
+        agent_hash: row.get(1)?,
+        seed: row.get::<_, i64>(2)? as u64,
+        params: row.get(3)?,
+        score: row.get::<_, Option<i64>>(4)?.map(|s| s as u64),
+        duration_ms: row.get::<_, i64>(5)? as u64,
+        verdict: row.get(6)?,
+        recorded_at: row.get::<_, i64>(7)? as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(agent: &str, seed: u64, score: Option<u64>) -> RunRecord {
+        RunRecord {
+            agent: agent.to_string(),
+            agent_hash: None,
+            seed,
+            params: "{}".to_string(),
+            score,
+            duration_ms: 10,
+            verdict: if score.is_some() { "OK" } else { "error" }.to_string(),
+            recorded_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_best_and_history() -> Result<()> {
+        let db = ResultsDb::open_in_memory()?;
+        db.record(&run("greedy", 0, Some(120)))?;
+        db.record(&run("sample", 0, Some(300)))?;
+        db.record(&run("sample", 1, Some(90)))?;
+        db.record(&run("greedy", 1, None))?;
+        db.record(&run("greedy", 1, Some(95)))?;
+
+        let best = db.best()?;
+        assert_eq!(best.len(), 2);
+        assert_eq!(
+            (best[0].agent.as_str(), best[0].score),
+            ("greedy", Some(120))
+        );
+        assert_eq!(
+            (best[1].agent.as_str(), best[1].score),
+            ("sample", Some(90))
+        );
+
+        let history = db.history("greedy")?;
+        let scores: Vec<_> = history.iter().map(|r| r.score).collect();
+        assert_eq!(scores, vec![Some(120), None, Some(95)]);
+
+        // Only the latest run on a seed counts, so greedy's failure is superseded
+        let standings = db.standings()?;
+        let totals: Vec<_> = standings
+            .iter()
+            .map(|s| (s.agent.as_str(), s.total, s.failures))
+            .collect();
+        assert_eq!(totals, vec![("greedy", 215, 0), ("sample", 390, 0)]);
+        // The same seed under other parameters is another instance
+        db.record(&RunRecord {
+            params: "{\"n\":20}".to_string(),
+            ..run("sample", 0, Some(500))
+        })?;
+        let best: Vec<_> = db.best()?.into_iter().map(|r| (r.seed, r.score)).collect();
+        assert_eq!(best, vec![(0, Some(500)), (0, Some(120)), (1, Some(90))]);
+        // ... and does not supersede the run on it in the standings
+        let sample = db.standings()?.pop().unwrap();
+        assert_eq!((sample.cases, sample.total), (3, 890));
+
+        assert!(!db.has_run("greedy", "abc", 2, "{}")?);
+        db.record(&RunRecord {
+            agent_hash: Some("abc".to_string()),
+            ..run("greedy", 2, None)
+        })?;
+        assert!(db.has_run("greedy", "abc", 2, "{}")?);
+        // A submission stopped partway still has its other cases to judge
+        assert!(!db.has_run("greedy", "abc", 3, "{}")?);
+        // A new hash is a new submission, ranked below those without failures
+        let last = db.standings()?.pop().unwrap();
+        assert_eq!(
+            (last.agent_hash.as_deref(), last.failures),
+            (Some("abc"), 1)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{Event, Instance, ReplayReport, SimParams};
+use anyhow::{Result, bail};
+
+/// A small builder for deterministic, hand-written instances, mainly for tests:
+///
+/// ```
+/// use elevator_sim::Scenario;
+///
+/// Scenario::new(5, 1)
+///     .turns(3)
+///     .passenger(2, 4, 0)
+///     .actions("OPEN 0\nUP\nUP")
+///     .expect_score(9);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    params: SimParams,
+    // (floor, target, turn)
+    passengers: Vec<(usize, usize, usize)>,
+    // (floor, target, turns waited before turn 0)
+    waiting: Vec<(usize, usize, usize)>,
+    output: String,
+}
+
+impl Scenario {
+    /// Names of the built-in example instances; see [`Scenario::demo`].
+    pub const DEMOS: [&str; 3] = ["one-passenger", "capacity-edge", "burst"];
+
+    /// A built-in example instance, small enough to follow by hand:
+    ///
+    /// - `one-passenger`: a single passenger below the elevator, going to the top
+    /// - `capacity-edge`: three passengers for an elevator of capacity 2 at its own
+    ///   floor, then one going the other way
+    /// - `burst`: a dozen passengers at the ground floor at once, bound for every floor,
+    ///   and a few coming down later
+    pub fn demo(name: &str) -> Result<Self> {
+        let scenario = match name {
+            "one-passenger" => Self::new(5, 1).turns(10).passenger(0, 4, 0),
+            "capacity-edge" => Self::new(4, 1)
+                .turns(10)
+                .capacity(2)
+                .passenger(2, 3, 0)
+                .passenger(2, 3, 0)
+                .passenger(2, 3, 0)
+                .passenger(3, 0, 1),
+            "burst" => {
+                let mut scenario = Self::new(10, 2).turns(40).capacity(4);
+                for i in 0..12 {
+                    scenario = scenario.passenger(0, 1 + i % 9, 5);
+                }
+                for floor in [9, 7, 4] {
+                    scenario = scenario.passenger(floor, 0, 20);
+                }
+                scenario
+            }
+            _ => bail!(
+                "Unknown demo {:?} (expected one of {})",
+                name,
+                Self::DEMOS.join(", ")
+            ),
+        };
+        Ok(scenario)
+    }
+
+    pub fn new(n: usize, m: usize) -> Self {
+        Self {
+            params: SimParams {
+                n,
+                m,
+                ..SimParams::default()
+            },
+            passengers: vec![],
+            waiting: vec![],
+            output: String::new(),
+        }
+    }
+
+    pub fn capacity(mut self, c: usize) -> Self {
+        self.params.c = c;
+        self
+    }
+
+    pub fn turns(mut self, t: usize) -> Self {
+        self.params.t = t;
+        self
+    }
+
+    pub fn params(mut self, f: impl FnOnce(&mut SimParams)) -> Self {
+        f(&mut self.params);
+        self
+    }
+
+    /// Adds a passenger appearing at floor `at` on `turn`, heading to floor `to`.
+    pub fn passenger(mut self, at: usize, to: usize, turn: usize) -> Self {
+        self.passengers.push((at, to, turn));
+        self
+    }
+
+    /// Adds a passenger already waiting at floor `at` on turn 0, for `waited` turns so
+    /// far. They come before the turn-0 arrivals of their floor.
+    pub fn waiting(mut self, at: usize, to: usize, waited: usize) -> Self {
+        self.waiting.push((at, to, waited));
+        self
+    }
+
+    /// Sets the agent output (v1 or v2). Missing turns are scored as `STAY`.
+    pub fn actions(mut self, output: &str) -> Self {
+        self.output = output.to_string();
+        self
+    }
+
+    /// Fails on a passenger heading to their own floor, unless `same_floor` allows it.
+    pub fn instance(&self) -> Result<Instance> {
+        let mut targets = vec![vec![vec![]; self.params.t]; self.params.n];
+        for &(at, to, _) in &self.waiting {
+            targets[at][0].push(to);
+        }
+        for &(at, to, turn) in &self.passengers {
+            targets[at][turn].push(to);
+        }
+        let mut instance = Instance::from_targets(self.params.clone(), targets)?;
+        let mut next = vec![0; self.params.n];
+        for &(at, _, waited) in &self.waiting {
+            instance.passengers[at][0][next[at]].waited_before = waited;
+            next[at] += 1;
+        }
+        Ok(instance)
+    }
+
+    pub fn run(&self) -> Result<ReplayReport> {
+        Ok(self.instance()?.replay(&self.output, false))
+    }
+
+    // For the assertions, which panic on an invalid scenario as on a failed check
+    fn report(&self) -> ReplayReport {
+        self.run()
+            .unwrap_or_else(|e| panic!("invalid scenario: {:#}", e))
+    }
+
+    /// Asserts the final score, ignoring a too-short output.
+    pub fn expect_score(self, score: u64) -> Self {
+        let report = self.report();
+        assert_eq!(
+            report.score, score,
+            "unexpected score (diagnostics: {:?})",
+            report.diagnostics
+        );
+        self
+    }
+
+    /// Asserts that the passenger with `id` got off at `turn`. IDs follow the input
+    /// order rule: by floor, then turn, then the order `passenger` was called.
+    pub fn expect_delivered(self, id: usize, turn: usize) -> Self {
+        let report = self.report();
+        let delivered_at = report.history.iter().find_map(|s| {
+            s.events
+                .iter()
+                .any(|e| matches!(e, Event::Delivered { passenger_id, .. } if *passenger_id == id))
+                .then_some(s.turn)
+        });
+        assert_eq!(delivered_at, Some(turn), "passenger {} delivery turn", id);
+        self
+    }
+
+    /// Asserts that the output has no invalid lines other than being too short.
+    pub fn expect_valid(self) -> Self {
+        let report = self.report();
+        let errors: Vec<_> = report
+            .diagnostics
+            .iter()
+            .filter(|d| !d.message.starts_with("Output too short"))
+            .collect();
+        assert!(errors.is_empty(), "invalid output: {:?}", errors);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undelivered_passenger() {
+        Scenario::new(5, 1)
+            .turns(4)
+            .passenger(2, 4, 1)
+            .expect_score(9);
+    }
+
+    #[test]
+    fn test_delivery() {
+        Scenario::new(5, 1)
+            .turns(5)
+            .passenger(2, 4, 0)
+            .actions("OPEN 0\nUP\nUP\nOPEN\nSTAY")
+            .expect_valid()
+            .expect_delivered(0, 3)
+            .expect_score(16);
+    }
+
+    #[test]
+    fn test_initial_waiting_passengers() {
+        // Waiting for 3 turns already: delivered at turn 3 after 6 turns in all
+        Scenario::new(5, 1)
+            .turns(5)
+            .waiting(2, 4, 3)
+            .passenger(2, 0, 0)
+            .actions("OPEN 0\nUP\nUP\nOPEN\nSTAY")
+            .expect_delivered(0, 3)
+            .expect_score(49 + 25);
+    }
+
+    #[test]
+    fn test_capacity_limits_boarding() {
+        Scenario::new(3, 1)
+            .turns(3)
+            .capacity(1)
+            .passenger(1, 2, 0)
+            .passenger(1, 2, 0)
+            .actions("OPEN 0 1\nUP\nOPEN")
+            // One rides and arrives at turn 2, the other is left waiting
+            .expect_delivered(1, 2)
+            .expect_score(9 + 9);
+    }
+
+    #[test]
+    fn test_demos() -> Result<()> {
+        for name in Scenario::DEMOS {
+            Scenario::demo(name)?.instance()?.params.validate()?;
+        }
+        assert!(Scenario::demo("missing").is_err());
+        Scenario::demo("one-passenger")?
+            .actions("DOWN\nDOWN\nOPEN 0\nUP\nUP\nUP\nUP\nOPEN")
+            .expect_delivered(0, 7)
+            .expect_score(64);
+        // Only two of the three fit
+        Scenario::demo("capacity-edge")?
+            .actions("OPEN 0 1 2\nUP\nOPEN")
+            .expect_delivered(1, 2)
+            .expect_score(9 + 9 + 100 + 81);
+        Ok(())
+    }
+
+    #[test]
+    fn test_earlier_elevator_wins_contested_pickup() {
+        Scenario::new(3, 2)
+            .turns(2)
+            .passenger(1, 2, 0)
+            .actions("OPEN 0\nOPEN 0\nUP\nUP")
+            .expect_score(4);
+    }
+}
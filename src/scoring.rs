@@ -0,0 +1,207 @@
+use crate::ScoreReason;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The objective a run is scored under. Implement it to score a run some other way and
+/// pass it to `SimulationState::with_scoring`; [`Scoring`] selects one of the built-in
+/// policies from `SimParams`.
+pub trait ScoringPolicy {
+    /// Penalty for a passenger whose trip took `turns` turns, from arrival to delivery
+    /// or to the end of the run.
+    fn trip_penalty(&self, turns: usize) -> u64;
+
+    /// Penalty for one turn of an elevator moving with `load` passengers aboard, on top
+    /// of `SimParams::move_penalty`.
+    fn move_penalty(&self, _load: usize) -> u64 {
+        0
+    }
+}
+
+/// The built-in scoring policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scoring {
+    /// The square of the trip's turns.
+    #[default]
+    Quadratic,
+    /// The trip's turns.
+    Linear,
+    /// The square of the trip's turns, but at most `cap`.
+    Capped { cap: u64 },
+    /// Quadratic trips, and every turn an elevator moves costs `weight` for the car
+    /// plus `weight` for each passenger aboard.
+    EnergyWeighted { weight: u64 },
+}
+
+impl ScoringPolicy for Scoring {
+    fn trip_penalty(&self, turns: usize) -> u64 {
+        let turns = turns as u64;
+        match *self {
+            Scoring::Quadratic | Scoring::EnergyWeighted { .. } => turns.pow(2),
+            Scoring::Linear => turns,
+            Scoring::Capped { cap } => turns.pow(2).min(cap),
+        }
+    }
+
+    fn move_penalty(&self, load: usize) -> u64 {
+        match *self {
+            Scoring::EnergyWeighted { weight } => weight * (1 + load as u64),
+            _ => 0,
+        }
+    }
+}
+
+/// A score split by what it was added for, before [`ScoreWeights`] are applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreComponents {
+    /// Trip penalties, of delivered and unfinished passengers alike
+    pub wait: u64,
+    pub movement: u64,
+    pub crowding: u64,
+    pub long_ride: u64,
+}
+
+impl ScoreComponents {
+    pub fn add(&mut self, reason: ScoreReason, amount: u64) {
+        match reason {
+            ScoreReason::Delivery | ScoreReason::Unfinished => self.wait += amount,
+            ScoreReason::Movement => self.movement += amount,
+            ScoreReason::Crowding => self.crowding += amount,
+            ScoreReason::LongRide => self.long_ride += amount,
+        }
+    }
+
+    /// The score: each component times its weight.
+    pub fn total(&self, weights: &ScoreWeights) -> u64 {
+        self.wait * weights.wait
+            + self.movement * weights.movement
+            + self.crowding * weights.crowding
+            + self.long_ride * weights.long_ride
+    }
+}
+
+impl fmt::Display for ScoreComponents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "wait {} movement {} crowding {} long rides {}",
+            self.wait, self.movement, self.crowding, self.long_ride
+        )
+    }
+}
+
+/// How much each of the [`ScoreComponents`] counts toward the score; all 1 by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoreWeights {
+    pub wait: u64,
+    pub movement: u64,
+    pub crowding: u64,
+    pub long_ride: u64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            wait: 1,
+            movement: 1,
+            crowding: 1,
+            long_ride: 1,
+        }
+    }
+}
+
+impl ScoreWeights {
+    pub fn weight(&self, reason: ScoreReason) -> u64 {
+        match reason {
+            ScoreReason::Delivery | ScoreReason::Unfinished => self.wait,
+            ScoreReason::Movement => self.movement,
+            ScoreReason::Crowding => self.crowding,
+            ScoreReason::LongRide => self.long_ride,
+        }
+    }
+}
+
+impl FromStr for ScoreWeights {
+    type Err = anyhow::Error;
+
+    /// `wait=1,movement=3`: the named weights, the others staying 1.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut weights = Self::default();
+        for pair in s.split(',') {
+            let (name, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Expected name=weight, got {:?}", pair))?;
+            let value = value
+                .parse()
+                .with_context(|| format!("Invalid weight for {}", name))?;
+            match name.trim() {
+                "wait" => weights.wait = value,
+                "movement" => weights.movement = value,
+                "crowding" => weights.crowding = value,
+                "long_ride" => weights.long_ride = value,
+                _ => bail!("Unknown score component: {}", name),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+impl FromStr for Scoring {
+    type Err = anyhow::Error;
+
+    /// `quadratic`, `linear`, `capped:CAP` or `energy-weighted:WEIGHT`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let number = |what: &str| -> Result<u64> {
+            arg.with_context(|| format!("{} needs a {} (e.g. {}:400)", name, what, name))?
+                .parse()
+                .with_context(|| format!("Invalid {} for {}", what, name))
+        };
+        match name {
+            "quadratic" => Ok(Scoring::Quadratic),
+            "linear" => Ok(Scoring::Linear),
+            "capped" => Ok(Scoring::Capped {
+                cap: number("cap")?,
+            }),
+            "energy-weighted" => Ok(Scoring::EnergyWeighted {
+                weight: number("weight")?,
+            }),
+            _ => bail!("Unknown scoring policy: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() -> Result<()> {
+        assert_eq!(Scoring::default().trip_penalty(7), 49);
+        assert_eq!(Scoring::Linear.trip_penalty(7), 7);
+        assert_eq!("capped:40".parse::<Scoring>()?.trip_penalty(7), 40);
+        let energy: Scoring = "energy-weighted:2".parse()?;
+        assert_eq!((energy.trip_penalty(3), energy.move_penalty(4)), (9, 10));
+        assert_eq!(Scoring::Quadratic.move_penalty(4), 0);
+        assert!("capped".parse::<Scoring>().is_err());
+        assert!("cubic".parse::<Scoring>().is_err());
+
+        let weights: ScoreWeights = "movement=3, long_ride=0".parse()?;
+        let components = ScoreComponents {
+            wait: 10,
+            movement: 2,
+            crowding: 1,
+            long_ride: 5,
+        };
+        assert_eq!(components.total(&weights), 10 + 6 + 1);
+        assert_eq!(components.total(&ScoreWeights::default()), 18);
+        assert!("speed=2".parse::<ScoreWeights>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,731 @@
+use crate::contest::{Contest, Episode};
+use crate::{Diagnostic, ScoreComponents, ScoreRequest};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the score histogram buckets
+const SCORE_BUCKETS: [f64; 7] = [1e3, 3e3, 1e4, 3e4, 1e5, 3e5, 1e6];
+/// Episodes left untouched this long are dropped, freeing the client's slot
+const EPISODE_IDLE: Duration = Duration::from_secs(600);
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 6] = [0.001, 0.01, 0.1, 1.0, 10.0, 60.0];
+/// Largest request body read, in bytes
+const MAX_BODY_BYTES: u64 = 16 << 20;
+/// Most floor-turns (N x T) of an instance sent to `/score`
+const MAX_CELLS: usize = 1 << 24;
+
+/// Counters behind `GET /metrics`.
+#[derive(Debug, Default)]
+struct Metrics {
+    episodes: u64,
+    failures: u64,
+    rejected: u64,
+    in_flight: usize,
+    scores: Histogram,
+    latency: Histogram,
+}
+
+/// A cumulative histogram in the Prometheus sense: `counts[i]` counts the values up to
+/// the i-th bound, and the last count is the total.
+#[derive(Debug, Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        self.counts.resize(bounds.len() + 1, 0);
+        for (count, &bound) in self.counts.iter_mut().zip(bounds) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        self.counts[bounds.len()] += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, bounds: &[f64]) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (i, bound) in bounds.iter().enumerate() {
+            let count = self.counts.get(i).copied().unwrap_or(0);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let total = self.counts.last().copied().unwrap_or(0);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total);
+        let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, self.sum, name, total);
+    }
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP elevator_episodes_total Episodes judged\n\
+             # TYPE elevator_episodes_total counter\n\
+             elevator_episodes_total {}\n\
+             # HELP elevator_episode_failures_total Requests that could not be judged\n\
+             # TYPE elevator_episode_failures_total counter\n\
+             elevator_episode_failures_total {}\n\
+             # HELP elevator_rejected_total Requests refused for a missing key or a used-up quota\n\
+             # TYPE elevator_rejected_total counter\n\
+             elevator_rejected_total {}\n\
+             # HELP elevator_queue_depth Episodes being judged right now\n\
+             # TYPE elevator_queue_depth gauge\n\
+             elevator_queue_depth {}",
+            self.episodes, self.failures, self.rejected, self.in_flight
+        );
+        self.scores.render(
+            &mut out,
+            "elevator_score",
+            "Scores of judged episodes",
+            &SCORE_BUCKETS,
+        );
+        self.latency.render(
+            &mut out,
+            "elevator_episode_seconds",
+            "Time spent judging one episode",
+            &LATENCY_BUCKETS,
+        );
+        out
+    }
+}
+
+/// An HTTP response from [`JudgeServer::handle`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": message }).to_string())
+    }
+}
+
+/// An episode in progress and who may play it.
+struct OpenEpisode {
+    client: String,
+    _slot: Slot,
+    episode: Episode,
+    started: Instant,
+    last_active: Instant,
+}
+
+#[derive(Serialize)]
+struct ScoreResponse {
+    score: u64,
+    components: ScoreComponents,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// An HTTP request as [`JudgeServer::handle`] sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a str,
+    /// From an `Authorization: Bearer KEY` header
+    pub api_key: Option<&'a str>,
+    /// Address of the client, which quotas are counted against when there are no keys
+    pub remote: &'a str,
+}
+
+/// What one client has used: the start times of its episodes in the last hour and the
+/// number still running.
+#[derive(Debug, Default)]
+struct Usage {
+    recent: VecDeque<Instant>,
+    running: usize,
+}
+
+type UsageTable = Arc<Mutex<HashMap<String, Usage>>>;
+
+/// A running episode's place in its client's `--concurrent` quota, given back when
+/// dropped, so that a request that fails or panics does not keep it.
+struct Slot {
+    usage: UsageTable,
+    client: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(usage) = usage.get_mut(&self.client) {
+            usage.running -= 1;
+        }
+    }
+}
+
+/// Counts one episode in the queue-depth gauge while alive.
+struct InFlight<'a>(&'a Mutex<Metrics>);
+
+impl<'a> InFlight<'a> {
+    fn new(metrics: &'a Mutex<Metrics>) -> Self {
+        metrics.lock().unwrap().in_flight += 1;
+        Self(metrics)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .in_flight -= 1;
+    }
+}
+
+/// A hosted judge: `POST /score` takes a [`ScoreRequest`] as JSON and answers with the
+/// score and diagnostics; `GET /metrics` reports counters in the Prometheus text format.
+///
+/// With [`api_keys`](Self::api_keys), scoring needs one of the keys; the per-client
+/// limits then apply per key, and otherwise per client address.
+///
+/// With a [`contest`](Self::contest), clients also play its hidden cases turn by turn:
+/// `POST /episodes` with `{"case": i}` answers with an episode id and the protocol
+/// header and first observation, and each `POST /episodes/<id>` with a line of actions
+/// answers with the next observation, or with the score after the last turn.
+#[derive(Default)]
+pub struct JudgeServer {
+    metrics: Mutex<Metrics>,
+    api_keys: Option<HashSet<String>>,
+    per_hour: Option<usize>,
+    concurrent: Option<usize>,
+    usage: UsageTable,
+    contest: Option<Contest>,
+    plays_per_case: Option<usize>,
+    /// Episodes each client has started on each case
+    plays: Mutex<HashMap<(String, usize), usize>>,
+    /// Each episode has its own lock, so that playing one turn does not hold up the
+    /// others
+    episodes: Mutex<HashMap<u64, Arc<Mutex<OpenEpisode>>>>,
+    next_episode: AtomicU64,
+}
+
+impl JudgeServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only accept scoring requests carrying one of `keys`.
+    pub fn api_keys(mut self, keys: impl IntoIterator<Item = String>) -> Self {
+        self.api_keys = Some(keys.into_iter().collect());
+        self
+    }
+
+    /// Most episodes one client may start in any hour.
+    pub fn per_hour(mut self, limit: usize) -> Self {
+        self.per_hour = Some(limit);
+        self
+    }
+
+    /// Most episodes one client may have running at once.
+    pub fn concurrent(mut self, limit: usize) -> Self {
+        self.concurrent = Some(limit);
+        self
+    }
+
+    /// Serves the hidden cases of `contest` through `/episodes`.
+    pub fn contest(mut self, contest: Contest) -> Self {
+        self.contest = Some(contest);
+        self
+    }
+
+    /// Most episodes one client may start on each hidden case, so that a case cannot
+    /// be probed by replaying it over and over.
+    pub fn plays_per_case(mut self, limit: usize) -> Self {
+        self.plays_per_case = Some(limit);
+        self
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        match (request.method, request.path) {
+            ("POST", "/score") => {
+                let client = match self.client(request) {
+                    Ok(client) => client,
+                    Err(response) => return self.reject(response),
+                };
+                let _slot = match self.admit(&client, Instant::now()) {
+                    Ok(slot) => slot,
+                    Err(response) => return self.reject(response),
+                };
+                self.score(request.body)
+            }
+            ("POST", "/episodes") if self.contest.is_some() => self.start_episode(request),
+            ("POST", path) if self.contest.is_some() && path.starts_with("/episodes/") => {
+                self.play_episode(request)
+            }
+            ("GET", "/metrics") => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: self.metrics.lock().unwrap().render(),
+            },
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    /// Who quotas are counted against: the API key when keys are required.
+    fn client(&self, request: &Request) -> Result<String, Response> {
+        let Some(ref keys) = self.api_keys else {
+            return Ok(request.remote.to_string());
+        };
+        match request.api_key {
+            Some(key) if keys.contains(key) => Ok(key.to_string()),
+            Some(_) => Err(Response::error(401, "Unknown API key")),
+            None => Err(Response::error(401, "An API key is required")),
+        }
+    }
+
+    fn reject(&self, response: Response) -> Response {
+        self.metrics.lock().unwrap().rejected += 1;
+        response
+    }
+
+    /// Starts an episode for `client` at `now` if its quotas allow it. The episode holds
+    /// the returned slot until it ends.
+    fn admit(&self, client: &str, now: Instant) -> Result<Slot, Response> {
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(client.to_string()).or_default();
+        let hour = Duration::from_secs(3600);
+        while usage
+            .recent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= hour)
+        {
+            usage.recent.pop_front();
+        }
+        if let Some(limit) = self.per_hour
+            && usage.recent.len() >= limit
+        {
+            return Err(Response::error(
+                429,
+                &format!("Quota of {} episodes per hour used up", limit),
+            ));
+        }
+        if let Some(limit) = self.concurrent
+            && usage.running >= limit
+        {
+            return Err(Response::error(
+                429,
+                &format!("At most {} episodes may run at once", limit),
+            ));
+        }
+        usage.recent.push_back(now);
+        usage.running += 1;
+        Ok(Slot {
+            usage: self.usage.clone(),
+            client: client.to_string(),
+        })
+    }
+
+    fn score(&self, body: &str) -> Response {
+        let in_flight = InFlight::new(&self.metrics);
+        let started = Instant::now();
+        let result = serde_json::from_str::<ScoreRequest>(body)
+            .map_err(anyhow::Error::from)
+            .and_then(|request| {
+                let instance = request.instance_capped(Some(MAX_CELLS))?;
+                Ok(instance.replay(&request.output, request.lenient))
+            });
+        drop(in_flight);
+        let mut metrics = self.metrics.lock().unwrap();
+        match result {
+            Ok(report) => {
+                metrics.episodes += 1;
+                metrics.scores.observe(&SCORE_BUCKETS, report.score as f64);
+                let elapsed = started.elapsed().as_secs_f64();
+                metrics.latency.observe(&LATENCY_BUCKETS, elapsed);
+                let response = ScoreResponse {
+                    score: report.score,
+                    components: report.components,
+                    diagnostics: report.diagnostics,
+                };
+                Response::json(200, serde_json::to_string(&response).unwrap())
+            }
+            Err(e) => {
+                metrics.failures += 1;
+                Response::error(400, &format!("{:#}", e))
+            }
+        }
+    }
+
+    fn start_episode(&self, request: &Request) -> Response {
+        let Some(ref contest) = self.contest else {
+            return Response::error(404, "Not found");
+        };
+        let client = match self.client(request) {
+            Ok(client) => client,
+            Err(response) => return self.reject(response),
+        };
+        let now = Instant::now();
+        self.drop_idle(now);
+        let case = serde_json::from_str::<serde_json::Value>(request.body)
+            .ok()
+            .and_then(|body| body["case"].as_u64());
+        let Some(case) = case.map(|case| case as usize) else {
+            return Response::error(400, "Expected a body like {\"case\": 0}");
+        };
+        let (episode, observation) = match contest.start(case) {
+            Ok(started) => started,
+            Err(e) => return Response::error(400, &format!("{:#}", e)),
+        };
+        // Quotas come last, so that a refused request uses up none of them
+        let slot = match self.count_play(&client, case, || self.admit(&client, now)) {
+            Ok(slot) => slot,
+            Err(response) => return self.reject(response),
+        };
+        let id = self.next_episode.fetch_add(1, Ordering::Relaxed);
+        let body = serde_json::json!({
+            "episode": id,
+            "case": episode.case,
+            "split": contest.split.to_string(),
+            "observation": observation,
+        });
+        self.episodes.lock().unwrap().insert(
+            id,
+            Arc::new(Mutex::new(OpenEpisode {
+                client,
+                _slot: slot,
+                episode,
+                started: now,
+                last_active: now,
+            })),
+        );
+        self.metrics.lock().unwrap().in_flight += 1;
+        Response::json(200, body.to_string())
+    }
+
+    /// Counts an episode of `case` for `client` if it has plays left on the case and
+    /// `admit` lets it start.
+    fn count_play(
+        &self,
+        client: &str,
+        case: usize,
+        admit: impl FnOnce() -> Result<Slot, Response>,
+    ) -> Result<Slot, Response> {
+        let mut plays = self.plays.lock().unwrap();
+        let played = plays.entry((client.to_string(), case)).or_default();
+        if let Some(limit) = self.plays_per_case
+            && *played >= limit
+        {
+            return Err(Response::error(
+                429,
+                &format!("Case {} may be played at most {} times", case, limit),
+            ));
+        }
+        let slot = admit()?;
+        *played += 1;
+        Ok(slot)
+    }
+
+    fn play_episode(&self, request: &Request) -> Response {
+        let client = match self.client(request) {
+            Ok(client) => client,
+            Err(response) => return self.reject(response),
+        };
+        self.drop_idle(Instant::now());
+        let id = request.path["/episodes/".len()..].parse::<u64>().ok();
+        let Some(open) = id.and_then(|id| self.episodes.lock().unwrap().get(&id).cloned()) else {
+            return Response::error(404, "No such episode");
+        };
+        let Ok(mut open) = open.lock() else {
+            // A turn panicked halfway: the episode cannot go on, and dropping it gives
+            // back its slot
+            if self.episodes.lock().unwrap().remove(&id.unwrap()).is_some() {
+                self.metrics.lock().unwrap().in_flight -= 1;
+            }
+            return Response::error(500, "The episode failed on an earlier turn");
+        };
+        // Someone else's episode is as good as missing
+        if open.client != client {
+            return Response::error(404, "No such episode");
+        }
+        open.last_active = Instant::now();
+        match open.episode.play(request.body) {
+            Ok(Some(observation)) => Response::json(
+                200,
+                serde_json::json!({ "observation": observation }).to_string(),
+            ),
+            Ok(None) => {
+                let score = open.episode.score();
+                let case = open.episode.case;
+                let elapsed = open.started.elapsed().as_secs_f64();
+                drop(open);
+                // Only the request that removes the episode counts it, in case another
+                // one played the same last turn meanwhile
+                if self.episodes.lock().unwrap().remove(&id.unwrap()).is_none() {
+                    return Response::error(404, "No such episode");
+                }
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.in_flight -= 1;
+                metrics.episodes += 1;
+                metrics.scores.observe(&SCORE_BUCKETS, score as f64);
+                metrics.latency.observe(&LATENCY_BUCKETS, elapsed);
+                let split = self.contest.as_ref().map(|c| c.split.to_string());
+                Response::json(
+                    200,
+                    serde_json::json!({ "score": score, "case": case, "split": split }).to_string(),
+                )
+            }
+            Err(e) => Response::error(400, &format!("{:#}", e)),
+        }
+    }
+
+    /// Drops the episodes nobody has played for [`EPISODE_IDLE`].
+    fn drop_idle(&self, now: Instant) {
+        let mut episodes = self.episodes.lock().unwrap();
+        // An episode being played right now is not idle
+        let idle: Vec<u64> = episodes
+            .iter()
+            .filter_map(|(&id, open)| {
+                let open = match open.try_lock() {
+                    Ok(open) => open,
+                    Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                    Err(TryLockError::WouldBlock) => return None,
+                };
+                (now.duration_since(open.last_active) >= EPISODE_IDLE).then_some(id)
+            })
+            .collect();
+        for id in idle {
+            episodes.remove(&id);
+            self.metrics.lock().unwrap().in_flight -= 1;
+        }
+    }
+
+    /// Serves requests on `addr` (e.g. `127.0.0.1:8080`) with `threads` workers until
+    /// the process is stopped.
+    pub fn serve(&self, addr: &str, threads: usize) -> Result<()> {
+        let server = tiny_http::Server::http(addr).map_err(|e| anyhow!("{}", e))?;
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| {
+                    while let Ok(mut request) = server.recv() {
+                        let mut body = String::new();
+                        let read = request
+                            .as_reader()
+                            .take(MAX_BODY_BYTES + 1)
+                            .read_to_string(&mut body);
+                        let response = match read {
+                            Ok(_) if body.len() as u64 > MAX_BODY_BYTES => Response::error(
+                                413,
+                                &format!("The body is over {} bytes", MAX_BODY_BYTES),
+                            ),
+                            Ok(_) => {
+                                let api_key = request
+                                    .headers()
+                                    .iter()
+                                    .find(|h| h.field.equiv("Authorization"))
+                                    .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                                    .map(str::to_string);
+                                let remote = request
+                                    .remote_addr()
+                                    .map_or(String::new(), |a| a.ip().to_string());
+                                self.handle(&Request {
+                                    method: request.method().as_str(),
+                                    path: request.url(),
+                                    body: &body,
+                                    api_key: api_key.as_deref(),
+                                    remote: &remote,
+                                })
+                            }
+                            Err(_) => Response::error(400, "Body is not UTF-8"),
+                        };
+                        let header = tiny_http::Header::from_bytes(
+                            &b"Content-Type"[..],
+                            response.content_type.as_bytes(),
+                        )
+                        .expect("content types are valid headers");
+                        let reply = tiny_http::Response::from_string(response.body)
+                            .with_status_code(response.status)
+                            .with_header(header);
+                        // A client that hung up should not stop the server
+                        let _ = request.respond(reply);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post<'a>(body: &'a str, api_key: Option<&'a str>) -> Request<'a> {
+        Request {
+            method: "POST",
+            path: "/score",
+            body,
+            api_key,
+            remote: "127.0.0.1",
+        }
+    }
+
+    fn get(path: &str) -> Request<'_> {
+        Request {
+            method: "GET",
+            path,
+            body: "",
+            api_key: None,
+            remote: "127.0.0.1",
+        }
+    }
+
+    #[test]
+    fn test_score_and_metrics() {
+        let server = JudgeServer::new();
+        let request = serde_json::json!({
+            "input": "4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 0 0\n",
+            "output": "OPEN 0\nUP\nOPEN",
+        });
+        let response = server.handle(&post(&request.to_string(), None));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"score":9,"components":{"wait":9,"movement":0,"crowding":0,"long_ride":0},"diagnostics":[]}"#
+        );
+        assert_eq!(server.handle(&post("{}", None)).status, 400);
+        assert_eq!(server.handle(&get("/nowhere")).status, 404);
+
+        let metrics = server.handle(&get("/metrics")).body;
+        assert!(metrics.contains("elevator_episodes_total 1\n"));
+        assert!(metrics.contains("elevator_episode_failures_total 1\n"));
+        assert!(metrics.contains("elevator_queue_depth 0\n"));
+        assert!(metrics.contains("elevator_score_bucket{le=\"1000\"} 1\n"));
+        assert!(metrics.contains("elevator_score_sum 9\n"));
+
+        // Too many floor-turns are refused before anything is allocated
+        let huge = r#"{"input": "2 1 1 1000000000000000000 0.1", "output": ""}"#;
+        assert_eq!(server.handle(&post(huge, None)).status, 400);
+        let metrics = server.handle(&get("/metrics")).body;
+        assert!(metrics.contains("elevator_queue_depth 0\n"));
+    }
+
+    #[test]
+    fn test_keys_and_quotas() {
+        let server = JudgeServer::new()
+            .api_keys(["alice".to_string()])
+            .per_hour(2);
+        let request = r#"{"seed": 0, "output": ""}"#;
+        assert_eq!(server.handle(&post(request, None)).status, 401);
+        assert_eq!(server.handle(&post(request, Some("bob"))).status, 401);
+        assert_eq!(server.handle(&post(request, Some("alice"))).status, 200);
+        assert_eq!(server.handle(&post(request, Some("alice"))).status, 200);
+        assert_eq!(server.handle(&post(request, Some("alice"))).status, 429);
+        let metrics = server.handle(&get("/metrics")).body;
+        assert!(metrics.contains("elevator_rejected_total 3\n"));
+
+        // The hourly window slides, and running episodes count against --concurrent
+        let server = JudgeServer::new().per_hour(1).concurrent(1);
+        let start = Instant::now();
+        let a = server.admit("a", start);
+        assert!(a.is_ok());
+        drop(server.admit("b", start));
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(server.admit("a", later).err().unwrap().status, 429);
+        drop(a);
+        assert!(server.admit("a", later).is_ok());
+
+        // A request that panics still gives its slot back
+        let server = JudgeServer::new().concurrent(1);
+        let panicked = std::panic::catch_unwind(|| {
+            let _slot = server.admit("a", start);
+            panic!("judging failed");
+        });
+        assert!(panicked.is_err());
+        assert!(server.admit("a", start).is_ok());
+    }
+
+    #[test]
+    fn test_hidden_episodes() -> anyhow::Result<()> {
+        let server = JudgeServer::new()
+            .concurrent(1)
+            .contest(Contest::new("secret", 2, 0));
+        let start = |body: &str| {
+            server.handle(&Request {
+                path: "/episodes",
+                ..post(body, None)
+            })
+        };
+        assert_eq!(start(r#"{"case": 2}"#).status, 400);
+        let response = start(r#"{"case": 1}"#);
+        assert_eq!(response.status, 200);
+        let body: serde_json::Value = serde_json::from_str(&response.body)?;
+        assert_eq!(body["split"], "provisional");
+        assert!(body["observation"].as_str().is_some());
+        // The open episode holds the client's only slot
+        assert_eq!(start(r#"{"case": 0}"#).status, 429);
+
+        let path = format!("/episodes/{}", body["episode"]);
+        let m = crate::SimParams::default().m;
+        let stay = vec!["STAY"; m].join(";");
+        let play = |line: &str| {
+            server.handle(&Request {
+                path: &path,
+                ..post(line, None)
+            })
+        };
+        assert_eq!(play("JUMP").status, 400);
+        // One action too many is refused without breaking the episode
+        assert_eq!(play(&vec!["STAY"; m + 1].join(";")).status, 400);
+        let mut last = play(&stay);
+        while last.status == 200 && last.body.contains("observation") {
+            last = play(&stay);
+        }
+        let body: serde_json::Value = serde_json::from_str(&last.body)?;
+        assert!(body["score"].as_u64().is_some_and(|score| score > 0));
+        assert!(!last.body.contains("seed"));
+        assert_eq!(play(&stay).status, 404);
+        assert_eq!(start(r#"{"case": 0}"#).status, 200);
+
+        let server = JudgeServer::new()
+            .plays_per_case(1)
+            .contest(Contest::new("secret", 2, 0));
+        let start = |body: &str| {
+            server.handle(&Request {
+                path: "/episodes",
+                ..post(body, None)
+            })
+        };
+        assert_eq!(start(r#"{"case": 0}"#).status, 200);
+        assert_eq!(start(r#"{"case": 0}"#).status, 429);
+        assert_eq!(start(r#"{"case": 1}"#).status, 200);
+
+        // Refused requests leave the hourly quota alone
+        let server = JudgeServer::new()
+            .per_hour(2)
+            .plays_per_case(1)
+            .contest(Contest::new("secret", 2, 0));
+        let start = |body: &str| {
+            server.handle(&Request {
+                path: "/episodes",
+                ..post(body, None)
+            })
+        };
+        assert_eq!(start("{case: 0}").status, 400);
+        assert_eq!(start(r#"{"case": 2}"#).status, 400);
+        assert_eq!(start(r#"{"case": 0}"#).status, 200);
+        assert_eq!(start(r#"{"case": 0}"#).status, 429);
+        assert_eq!(start(r#"{"case": 1}"#).status, 200);
+        Ok(())
+    }
+}
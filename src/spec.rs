@@ -0,0 +1,145 @@
+//! Version numbers and defaults of the formats this build speaks, in one place, with a
+//! serializable [`Spec`] so that tools can check what a build supports.
+
+use crate::{ProblemVariant, SimParams};
+use serde::Serialize;
+
+/// Highest protocol version the judge can speak: the last of [`PROTOCOL_CHANGES`].
+pub const PROTOCOL_VERSION: u32 = PROTOCOL_CHANGES[PROTOCOL_CHANGES.len() - 1].0;
+
+/// Highest input format version: 1 is the text format, 2 the JSON one.
+pub const INPUT_VERSION: u32 = 2;
+
+/// Highest output format version: 1 is one line per elevator per turn, 2 tagged lines.
+pub const OUTPUT_VERSION: u32 = 2;
+
+/// Version of the [`ReplayFile`](crate::ReplayFile) format.
+pub const REPLAY_VERSION: u32 = 1;
+
+pub const DEFAULT_N: usize = 10;
+pub const DEFAULT_M: usize = 3;
+pub const DEFAULT_C: usize = 10;
+pub const DEFAULT_T: usize = 100;
+pub const DEFAULT_LAMBDA: f64 = 0.1;
+/// Picks an `OPEN` may list beyond the elevator's capacity by default.
+pub const DEFAULT_PICK_SLACK: usize = 64;
+
+/// What each protocol version adds to the one before, from version 2 on. This table is
+/// the protocol's description: `elevator-sim spec` and `spec_wasm` report it as is.
+pub const PROTOCOL_CHANGES: &[(u32, &str)] = &[
+    (
+        2,
+        "a line with the agent's remaining time in milliseconds (-1 when unlimited) at \
+         the start of every turn",
+    ),
+    (3, "one capability line per elevator right after the header"),
+    (
+        4,
+        "one line per elevator holding its label (empty when unnamed) after the \
+         capabilities",
+    ),
+    (
+        5,
+        "one line with the label of every floor from the bottom (indices when unnamed) \
+         after the elevator labels",
+    ),
+    (
+        6,
+        "a line 'a_1 b_1 ... a_M b_M' after the time line: per elevator, how many picked \
+         passengers did not board and how many got off early in the previous turn",
+    ),
+    (
+        7,
+        "the action budget left (-1 without a budget) after the noise counts",
+    ),
+    (
+        8,
+        "sparse waiting passengers: a line with the number of floors where someone \
+         waits, then one line per such floor, led by the floor",
+    ),
+    (
+        9,
+        "the agent answers each turn with one line holding all M actions separated by ';'",
+    ),
+    (
+        10,
+        "a line 'K f_1 i_1 g_1 ... f_K i_K g_K' after the budget with this turn's \
+         destination changes: the i-th passenger waiting at floor f now wants to go to \
+         floor g",
+    ),
+    (
+        11,
+        "one line 'b i_1 ... i_b s j_1 ... j_s' per elevator after the destination \
+         changes on its OPEN in the previous turn: the passenger IDs of the picks that \
+         boarded and of those left waiting because the elevator was full",
+    ),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolChange {
+    pub version: u32,
+    pub adds: &'static str,
+}
+
+/// What this build supports, as reported by `elevator-sim spec` and `spec_wasm`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Spec {
+    pub crate_version: &'static str,
+    pub protocol_version: u32,
+    pub input_version: u32,
+    pub output_version: u32,
+    pub replay_version: u32,
+    pub protocol_changes: Vec<ProtocolChange>,
+    pub variants: Vec<&'static str>,
+    /// Optional features compiled in
+    pub features: Vec<&'static str>,
+    pub defaults: SimParams,
+}
+
+pub fn spec() -> Spec {
+    let mut features = vec![];
+    if cfg!(feature = "batch") {
+        features.push("batch");
+    }
+    if cfg!(feature = "results-db") {
+        features.push("results-db");
+    }
+    if cfg!(feature = "server") {
+        features.push("server");
+    }
+    Spec {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        input_version: INPUT_VERSION,
+        output_version: OUTPUT_VERSION,
+        replay_version: REPLAY_VERSION,
+        protocol_changes: PROTOCOL_CHANGES
+            .iter()
+            .map(|&(version, adds)| ProtocolChange { version, adds })
+            .collect(),
+        variants: ProblemVariant::ALL.iter().map(|v| v.name()).collect(),
+        features,
+        defaults: SimParams::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_changes_cover_every_version() {
+        let versions: Vec<u32> = PROTOCOL_CHANGES.iter().map(|&(v, _)| v).collect();
+        assert_eq!(versions, (2..=PROTOCOL_VERSION).collect::<Vec<_>>());
+        let spec = spec();
+        assert_eq!(spec.defaults.n, DEFAULT_N);
+        assert_eq!(spec.variants.len(), ProblemVariant::ALL.len());
+        for (feature, on) in [
+            ("batch", cfg!(feature = "batch")),
+            ("results-db", cfg!(feature = "results-db")),
+            ("server", cfg!(feature = "server")),
+        ] {
+            assert_eq!(spec.features.contains(&feature), on, "{}", feature);
+        }
+    }
+}
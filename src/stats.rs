@@ -0,0 +1,573 @@
+use crate::{Direction, Event, Passenger, ScoreWeights, Scoring, SimParams, Snapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Elevator labels, when the run named its elevators.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Turns from arrival until boarding, over passengers that boarded.
+    pub mean_wait: f64,
+    pub p95_wait: usize,
+    pub max_wait: usize,
+    pub delivered: usize,
+    /// Average load factor of each elevator over the run.
+    pub utilization: Vec<f64>,
+    /// Turns each elevator spent empty and not moving.
+    pub idle_turns: Vec<usize>,
+    /// Waiting passengers per floor, indexed as `[turn][floor]`.
+    pub heatmap: Vec<Vec<usize>>,
+}
+
+impl RunStats {
+    pub fn from_history(history: &[Snapshot], capacity: usize) -> Self {
+        let m = history.first().map_or(0, |s| s.elevators.len());
+        let mut arrivals = HashMap::new();
+        let mut waits = vec![];
+        let mut delivered = 0;
+        let mut load = vec![0.0; m];
+        let mut idle_turns = vec![0; m];
+        let mut heatmap = Vec::with_capacity(history.len());
+
+        for snapshot in history {
+            for p in &snapshot.arrivals {
+                arrivals.insert(p.id, p);
+            }
+            for event in &snapshot.events {
+                match event {
+                    Event::Boarded { passenger_id, .. } => {
+                        if let Some(p) = arrivals.get(passenger_id) {
+                            waits.push(p.waited(snapshot.turn));
+                        }
+                    }
+                    Event::Delivered { .. } => delivered += 1,
+                    _ => {}
+                }
+            }
+            for (i, e) in snapshot.elevators.iter().enumerate() {
+                load[i] += e.passenger_count as f64 / capacity.max(1) as f64;
+                if e.passenger_count == 0 && e.direction == Direction::Idle {
+                    idle_turns[i] += 1;
+                }
+            }
+            heatmap.push(snapshot.floors.iter().map(|f| f.waiting_count).collect());
+        }
+
+        waits.sort_unstable();
+        let turns = history.len().max(1) as f64;
+        let labels = history
+            .first()
+            .map(|s| {
+                s.elevators
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| e.label.clone().unwrap_or_else(|| i.to_string()))
+                    .collect()
+            })
+            .filter(|_| history[0].elevators.iter().any(|e| e.label.is_some()))
+            .unwrap_or_default();
+        Self {
+            labels,
+            mean_wait: if waits.is_empty() {
+                0.0
+            } else {
+                waits.iter().sum::<usize>() as f64 / waits.len() as f64
+            },
+            p95_wait: percentile(&waits, 0.95),
+            max_wait: waits.last().copied().unwrap_or(0),
+            delivered,
+            utilization: load.into_iter().map(|l| l / turns).collect(),
+            idle_turns,
+            heatmap,
+        }
+    }
+}
+
+/// Compact end-of-run figures, collected one snapshot at a time so that a long run
+/// does not have to keep its history. Penalties are weighted by `SimParams::weights`
+/// as in the score.
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    pub score: u64,
+    /// Turn with the most boardings and deliveries, and that count.
+    pub busiest_turn: (usize, usize),
+    /// Most passengers waiting at once, over all floors.
+    pub max_queue: usize,
+    /// Delivery penalty added in each turn.
+    pub score_deltas: Vec<u64>,
+    /// Score added for crowded elevators (`SimParams::comfort_threshold`), which the
+    /// breakdowns by floor and elevator leave out.
+    pub comfort_penalty: u64,
+    /// Score added for rides over `SimParams::max_ride`, likewise left out of them.
+    pub ride_penalty: u64,
+    waits: Vec<usize>,
+    arrivals: HashMap<usize, Passenger>,
+    last_score: u64,
+    // Origin floor of every passenger that has boarded
+    origins: HashMap<usize, usize>,
+    // Penalties of delivered passengers, and of those still travelling after the last turn
+    floor_penalty: Vec<u64>,
+    elevator_penalty: Vec<u64>,
+    floor_pending: Vec<u64>,
+    elevator_pending: Vec<u64>,
+    warmup: usize,
+    scoring: Scoring,
+    weights: ScoreWeights,
+}
+
+impl RunSummary {
+    /// A summary of a run under a warm-up of `warmup` turns (see `SimParams::warmup`).
+    pub fn with_warmup(warmup: usize) -> Self {
+        Self {
+            warmup,
+            ..Self::default()
+        }
+    }
+
+    /// A summary of a run under `params`' warm-up, scoring policy and weights.
+    pub fn for_params(params: &SimParams) -> Self {
+        Self {
+            scoring: params.scoring,
+            weights: params.weights,
+            ..Self::with_warmup(params.warmup)
+        }
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot) {
+        for p in &snapshot.arrivals {
+            self.arrivals.insert(p.id, p.clone());
+        }
+        let (n, m) = (snapshot.floors.len(), snapshot.elevators.len());
+        self.floor_penalty.resize(n, 0);
+        self.elevator_penalty.resize(m, 0);
+        let mut busy = 0;
+        for event in &snapshot.events {
+            match *event {
+                Event::Boarded {
+                    passenger_id,
+                    floor,
+                    ..
+                } => {
+                    if let Some(p) = self.arrivals.remove(&passenger_id) {
+                        self.waits.push(p.waited(snapshot.turn));
+                    }
+                    self.origins.entry(passenger_id).or_insert(floor);
+                    busy += 1;
+                }
+                Event::Delivered {
+                    passenger_id,
+                    elevator,
+                    penalty,
+                    ..
+                } => {
+                    let penalty = penalty * self.weights.wait;
+                    if let Some(&origin) = self.origins.get(&passenger_id) {
+                        self.floor_penalty[origin] += penalty;
+                    }
+                    self.elevator_penalty[elevator] += penalty;
+                    busy += 1;
+                }
+                Event::Crowded { penalty, .. } => {
+                    self.comfort_penalty += penalty * self.weights.crowding
+                }
+                Event::RideTooLong { penalty, .. } => {
+                    self.ride_penalty += penalty * self.weights.long_ride
+                }
+                _ => {}
+            }
+        }
+
+        // Passengers not yet delivered, scored as if the run ended after this turn
+        let unfinished = |p: &Passenger| {
+            p.penalty(snapshot.turn + 1, self.warmup, &self.scoring) * self.weights.wait
+        };
+        self.floor_pending = vec![0; n];
+        self.elevator_pending = vec![0; m];
+        for (floor, f) in snapshot.floors.iter().enumerate() {
+            for p in &f.waiting {
+                let origin = self.origins.get(&p.id).copied().unwrap_or(floor);
+                self.floor_pending[origin] += unfinished(p);
+            }
+        }
+        for (i, e) in snapshot.elevators.iter().enumerate() {
+            for p in &e.passengers {
+                if let Some(&origin) = self.origins.get(&p.id) {
+                    self.floor_pending[origin] += unfinished(p);
+                }
+                self.elevator_pending[i] += unfinished(p);
+            }
+        }
+        if busy > self.busiest_turn.1 {
+            self.busiest_turn = (snapshot.turn, busy);
+        }
+        let queue = snapshot.floors.iter().map(|f| f.waiting_count).sum();
+        self.max_queue = self.max_queue.max(queue);
+        self.score_deltas.push(snapshot.score - self.last_score);
+        self.last_score = snapshot.score;
+    }
+
+    pub fn from_history(history: &[Snapshot], score: u64, params: &SimParams) -> Self {
+        let mut summary = Self::for_params(params);
+        for snapshot in history {
+            summary.push(snapshot);
+        }
+        summary.score = score;
+        summary
+    }
+
+    /// Passenger penalties by origin floor, counting passengers still travelling after
+    /// the last pushed turn as the final score does.
+    pub fn score_by_floor(&self) -> Vec<u64> {
+        add(&self.floor_penalty, &self.floor_pending)
+    }
+
+    /// Passenger penalties by the elevator that delivered or is carrying the passenger.
+    /// Passengers that never boarded are not attributed to any elevator.
+    pub fn score_by_elevator(&self) -> Vec<u64> {
+        add(&self.elevator_penalty, &self.elevator_pending)
+    }
+
+    pub fn p95_wait(&self) -> usize {
+        let mut waits = self.waits.clone();
+        waits.sort_unstable();
+        percentile(&waits, 0.95)
+    }
+
+    /// Score deltas in at most `width` buckets, drawn with block characters.
+    pub fn sparkline(&self, width: usize) -> String {
+        const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        let chunk = self.score_deltas.len().div_ceil(width.max(1)).max(1);
+        let buckets: Vec<u64> = self
+            .score_deltas
+            .chunks(chunk)
+            .map(|c| c.iter().sum())
+            .collect();
+        let max = buckets.iter().copied().max().unwrap_or(0).max(1);
+        buckets
+            .iter()
+            .map(|&b| BARS[(b * (BARS.len() as u64 - 1)).div_ceil(max) as usize])
+            .collect()
+    }
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Busiest turn: {} ({} boardings and deliveries)",
+            self.busiest_turn.0, self.busiest_turn.1
+        )?;
+        writeln!(f, "Max queue: {}", self.max_queue)?;
+        writeln!(f, "p95 wait: {}", self.p95_wait())?;
+        writeln!(f, "Score by floor: {}", join(&self.score_by_floor()))?;
+        writeln!(f, "Score by elevator: {}", join(&self.score_by_elevator()))?;
+        if self.comfort_penalty > 0 {
+            writeln!(f, "Comfort penalty: {}", self.comfort_penalty)?;
+        }
+        if self.ride_penalty > 0 {
+            writeln!(f, "Ride penalty: {}", self.ride_penalty)?;
+        }
+        write!(f, "Score deltas: {}", self.sparkline(50))
+    }
+}
+
+fn add(a: &[u64], b: &[u64]) -> Vec<u64> {
+    a.iter().zip(b).map(|(x, y)| x + y).collect()
+}
+
+fn join(values: &[u64]) -> String {
+    let values: Vec<String> = values.iter().map(u64::to_string).collect();
+    values.join(" ")
+}
+
+/// One delivered passenger in [`Statistics`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PassengerRecord {
+    pub id: usize,
+    /// Turns from arrival until first boarding
+    pub wait: usize,
+    /// Turns from first boarding until delivery, transfers included
+    pub ride: usize,
+    pub delivered_turn: usize,
+    /// The elevator that delivered them; `None` when they arrived at their destination
+    pub elevator: Option<usize>,
+}
+
+/// Per-passenger outcomes of a run, as collected by the simulation while it plays;
+/// see [`crate::SimulationState::statistics`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Statistics {
+    /// Delivered passengers, in delivery order
+    pub passengers: Vec<PassengerRecord>,
+    pub max_wait: usize,
+    pub p95_wait: usize,
+    /// Deliveries per turn played, by elevator
+    pub throughput: Vec<f64>,
+}
+
+/// Builds [`Statistics`] from boardings and deliveries as they happen.
+#[derive(Clone, Default)]
+pub(crate) struct StatsCollector {
+    // Turn and wait of each passenger's first boarding, until they are delivered
+    boarded: HashMap<usize, (usize, usize)>,
+    records: Vec<PassengerRecord>,
+    deliveries: Vec<usize>,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(m: usize) -> Self {
+        Self {
+            deliveries: vec![0; m],
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn boarded(&mut self, passenger: &Passenger, turn: usize) {
+        self.boarded
+            .entry(passenger.id)
+            .or_insert((turn, passenger.waited(turn)));
+    }
+
+    pub(crate) fn delivered(
+        &mut self,
+        passenger: &Passenger,
+        elevator: Option<usize>,
+        turn: usize,
+    ) {
+        let (boarded, wait) = self
+            .boarded
+            .remove(&passenger.id)
+            .unwrap_or((turn, passenger.waited(turn)));
+        if let Some(e) = elevator {
+            self.deliveries[e] += 1;
+        }
+        self.records.push(PassengerRecord {
+            id: passenger.id,
+            wait,
+            ride: turn - boarded,
+            delivered_turn: turn,
+            elevator,
+        });
+    }
+
+    /// The statistics after `turns` turns.
+    pub(crate) fn statistics(&self, turns: usize) -> Statistics {
+        let mut waits: Vec<usize> = self.records.iter().map(|r| r.wait).collect();
+        waits.sort_unstable();
+        Statistics {
+            passengers: self.records.clone(),
+            max_wait: waits.last().copied().unwrap_or(0),
+            p95_wait: percentile(&waits, 0.95),
+            throughput: self
+                .deliveries
+                .iter()
+                .map(|&d| d as f64 / turns.max(1) as f64)
+                .collect(),
+        }
+    }
+}
+
+/// Spread of the scores over a set of cases. Lower scores are better, so the worst
+/// case is the highest-scoring one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScoreSummary {
+    pub cases: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    /// The worst case's name and score, unless there were no cases
+    pub worst: Option<(String, u64)>,
+}
+
+impl ScoreSummary {
+    pub fn from_scores(scores: &[(String, u64)]) -> Self {
+        if scores.is_empty() {
+            return Self::default();
+        }
+        let n = scores.len() as f64;
+        let mut sorted: Vec<u64> = scores.iter().map(|(_, s)| *s).collect();
+        sorted.sort_unstable();
+        let mean = sorted.iter().sum::<u64>() as f64 / n;
+        let mid = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[mid - 1] + sorted[mid]) as f64 / 2.0
+        } else {
+            sorted[mid] as f64
+        };
+        let variance = sorted
+            .iter()
+            .map(|&s| (s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        // The first of equally bad cases, so that the report is stable
+        let worst = scores
+            .iter()
+            .rev()
+            .max_by_key(|(_, s)| *s)
+            .map(|(case, s)| (case.clone(), *s));
+        Self {
+            cases: scores.len(),
+            mean,
+            median,
+            stddev: variance.sqrt(),
+            worst,
+        }
+    }
+}
+
+impl std::fmt::Display for ScoreSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Cases: {}", self.cases)?;
+        writeln!(f, "Mean: {:.1}", self.mean)?;
+        writeln!(f, "Median: {:.1}", self.median)?;
+        write!(f, "Stddev: {:.1}", self.stddev)?;
+        if let Some((case, score)) = &self.worst {
+            write!(f, "\nWorst: {} ({})", case, score)?;
+        }
+        Ok(())
+    }
+}
+
+/// Nearest-rank percentile of an ascending slice; 0 when empty.
+pub fn percentile(sorted: &[usize], q: f64) -> usize {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, SimulationState};
+    use anyhow::Result;
+
+    #[test]
+    fn test_run_stats() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 2, 3)?;
+        let mut history = vec![];
+        sim.add_passenger(5, 6, 0, 0)?;
+        sim.apply_action(0, &Action::Stay)?;
+        history.push(sim.create_snapshot());
+        sim.turn = 1;
+        sim.apply_action(0, &Action::open(&[0]))?;
+        history.push(sim.create_snapshot());
+        sim.turn = 2;
+        sim.apply_action(0, &Action::Up)?;
+        history.push(sim.create_snapshot());
+
+        let stats = RunStats::from_history(&history, 2);
+        assert_eq!(stats.mean_wait, 1.0);
+        assert_eq!(stats.p95_wait, 1);
+        assert_eq!(stats.idle_turns, vec![1]);
+        assert!((stats.utilization[0] - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.heatmap[0][5], 1);
+        assert_eq!(stats.heatmap[1][5], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_summary() -> Result<()> {
+        let mut sim = SimulationState::new(10, 1, 2, 3)?;
+        let mut summary = RunSummary::default();
+        sim.add_passenger(5, 6, 0, 0)?;
+        sim.add_passenger(5, 4, 0, 1)?;
+        sim.add_passenger(2, 4, 0, 2)?;
+        sim.apply_action(0, &Action::open(&[0, 1]))?;
+        summary.push(&sim.create_snapshot());
+        sim.turn = 1;
+        sim.apply_action(0, &Action::Up)?;
+        summary.push(&sim.create_snapshot());
+        sim.turn = 2;
+        sim.apply_action(0, &Action::open(&[]))?;
+        summary.push(&sim.create_snapshot());
+
+        assert_eq!(summary.busiest_turn, (0, 2));
+        assert_eq!(summary.max_queue, 1);
+        assert_eq!(summary.score_deltas, vec![0, 0, 9]);
+        assert_eq!(summary.sparkline(50), "▁▁█");
+        // Delivered at turn 2 (9), still riding (9) and never picked up (9)
+        assert_eq!(summary.score_by_floor()[5], 18);
+        assert_eq!(summary.score_by_floor()[2], 9);
+        assert_eq!(summary.score_by_elevator(), vec![18]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_summary() -> Result<()> {
+        let params = SimParams {
+            n: 4,
+            m: 1,
+            c: 4,
+            t: 6,
+            comfort_threshold: Some(0.5),
+            comfort_penalty: 10,
+            max_ride: Some(2),
+            ride_penalty: 100,
+            weights: "wait=2,crowding=3,long_ride=5".parse()?,
+            ..SimParams::default()
+        };
+        let mut targets = vec![vec![vec![]; 6]; 4];
+        targets[2][0] = vec![0, 0, 0];
+        targets[3][1] = vec![1];
+        let instance = crate::Instance::from_targets(params, targets)?;
+        let report = instance.replay("OPEN 0 1 2\nDOWN\nSTAY\nDOWN\nOPEN", false);
+        let summary = RunSummary::from_history(&report.history, report.score, &instance.params);
+        assert!(summary.comfort_penalty > 0 && summary.ride_penalty > 0);
+        let by_floor: u64 = summary.score_by_floor().iter().sum();
+        assert_eq!(
+            by_floor + summary.comfort_penalty + summary.ride_penalty,
+            report.score
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 0.95), 0);
+        assert_eq!(percentile(&[1, 2, 3, 4], 0.5), 2);
+        assert_eq!(percentile(&[1, 2, 3, 4], 0.95), 4);
+    }
+
+    #[test]
+    fn test_score_summary() {
+        let scores: Vec<(String, u64)> = [("a", 10), ("b", 30), ("c", 20), ("d", 30)]
+            .iter()
+            .map(|(case, s)| (case.to_string(), *s))
+            .collect();
+        let summary = ScoreSummary::from_scores(&scores);
+        assert_eq!(summary.cases, 4);
+        assert_eq!(summary.mean, 22.5);
+        assert_eq!(summary.median, 25.0);
+        assert!((summary.stddev - 8.291).abs() < 1e-3);
+        assert_eq!(summary.worst, Some(("b".to_string(), 30)));
+        assert_eq!(ScoreSummary::from_scores(&[]).worst, None);
+    }
+
+    #[test]
+    fn test_statistics() -> Result<()> {
+        let instance = crate::Instance::parse("4 1 10 3 0.1\n0 0 0\n0 0 0\n1 3 0 0\n0 1 1 0\n")?;
+        let report = instance.replay("OPEN 0\nUP\nOPEN", false);
+        assert!(report.history[..2].iter().all(|s| s.statistics.is_none()));
+        let statistics = report.history[2].statistics.clone().unwrap();
+        assert_eq!(
+            statistics.passengers,
+            vec![PassengerRecord {
+                id: 0,
+                wait: 0,
+                ride: 2,
+                delivered_turn: 2,
+                elevator: Some(0),
+            }]
+        );
+        assert_eq!((statistics.max_wait, statistics.p95_wait), (0, 0));
+        assert_eq!(statistics.throughput, vec![1.0 / 3.0]);
+
+        let mut sim = SimulationState::from_instance(&instance)?;
+        sim.step(&[Action::open(&[0])])?;
+        assert!(sim.statistics().passengers.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{ArrivalProcess, SimParams};
+use anyhow::{Context, Result, bail};
+
+/// Arrivals replayed from a traffic log: CSV rows of `time,origin,destination`, with an
+/// optional header row. Times are seconds, or `HH:MM` / `HH:MM:SS`; floors are indices
+/// or floor labels.
+pub struct TraceArrivals {
+    // Targets per `[floor][turn]`
+    targets: Vec<Vec<Vec<usize>>>,
+    /// Rows falling after the last turn or going nowhere (origin = destination)
+    pub dropped: usize,
+}
+
+impl TraceArrivals {
+    /// Maps the log onto `params.t` turns of `seconds_per_turn` each, starting at the
+    /// earliest time in the log.
+    pub fn parse(text: &str, params: &SimParams, seconds_per_turn: f64) -> Result<Self> {
+        if seconds_per_turn <= 0.0 {
+            bail!("Seconds per turn must be positive");
+        }
+        let rows = read_rows(text, params)?;
+        let start = rows.iter().map(|r| r.0).fold(f64::INFINITY, f64::min);
+        let mut targets = vec![vec![vec![]; params.t]; params.n];
+        let mut dropped = 0;
+        for (time, origin, destination) in rows {
+            let turn = ((time - start) / seconds_per_turn) as usize;
+            if turn >= params.t || origin == destination {
+                dropped += 1;
+                continue;
+            }
+            targets[origin][turn].push(destination);
+        }
+        Ok(Self { targets, dropped })
+    }
+
+    /// Turns needed to cover the whole log at `seconds_per_turn`.
+    pub fn span(text: &str, params: &SimParams, seconds_per_turn: f64) -> Result<usize> {
+        if seconds_per_turn <= 0.0 {
+            bail!("Seconds per turn must be positive");
+        }
+        let rows = read_rows(text, params)?;
+        let start = rows.iter().map(|r| r.0).fold(f64::INFINITY, f64::min);
+        let end = rows.iter().map(|r| r.0).fold(start, f64::max);
+        Ok(((end - start) / seconds_per_turn) as usize + 1)
+    }
+}
+
+impl ArrivalProcess for TraceArrivals {
+    fn sample(&mut self, floor: usize, turn: usize) -> Vec<usize> {
+        std::mem::take(&mut self.targets[floor][turn])
+    }
+}
+
+fn read_rows(text: &str, params: &SimParams) -> Result<Vec<(f64, usize, usize)>> {
+    let floor = |s: &str| -> Result<usize> {
+        let floor = match s.parse::<usize>() {
+            Ok(floor) => floor,
+            Err(_) => params
+                .floor_index(s)
+                .with_context(|| format!("Unknown floor: {}", s))?,
+        };
+        if floor >= params.n {
+            bail!("Floor {} is out of range", floor);
+        }
+        Ok(floor)
+    };
+    let mut rows = vec![];
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [time, origin, destination] = fields[..] else {
+            bail!("Line {}: expected time,origin,destination", i + 1);
+        };
+        let Some(time) = parse_time(time) else {
+            if i == 0 {
+                continue; // header
+            }
+            bail!("Line {}: invalid time: {}", i + 1, time);
+        };
+        rows.push((time, floor(origin)?, floor(destination)?));
+    }
+    if rows.is_empty() {
+        bail!("The trace has no arrivals");
+    }
+    Ok(rows)
+}
+
+/// Seconds from `S`, `HH:MM` or `HH:MM:SS`.
+fn parse_time(s: &str) -> Option<f64> {
+    let parts: Vec<f64> = s
+        .split(':')
+        .map(|p| p.parse::<f64>().ok().filter(|x| x.is_finite()))
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [seconds] => Some(seconds),
+        [h, m] => Some(h * 3600.0 + m * 60.0),
+        [h, m, sec] => Some(h * 3600.0 + m * 60.0 + sec),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instance;
+
+    #[test]
+    fn test_trace_arrivals() -> Result<()> {
+        let params = SimParams {
+            n: 4,
+            t: 5,
+            floor_labels: vec!["B1".into(), "1F".into(), "2F".into(), "3F".into()],
+            ..SimParams::default()
+        };
+        let csv = "time,origin,destination\n\
+                   08:00:00,1F,3F\n\
+                   08:00:25,0,2\n\
+                   08:00:31,2F,2F\n\
+                   08:01:05,3,1\n\
+                   08:09:00,1,2\n";
+        let mut trace = TraceArrivals::parse(csv, &params, 30.0)?;
+        assert_eq!(trace.dropped, 2);
+        let instance = Instance::from_process(&params, &mut trace)?;
+        assert_eq!(instance.passengers[1][0][0].target_floor, 3);
+        assert_eq!(instance.passengers[0][0][0].target_floor, 2);
+        assert_eq!(instance.passengers[3][2][0].target_floor, 1);
+        assert_eq!(TraceArrivals::span(csv, &params, 30.0)?, 19);
+
+        assert!(TraceArrivals::parse("0,1,9\n", &params, 30.0).is_err());
+        Ok(())
+    }
+}
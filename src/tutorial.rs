@@ -0,0 +1,263 @@
+use crate::{Diagnostic, Event, Instance, ReplayReport, Scenario, Snapshot};
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::fmt;
+
+/// A success criterion of a [`Lesson`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Goal {
+    /// Every passenger delivered before turn `within`
+    DeliverAll { within: usize },
+    /// Passenger `id` delivered before turn `within`
+    Deliver { id: usize, within: usize },
+    /// A final score of at most `score`
+    ScoreAtMost { score: u64 },
+    /// No invalid lines (a short output is fine: the rest is `STAY`)
+    Valid,
+}
+
+impl Goal {
+    fn met(self, report: &ReplayReport, passengers: usize) -> bool {
+        let delivered_before = |within: usize| {
+            report
+                .history
+                .iter()
+                .filter(|s| s.turn < within)
+                .flat_map(|s| &s.events)
+                .filter_map(|e| match *e {
+                    Event::Delivered { passenger_id, .. }
+                    | Event::DeliveredOnArrival { passenger_id, .. } => Some(passenger_id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Goal::DeliverAll { within } => delivered_before(within).len() == passengers,
+            Goal::Deliver { id, within } => delivered_before(within).contains(&id),
+            Goal::ScoreAtMost { score } => report.score <= score,
+            Goal::Valid => report
+                .diagnostics
+                .iter()
+                .all(|d| d.message.starts_with("Output too short")),
+        }
+    }
+}
+
+impl fmt::Display for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Goal::DeliverAll { within } => {
+                write!(f, "Deliver every passenger within {} turns", within)
+            }
+            Goal::Deliver { id, within } => {
+                write!(f, "Deliver passenger {} within {} turns", id, within)
+            }
+            Goal::ScoreAtMost { score } => write!(f, "Finish with a score of at most {}", score),
+            Goal::Valid => write!(f, "Write only valid actions"),
+        }
+    }
+}
+
+/// A small scripted scenario with goals, for learning what the actions do.
+#[derive(Debug, Clone, Serialize)]
+pub struct Lesson {
+    pub name: &'static str,
+    pub title: &'static str,
+    /// What the lesson teaches and what to try
+    pub text: &'static str,
+    pub goals: Vec<Goal>,
+    #[serde(skip)]
+    scenario: Scenario,
+}
+
+/// How an output did on a [`Lesson`].
+#[derive(Clone, Serialize)]
+pub struct LessonReport {
+    pub lesson: &'static str,
+    pub passed: bool,
+    pub score: u64,
+    /// Each goal's description and whether it was met
+    pub goals: Vec<(String, bool)>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub history: Vec<Snapshot>,
+}
+
+impl Lesson {
+    /// Names of the lessons, in teaching order.
+    pub const NAMES: [&str; 5] = [
+        "first-ride",
+        "go-fetch",
+        "capacity",
+        "two-elevators",
+        "let-out",
+    ];
+
+    pub fn get(name: &str) -> Result<Self> {
+        let Some(name) = Self::NAMES.into_iter().find(|&n| n == name) else {
+            bail!(
+                "Unknown lesson {:?} (expected one of {})",
+                name,
+                Self::NAMES.join(", ")
+            );
+        };
+        let (title, text, scenario, goals) = match name {
+            "first-ride" => (
+                "Your first ride",
+                "The elevator starts at floor 2, where a passenger waits for floor 4. \
+                 `OPEN 0` boards the first passenger in the floor's line, `UP` moves one \
+                 floor, and `OPEN` at floor 4 lets them out.",
+                Scenario::new(5, 1).turns(10).passenger(2, 4, 0),
+                vec![Goal::Valid, Goal::DeliverAll { within: 4 }],
+            ),
+            "go-fetch" => (
+                "Go and fetch",
+                "Elevators start in the middle of the building. This passenger waits at the \
+                 ground floor for the top: go down, board them, and take them up. The \
+                 score is the square of the turns each trip took, waiting included.",
+                Scenario::new(7, 1).turns(16).passenger(0, 6, 0),
+                vec![
+                    Goal::Valid,
+                    Goal::DeliverAll { within: 11 },
+                    Goal::ScoreAtMost { score: 121 },
+                ],
+            ),
+            "capacity" => (
+                "A full car",
+                "Three passengers wait at floor 2, but the car holds 2. `OPEN 0 1` boards \
+                 two of them; come back for the third, who is then first in line.",
+                Scenario::new(4, 1)
+                    .turns(12)
+                    .capacity(2)
+                    .passenger(2, 3, 0)
+                    .passenger(2, 3, 0)
+                    .passenger(2, 3, 0),
+                vec![Goal::Valid, Goal::DeliverAll { within: 7 }],
+            ),
+            "two-elevators" => (
+                "Two elevators",
+                "Each turn takes one line per elevator, elevator 0 first. Send one elevator \
+                 down and the other up so that both passengers travel at once.",
+                Scenario::new(6, 2)
+                    .turns(14)
+                    .passenger(0, 5, 0)
+                    .passenger(5, 0, 0),
+                vec![
+                    Goal::Valid,
+                    Goal::DeliverAll { within: 10 },
+                    Goal::ScoreAtMost { score: 200 },
+                ],
+            ),
+            "let-out" => (
+                "Letting passengers out",
+                "Here every `OPEN` must say who gets off: after a `/` come the indices of \
+                 the passengers aboard to let out, as in `OPEN / 0`, or none, as in \
+                 `OPEN 0 1 /`. Board both passengers and drop each at their own floor.",
+                Scenario::new(5, 1)
+                    .turns(10)
+                    .params(|p| p.require_drops = true)
+                    .passenger(2, 4, 0)
+                    .passenger(2, 3, 0),
+                vec![
+                    Goal::Valid,
+                    Goal::Deliver { id: 1, within: 3 },
+                    Goal::DeliverAll { within: 5 },
+                ],
+            ),
+            _ => unreachable!("every name in NAMES has a lesson"),
+        };
+        Ok(Self {
+            name,
+            title,
+            text,
+            goals,
+            scenario,
+        })
+    }
+
+    pub fn all() -> Vec<Self> {
+        Self::NAMES
+            .into_iter()
+            .map(|name| Self::get(name).expect("built-in lessons exist"))
+            .collect()
+    }
+
+    pub fn instance(&self) -> Result<Instance> {
+        self.scenario.instance()
+    }
+
+    /// Plays `output` (v1 or v2) on the lesson and checks every goal.
+    pub fn check(&self, output: &str) -> Result<LessonReport> {
+        let instance = self.instance()?;
+        let passengers = instance.passengers.iter().flatten().flatten().count();
+        let report = instance.replay(output, false);
+        let goals: Vec<_> = self
+            .goals
+            .iter()
+            .map(|goal| (goal.to_string(), goal.met(&report, passengers)))
+            .collect();
+        Ok(LessonReport {
+            lesson: self.name,
+            passed: goals.iter().all(|(_, met)| *met),
+            score: report.score,
+            goals,
+            diagnostics: report.diagnostics,
+            history: report.history,
+        })
+    }
+}
+
+impl fmt::Display for LessonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (goal, met) in &self.goals {
+            writeln!(f, "[{}] {}", if *met { "x" } else { " " }, goal)?;
+        }
+        write!(
+            f,
+            "{}: {}",
+            self.lesson,
+            if self.passed { "passed" } else { "not yet" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lessons() -> Result<()> {
+        let solutions = [
+            "OPEN 0\nUP\nUP\nOPEN",
+            "DOWN\nDOWN\nDOWN\nOPEN 0\nUP\nUP\nUP\nUP\nUP\nUP\nOPEN",
+            "OPEN 0 1\nUP\nOPEN\nDOWN\nOPEN 0\nUP\nOPEN",
+            // One line per elevator per turn
+            concat!(
+                "DOWN\nUP\n",
+                "DOWN\nUP\n",
+                "DOWN\nOPEN 0\n",
+                "OPEN 0\nDOWN\n",
+                "UP\nDOWN\nUP\nDOWN\nUP\nDOWN\nUP\nDOWN\n",
+                "UP\nOPEN\n",
+                "OPEN",
+            ),
+            "OPEN 0 1 /\nUP\nOPEN / 0\nUP\nOPEN / 0",
+        ];
+        for (lesson, output) in Lesson::all().iter().zip(solutions) {
+            let report = lesson.check(output)?;
+            assert!(report.passed, "{}\n{:?}", report, report.diagnostics);
+        }
+
+        let report = Lesson::get("capacity")?.check("OPEN 0 1\nUP\nOPEN")?;
+        assert!(!report.passed);
+        assert_eq!(
+            report.goals[0],
+            ("Write only valid actions".to_string(), true)
+        );
+        assert!(!report.goals[1].1);
+        // A plain OPEN is invalid when drops must be listed
+        assert!(!Lesson::get("let-out")?.check("OPEN 0 1\nUP\nOPEN")?.goals[0].1);
+        assert!(Lesson::get("missing").is_err());
+        Ok(())
+    }
+}
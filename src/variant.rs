@@ -0,0 +1,93 @@
+use crate::SimParams;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A named bundle of rule options, so that each problem flavor is selected by one name
+/// instead of a combination of flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProblemVariant {
+    /// The original rules.
+    #[default]
+    Classic,
+    /// The controller decides who gets off where: `OPEN` must list the passengers to
+    /// let out.
+    DestinationDispatch,
+    /// Every turn an elevator spends moving adds `move_penalty` to the score.
+    Energy,
+}
+
+impl ProblemVariant {
+    pub const ALL: [ProblemVariant; 3] = [
+        ProblemVariant::Classic,
+        ProblemVariant::DestinationDispatch,
+        ProblemVariant::Energy,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProblemVariant::Classic => "classic",
+            ProblemVariant::DestinationDispatch => "destination-dispatch",
+            ProblemVariant::Energy => "energy",
+        }
+    }
+
+    /// `base` with this variant's rules. Options the variant does not govern are kept.
+    pub fn params(self, base: &SimParams) -> SimParams {
+        let mut params = SimParams {
+            variant: self,
+            require_drops: false,
+            move_penalty: 0,
+            ..base.clone()
+        };
+        match self {
+            ProblemVariant::Classic => {}
+            ProblemVariant::DestinationDispatch => params.require_drops = true,
+            ProblemVariant::Energy => params.move_penalty = 5,
+        }
+        params
+    }
+}
+
+impl FromStr for ProblemVariant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL.into_iter().find(|v| v.name() == s) {
+            Some(variant) => Ok(variant),
+            None => bail!("Unknown problem variant: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, SimulationState};
+
+    #[test]
+    fn test_variants() -> Result<()> {
+        for variant in ProblemVariant::ALL {
+            assert_eq!(variant.name().parse::<ProblemVariant>()?, variant);
+        }
+
+        let params = ProblemVariant::DestinationDispatch.params(&SimParams::default());
+        let mut sim = SimulationState::from_params(&params);
+        assert!(sim.apply_action(0, &Action::open(&[])).is_err());
+        sim.apply_action(
+            0,
+            &Action::Open {
+                picks: vec![],
+                drops: Some(vec![]),
+            },
+        )?;
+
+        let params = ProblemVariant::Energy.params(&SimParams::default());
+        let mut sim = SimulationState::from_params(&params);
+        sim.apply_action(0, &Action::Up)?;
+        sim.apply_action(1, &Action::Stay)?;
+        assert_eq!(sim.calculate_final_score(), 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::reference::fnv1a_hex;
+use crate::{Instance, Snapshot};
+use anyhow::{Result, bail};
+
+/// Hash of everything a snapshot shows, for comparing runs turn by turn.
+pub fn state_hash(snapshot: &Snapshot) -> String {
+    // Annotations are the agent's commentary, not state
+    if !snapshot.annotations.is_empty() {
+        let snapshot = Snapshot {
+            annotations: vec![],
+            ..snapshot.clone()
+        };
+        return state_hash(&snapshot);
+    }
+    fnv1a_hex(&serde_json::to_vec(snapshot).expect("snapshots always serialize"))
+}
+
+/// Rolling hash of a run: entry `k` hashes entry `k - 1` with the state after turn
+/// `k`, so a change to any turn changes every hash from there on.
+pub fn hash_chain(snapshots: &[Snapshot]) -> Vec<String> {
+    let mut previous = String::new();
+    snapshots
+        .iter()
+        .map(|snapshot| {
+            previous = fnv1a_hex(format!("{}{}", previous, state_hash(snapshot)).as_bytes());
+            previous.clone()
+        })
+        .collect()
+}
+
+/// Replays an output through [`Instance::replay`], the code path behind the wasm
+/// entry points, and checks it against what the judge recorded while the agent was
+/// playing: its final `score` and any `snapshots` saved with `--save-history`. Fails
+/// at the first recorded turn whose state differs. Returns the per-turn state hashes.
+pub fn verify_output(
+    instance: &Instance,
+    output_text: &str,
+    lenient: bool,
+    score: u64,
+    snapshots: &[Snapshot],
+) -> Result<Vec<String>> {
+    let report = instance.replay(output_text, lenient);
+    if let Some(d) = report.diagnostics.first() {
+        bail!("Line {} (turn {}): {}", d.line, d.turn, d.message);
+    }
+    let hashes: Vec<String> = report.history.iter().map(state_hash).collect();
+
+    for recorded in snapshots {
+        let Some(replayed) = hashes.get(recorded.turn) else {
+            bail!(
+                "The judge recorded turn {} but the replay ends after {} turns",
+                recorded.turn,
+                hashes.len()
+            );
+        };
+        let judge = state_hash(recorded);
+        if judge != *replayed {
+            bail!(
+                "State diverges at turn {}: judge {} vs replay {}",
+                recorded.turn,
+                judge,
+                replayed
+            );
+        }
+    }
+    if score != report.score {
+        bail!(
+            "Final scores differ: judge {} vs replay {}",
+            score,
+            report.score
+        );
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, SimParams, SimulationState};
+
+    #[test]
+    fn test_verify_against_judge_record() -> Result<()> {
+        let params = SimParams {
+            t: 4,
+            ..SimParams::default()
+        };
+        let instance = Instance::generate(0, &params)?;
+        let open = Action::Open {
+            picks: vec![],
+            drops: None,
+        };
+        let actions = [open, Action::Up, Action::Down];
+
+        // What the judge records: its own turn loop, not the replay
+        let mut state = SimulationState::from_instance(&instance)?;
+        let mut snapshots = vec![];
+        while state.turn < state.t {
+            snapshots.push(state.step(&actions)?.snapshot);
+        }
+        let score = state.calculate_final_score();
+
+        let output = "OPEN\nUP\nDOWN\n".repeat(4);
+        let hashes = verify_output(&instance, &output, false, score, &snapshots)?;
+        assert_eq!(hashes.len(), 4);
+        assert!(verify_output(&instance, &output, false, score, &[]).is_ok());
+
+        let error = verify_output(&instance, &output, false, score + 1, &[]).unwrap_err();
+        assert!(error.to_string().contains("Final scores differ"));
+        let mut tampered = snapshots[2].clone();
+        tampered.score += 1;
+        let error = verify_output(&instance, &output, false, score, &[tampered]).unwrap_err();
+        assert!(error.to_string().contains("turn 2"));
+        assert!(verify_output(&instance, "UP\n", false, score, &snapshots).is_err());
+        Ok(())
+    }
+}
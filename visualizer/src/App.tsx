@@ -1,7 +1,29 @@
 import { useState, useEffect, useCallback } from 'react';
-import init, { run_simulation_wasm, generate_passengers_wasm, Passenger } from '../pkg/elevator_sim';
+import init, { run_simulation_wasm, score_output_wasm, generate_passengers_wasm, Passenger } from '../pkg/elevator_sim';
+import BatchPanel from './BatchPanel';
 import { Play, Pause, SkipBack, SkipForward, RefreshCw, ChevronDown, ChevronUp, Settings2, Copy, Check, RotateCcw } from 'lucide-react';
 
+// Each passenger's turns waited so far, for coloring
+function withWaitTimes(snapshots: any[]) {
+  return snapshots.map(s => ({
+    ...s,
+    elevators: s.elevators.map((el: any) => ({
+      ...el,
+      passengers: el.passengers.map((p: any) => ({
+        ...p,
+        waitTime: s.turn - p.arrival_turn
+      }))
+    })),
+    floors: s.floors.map((f: any) => ({
+      ...f,
+      waiting: f.waiting.map((p: any) => ({
+        ...p,
+        waitTime: s.turn - p.arrival_turn
+      }))
+    }))
+  }));
+}
+
 export default function App() {
   const [seed, setSeed] = useState(0);
   const [output, setOutput] = useState('');
@@ -38,6 +60,39 @@ export default function App() {
     });
   }, []);
 
+  // Follow a judge run's --stream file, e.g. ?live=live.jsonl for
+  // `local_judge --stream visualizer/public/live.jsonl` under `npm run dev`
+  useEffect(() => {
+    const live = new URLSearchParams(window.location.search).get('live');
+    if (!live) return;
+    setShowConfig(false);
+    let shown = 0;
+    const poll = setInterval(async () => {
+      try {
+        const response = await fetch(live, { cache: 'no-store' });
+        if (!response.ok) return;
+        const snapshots: any[] = [];
+        for (const line of (await response.text()).split('\n')) {
+          // The last line may still be being written
+          try {
+            snapshots.push(JSON.parse(line));
+          } catch {
+            break;
+          }
+        }
+        if (snapshots.length === shown) return;
+        // Stay on the latest turn unless the viewer went back to an earlier one
+        const latest = shown - 1;
+        setTurn(t => (t >= latest ? snapshots.length - 1 : Math.min(t, snapshots.length - 1)));
+        shown = snapshots.length;
+        setHistory(withWaitTimes(snapshots));
+      } catch (e) {
+        console.error("Live stream failed", e);
+      }
+    }, 500);
+    return () => clearInterval(poll);
+  }, []);
+
   useEffect(() => {
     if (!isWasmLoaded) return;
     try {
@@ -67,25 +122,13 @@ export default function App() {
   const runSimulation = useCallback(async () => {
     if (!isWasmLoaded) return;
     try {
-      const snapshots = run_simulation_wasm(BigInt(seed), output) as any[];
-      const snapshotsWithWait = snapshots.map(s => ({
-        ...s,
-        elevators: s.elevators.map((el: any) => ({
-          ...el,
-          passengers: el.passengers.map((p: any) => ({
-            ...p,
-            waitTime: s.turn - p.arrival_turn
-          }))
-        })),
-        floors: s.floors.map((f: any) => ({
-          ...f,
-          waiting: f.waiting.map((p: any) => ({
-            ...p,
-            waitTime: s.turn - p.arrival_turn
-          }))
-        }))
-      }));
-      setHistory(snapshotsWithWait);
+      // Keep going past invalid lines so that the rest of the run can still be shown
+      const snapshots = run_simulation_wasm(BigInt(seed), output, undefined, undefined, true) as any[];
+      const { diagnostics } = score_output_wasm(BigInt(seed), output) as { diagnostics: any[] };
+      if (diagnostics.length > 0) {
+        console.warn(`${diagnostics.length} invalid line(s) were replaced with STAY`, diagnostics);
+      }
+      setHistory(withWaitTimes(snapshots));
       setTurn(0);
       setShowConfig(false);
     } catch (e: any) {
@@ -106,6 +149,13 @@ export default function App() {
     return () => clearInterval(interval);
   }, [isPlaying, turn, history]);
 
+  const openRun = (runSeed: number, snapshots: any[]) => {
+    setSeed(runSeed);
+    setHistory(withWaitTimes(snapshots));
+    setTurn(0);
+    setShowConfig(false);
+  };
+
   const currentState = history[turn];
 
   const getPassengerStyle = (waitTime: number) => {
@@ -188,6 +238,7 @@ export default function App() {
                 className="w-full flex-1 min-h-[120px] bg-slate-950 border border-slate-700 rounded-xl px-4 py-3 text-xs font-mono focus:ring-2 focus:ring-blue-500 outline-none resize-none transition-all"
               />
             </div>
+            {isWasmLoaded && <BatchPanel onOpen={openRun} />}
           </div>
         )}
 
@@ -198,7 +249,7 @@ export default function App() {
               <div className="flex flex-col-reverse gap-0">
                 {[...Array(10)].map((_, floor) => (
                   <div key={floor} className="h-16 flex items-center justify-end pr-4 text-[10px] font-black text-slate-600 border-r border-slate-800 w-10">
-                    {floor}F
+                    {currentState?.floors[floor]?.label ?? `${floor}F`}
                   </div>
                 ))}
               </div>
@@ -213,7 +264,7 @@ export default function App() {
                           className="absolute left-1 w-18 h-14 bg-gradient-to-br from-slate-700 to-slate-800 rounded border border-slate-500 shadow-xl flex flex-col items-center justify-start p-1 transition-all duration-300 ease-in-out z-10"
                           style={{ bottom: `${bottomOffset + 4}px` }}
                         >
-                          <div className="text-[7px] font-black text-slate-400 uppercase mb-0.5 leading-none">EL-{elIdx}</div>
+                          <div className="text-[7px] font-black text-slate-400 uppercase mb-0.5 leading-none">{el?.label ?? `EL-${elIdx}`}</div>
                           <div className="grid grid-cols-5 gap-0.5 w-full">
                             {el?.passengers.map((p: any, idx: number) => (
                               <div key={idx} style={getPassengerStyle(p.waitTime)} className="w-3 h-4 rounded-[1px] border-[0.5px] flex items-center justify-center transition-colors duration-500">
@@ -269,6 +320,12 @@ export default function App() {
                 <span>Turn 99</span>
               </div>
               <input type="range" min="0" max={Math.max(0, history.length - 1)} value={turn} onChange={e => setTurn(parseInt(e.target.value))} className="w-full h-1.5 bg-slate-800 rounded-lg appearance-none cursor-pointer accent-blue-500" />
+              {/* The agent's own `#` lines for this turn */}
+              {currentState?.annotations?.length > 0 && (
+                <div className="text-[10px] font-mono text-amber-300/80 truncate" title={currentState.annotations.join('\n')}>
+                  # {currentState.annotations.join(' · ')}
+                </div>
+              )}
             </div>
             <div className="flex gap-1 shrink-0">
               <button onClick={() => setTurn(Math.max(0, turn - 1))} className="p-2.5 hover:bg-slate-800 rounded-lg transition-colors text-slate-400 hover:text-white" title="Previous Turn">
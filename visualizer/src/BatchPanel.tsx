@@ -0,0 +1,117 @@
+import { useEffect, useRef, useState } from 'react';
+import { History } from '../pkg/elevator_sim';
+import { Layers } from 'lucide-react';
+import type { BatchReply, BatchRequest } from './batchWorker';
+
+interface BatchRow {
+  seed: number;
+  name: string;
+  score: bigint;
+  error: string;
+}
+
+// Scores a set of output files in batchWorker.ts and opens any of the runs
+export default function BatchPanel({ onOpen }: { onOpen: (seed: number, snapshots: any[]) => void }) {
+  const worker = useRef<Worker | null>(null);
+  const [rows, setRows] = useState<BatchRow[]>([]);
+  const [reply, setReply] = useState<BatchReply | null>(null);
+  const [status, setStatus] = useState('');
+
+  useEffect(() => {
+    const w = new Worker(new URL('./batchWorker.ts', import.meta.url), { type: 'module' });
+    worker.current = w;
+    return () => w.terminate();
+  }, []);
+
+  const scoreFiles = async (files: FileList | null) => {
+    const w = worker.current;
+    if (!w || !files) return;
+    // Outputs are named after their seed, e.g. 0003.txt
+    const picked = [...files]
+      .map(file => ({ file, seed: parseInt(file.name, 10) }))
+      .filter(f => !isNaN(f.seed));
+    if (picked.length === 0) {
+      setStatus('Name the output files after their seeds, e.g. 0003.txt');
+      return;
+    }
+    const outputs = await Promise.all(picked.map(f => f.file.text()));
+    setStatus(`Scoring ${picked.length} outputs...`);
+    w.onmessage = (event: MessageEvent<BatchReply | { error: string }>) => {
+      const data = event.data;
+      if ('error' in data) {
+        setStatus('Batch failed: ' + data.error);
+        return;
+      }
+      setReply(data);
+      setRows(picked.map((f, i) => ({
+        seed: f.seed,
+        name: f.file.name,
+        score: data.scores[i],
+        error: data.errors[i],
+      })));
+      setStatus(picked.length < files.length ? `Skipped ${files.length - picked.length} files not named after a seed` : '');
+    };
+    const request: BatchRequest = { seeds: picked.map(f => f.seed), outputs, histories: true };
+    w.postMessage(request);
+  };
+
+  const open = (i: number) => {
+    if (!reply) return;
+    try {
+      const history = History.decompress(reply.histories.subarray(reply.offsets[i], reply.offsets[i + 1]));
+      const snapshots = history.snapshots() as any[];
+      history.free();
+      onOpen(rows[i].seed, snapshots);
+    } catch (e) {
+      alert('Could not open the run: ' + e);
+    }
+  };
+
+  const total = rows.reduce((sum, row) => sum + row.score, 0n);
+
+  return (
+    <div className="md:col-span-2 space-y-3">
+      <h3 className="text-xs font-bold text-slate-400 uppercase tracking-widest flex items-center gap-2">
+        <Layers className="w-3.5 h-3.5" /> Batch Evaluation
+      </h3>
+      <input
+        type="file"
+        multiple
+        onChange={e => scoreFiles(e.target.files)}
+        className="block w-full text-xs text-slate-400 file:mr-4 file:py-2 file:px-4 file:rounded-lg file:border-0 file:bg-slate-700 file:text-slate-100 file:font-bold hover:file:bg-slate-600"
+      />
+      {status && <div className="text-xs text-amber-300/80">{status}</div>}
+      {rows.length > 0 && (
+        <div className="max-h-64 overflow-y-auto custom-scrollbar border border-slate-800 rounded-xl">
+          <table className="w-full text-xs font-mono">
+            <thead className="text-[10px] text-slate-500 uppercase">
+              <tr>
+                <th className="text-left px-3 py-2">Seed</th>
+                <th className="text-left px-3 py-2">File</th>
+                <th className="text-right px-3 py-2">Score</th>
+                <th className="text-left px-3 py-2">First problem</th>
+              </tr>
+            </thead>
+            <tbody>
+              {rows.map((row, i) => (
+                <tr key={i} onClick={() => open(i)} className="cursor-pointer hover:bg-slate-800/60 border-t border-slate-800/50" title="Show this run">
+                  <td className="px-3 py-1.5">{row.seed}</td>
+                  <td className="px-3 py-1.5 text-slate-400">{row.name}</td>
+                  <td className="px-3 py-1.5 text-right text-green-400">{row.score.toLocaleString()}</td>
+                  <td className="px-3 py-1.5 text-amber-300/80 truncate max-w-xs">{row.error}</td>
+                </tr>
+              ))}
+            </tbody>
+            <tfoot>
+              <tr className="border-t border-slate-700 font-bold">
+                <td className="px-3 py-2" colSpan={2}>Total</td>
+                <td className="px-3 py-2 text-right text-green-400">{total.toLocaleString()}</td>
+                <td />
+              </tr>
+            </tfoot>
+          </table>
+        </div>
+      )}
+    </div>
+  );
+}
@@ -0,0 +1,49 @@
+// Scores many outputs off the main thread. Start it with
+//   new Worker(new URL('./batchWorker.ts', import.meta.url), { type: 'module' })
+// and post a BatchRequest; the reply's buffers are transferred, not copied.
+import init, { score_batch_wasm } from '../pkg/elevator_sim';
+
+export interface BatchRequest {
+  seeds: number[];
+  outputs: string[];
+  lenient?: boolean;
+  variant?: string;
+  // Also return each run's packed history, for History.decompress
+  histories?: boolean;
+}
+
+export interface BatchReply {
+  scores: BigUint64Array;
+  errors: string[];
+  // Run i's history is histories.subarray(offsets[i], offsets[i + 1])
+  offsets: Uint32Array;
+  histories: Uint8Array;
+}
+
+const ready = init();
+
+self.onmessage = async (event: MessageEvent<BatchRequest>) => {
+  await ready;
+  const { seeds, outputs, lenient, variant, histories } = event.data;
+  try {
+    const batch = score_batch_wasm(
+      BigUint64Array.from(seeds, (seed) => BigInt(seed)),
+      outputs,
+      lenient,
+      variant,
+      histories,
+    );
+    const reply: BatchReply = {
+      scores: batch.scores,
+      errors: batch.errors,
+      offsets: batch.offsets,
+      histories: batch.take_histories(),
+    };
+    batch.free();
+    self.postMessage(reply, {
+      transfer: [reply.scores.buffer, reply.offsets.buffer, reply.histories.buffer],
+    });
+  } catch (e) {
+    self.postMessage({ error: String(e) });
+  }
+};
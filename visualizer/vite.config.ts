@@ -13,4 +13,9 @@ export default defineConfig({
     topLevelAwait(),
     tailwindcss()
   ],
+  // batchWorker.ts loads the wasm package too
+  worker: {
+    format: 'es',
+    plugins: () => [wasm(), topLevelAwait()],
+  },
 })